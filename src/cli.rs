@@ -1,22 +1,211 @@
-use crate::engine::kv::SlackbaseEngine;
-use crate::serialization::plain::PlainSerializer;
-use crate::serialization::json::JsonSerializer;
-use crate::serialization::Serializer;
+use crate::engine::kv::{ SlackbaseEngine, key_to_bytes };
+use crate::serialization::{ self, Serializer, codec };
 use crate::engine::batch::BatchOp;
-use crate::script::ScriptManager;
-use crate::logging::print_lua_value;
+use crate::engine::agg::AggQuery;
+use crate::engine::vector::{ self, Metric, VectorIndexDef, VectorIndexKind };
+use crate::engine::geo::{ GeoIndexDef, GeoShape, GeoUnit };
+use crate::engine::trigger::{ TriggerDef, TriggerEvent };
+use crate::engine::content::ContentType;
+use crate::engine::compression::{ self, Algorithm, Compression };
+use crate::storage::crypto::{ CipherKind, EncryptionKey, KeySource };
+use crate::script::{ NextLine, ScriptManager };
+use crate::logging::{ print_lua_value, print_script_error };
 
-use std::io::{ self, Write };
-use std::sync::{ Arc, Mutex };
+use crate::engine::schedule::{ self, ScheduleSpec };
+use std::fs;
+use std::io::{ self, IsTerminal, Write };
+use std::process::ExitCode;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc::{ self, Receiver, RecvTimeoutError, Sender };
+use std::thread;
+use std::time::Duration;
 use crate::types::Error;
 
 use prettytable::{ Table, Row, Cell };
+use base64::{ engine::general_purpose, Engine };
+use clap::Parser;
 
-pub fn run() {
-    let serializer: Box<dyn Serializer>;
+// Maximum number of hits printed by `search`
+const SEARCH_LIMIT: usize = 20;
 
+// Database the CLI opens unless given `--db`
+const DB_PATH: &str = "slackbase.db";
+
+// Environment variables the key of an encrypted database is read from
+const KEY_FILE_ENV: &str = "SLACKBASE_KEY_FILE";
+const PASSPHRASE_ENV: &str = "SLACKBASE_PASSPHRASE";
+// Cipher for a database encrypted from the environment; AES-256-GCM if unset
+const CIPHER_ENV: &str = "SLACKBASE_CIPHER";
+
+// How often the CLI checks for due schedules while waiting for input
+const SCHEDULE_TICK: Duration = Duration::from_secs(1);
+
+// Exit codes: a command failed; bad arguments or the database can't be opened
+const EXIT_FAILED: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+
+thread_local! {
+    // Set once any command has failed, for the exit code
+    static FAILED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Prints a failed command's error to stderr, keeping stdout for results,
+/// and marks the run as failed.
+macro_rules! fail {
+    ($($arg:tt)*) => {{
+        FAILED.with(|failed| failed.set(true));
+        eprintln!($($arg)*);
+    }};
+}
+
+/// `print_script_error` for a failed command.
+fn script_failed(prefix: &str, err: &Error) {
+    FAILED.with(|failed| failed.set(true));
+    print_script_error(prefix, err);
+}
+
+/// What the CLI does after a command.
+enum Flow {
+    Continue,
+    Exit,
+}
+
+/// Reads stdin on a helper thread, one line per request, so the CLI loop can
+/// wake up to run due schedules while it waits. Between requests the thread
+/// is idle, so commands like `script begin` can still read stdin directly.
+struct LineReader {
+    requests: Sender<()>,
+    lines: Receiver<Option<String>>,
+    pending: bool,
+}
+
+impl LineReader {
+    fn spawn() -> Self {
+        let (requests, request_rx) = mpsc::channel::<()>();
+        let (line_tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            while request_rx.recv().is_ok() {
+                let mut line = String::new();
+                let line = match io::stdin().read_line(&mut line) {
+                    Ok(0) | Err(_) => None,
+                    Ok(_) => Some(line),
+                };
+                if line_tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { requests, lines, pending: false }
+    }
+
+    /// The next line, waiting as long as it takes; `None` at end of input.
+    fn wait_line(&mut self) -> Option<String> {
+        loop {
+            match self.next_line(SCHEDULE_TICK) {
+                Ok(line) => return line,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// The next line, `None` at end of input, or a timeout error if no line
+    /// arrived within `timeout` (the request stays open for the next call).
+    fn next_line(&mut self, timeout: Duration) -> Result<Option<String>, RecvTimeoutError> {
+        if !self.pending {
+            self.requests.send(()).map_err(|_| RecvTimeoutError::Disconnected)?;
+            self.pending = true;
+        }
+        let line = self.lines.recv_timeout(timeout)?;
+        self.pending = false;
+        Ok(line)
+    }
+}
+
+/// Runs the schedules that are due and reports each run. Returns whether any ran.
+/// `lead` is printed before each report, to move off a prompt that is showing.
+fn run_schedules(engine: &mut SlackbaseEngine, lead: &str) -> bool {
+    match engine.run_due_schedules() {
+        Ok(ran) => {
+            for (name, record) in &ran {
+                println!(
+                    "{}[schedule '{}'] finished in {} ms: {}",
+                    lead,
+                    name,
+                    record.duration_ms,
+                    record.outcome
+                );
+            }
+            !ran.is_empty()
+        }
+        Err(e) => {
+            eprintln!("{}[schedule] ERR: {:?}", lead, e);
+            true
+        }
+    }
+}
+
+/// Decodes a CLI byte value given as `hex` or `base64`.
+fn decode_binary(encoding: &str, data: &str) -> Option<Vec<u8>> {
+    match encoding {
+        "hex" => hex::decode(data).ok(),
+        "base64" => general_purpose::STANDARD.decode(data).ok(),
+        _ => None,
+    }
+}
+
+/// Key bytes from a CLI argument: `hex:<digits>` for a binary key, otherwise the text.
+fn parse_key(arg: &str) -> Option<Vec<u8>> {
+    match arg.strip_prefix("hex:") {
+        Some(digits) => hex::decode(digits).ok(),
+        None => Some(arg.as_bytes().to_vec()),
+    }
+}
+
+/// How a key is shown: binary keys as `hex:<digits>`, the form `parse_key` reads.
+fn display_key(key: &str) -> String {
+    let bytes = key_to_bytes(key);
+    if bytes == key.as_bytes() { key.to_string() } else { format!("hex:{}", hex::encode(bytes)) }
+}
+
+/// The encryption key from the environment, if one is set.
+fn key_from_env() -> Result<Option<EncryptionKey>, String> {
+    let source = match (std::env::var(KEY_FILE_ENV), std::env::var(PASSPHRASE_ENV)) {
+        (Ok(_), Ok(_)) => return Err(format!("set only one of {} and {}", KEY_FILE_ENV, PASSPHRASE_ENV)),
+        (Ok(path), _) => KeySource::KeyFile(path),
+        (_, Ok(passphrase)) => KeySource::Passphrase(passphrase),
+        _ => return Ok(None),
+    };
+    let cipher = match std::env::var(CIPHER_ENV) {
+        Ok(name) => CipherKind::parse(&name).ok_or_else(|| format!("unknown cipher '{}'", name))?,
+        Err(_) => CipherKind::Aes256Gcm,
+    };
+    Ok(Some(EncryptionKey { source, cipher }))
+}
+
+/// Explains errors opening a database that a `{:?}` wouldn't.
+fn open_error(e: &Error) -> String {
+    match e {
+        Error::WrongKey => "wrong encryption key".to_string(),
+        Error::KeyRequired =>
+            format!("the database is encrypted; set {} or {} to its key", KEY_FILE_ENV, PASSPHRASE_ENV),
+        e => format!("{:?}", e),
+    }
+}
+
+/// Parses `[keys..] [-- args..]`.
+fn split_keys_args(tail: &[&str]) -> (Vec<String>, Vec<String>) {
+    let mut split = tail.split(|&s| s == "--");
+    let keys = split.next().unwrap_or(&[]).iter().map(|s| s.to_string()).collect();
+    let args = split.next().unwrap_or(&[]).iter().map(|s| s.to_string()).collect();
+    (keys, args)
+}
+
+/// Asks which serializer a new database should use.
+fn choose_serializer() -> Box<dyn Serializer> {
     loop {
-        println!("Choose serialization format [plain/json]:");
+        println!("Choose serialization format [plain/json/msgpack/cbor]:");
         print!("> ");
         io::stdout().flush().unwrap();
 
@@ -26,436 +215,1671 @@ pub fn run() {
             continue;
         }
 
-        match ser_input.trim().to_lowercase().as_str() {
-            "plain" => {
-                serializer = Box::new(PlainSerializer);
-                break;
+        let choice = ser_input.trim().to_lowercase();
+        match serialization::from_id(&choice) {
+            Some(serializer) => {
+                return serializer;
+            }
+            None => {
+                println!("Invalid input '{}'. Please enter 'plain', 'json', 'msgpack' or 'cbor'.", choice);
+            }
+        }
+    }
+}
+
+/// Command-line arguments. Without a command or `-f`, the CLI reads commands
+/// from stdin: an interactive shell on a terminal, one per line from a pipe.
+#[derive(Parser)]
+#[command(name = "slackbase", version, about = "An embedded key-value store")]
+struct Args {
+    /// Database file to open
+    #[arg(long, value_name = "PATH", default_value = DB_PATH)]
+    db: String,
+
+    /// Serialization format of a new database (plain, json, msgpack or cbor)
+    #[arg(long)]
+    format: Option<String>,
+
+    /// Runs the commands in a file, one per line
+    #[arg(short = 'f', long = "file", value_name = "FILE", conflicts_with = "command")]
+    file: Option<String>,
+
+    /// A command to run, e.g. `get foo`
+    #[arg(trailing_var_arg = true)]
+    command: Vec<String>,
+}
+
+/// Opens the database at `args.db`. The serializer of a new one comes from
+/// `--format`, or is asked for on a terminal.
+fn open_db(args: &Args, interactive: bool) -> Result<Rc<RefCell<SlackbaseEngine>>, String> {
+    let requested = match &args.format {
+        Some(format) => {
+            let serializer = serialization::from_id(format);
+            Some(serializer.ok_or_else(|| format!("unknown format '{}' (plain|json|msgpack|cbor)", format))?)
+        }
+        None => None,
+    };
+    // A database records its serializer when it is created; only ask for new ones
+    let serializer = match SlackbaseEngine::stored_serializer(&args.db) {
+        Ok(Some(stored)) => {
+            if let Some(requested) = &requested && requested.id() != stored.id() {
+                return Err(
+                    format!(
+                        "{} uses {} serialization, not {}; convert it with migrate-format",
+                        args.db,
+                        stored.id(),
+                        requested.id()
+                    )
+                );
+            }
+            if interactive {
+                println!("Using {} serialization (from {}.manifest)", stored.id(), args.db);
             }
-            "json" => {
-                serializer = Box::new(JsonSerializer);
-                break;
+            stored
+        }
+        Ok(None) =>
+            match requested {
+                Some(serializer) => serializer,
+                None if interactive => choose_serializer(),
+                None => {
+                    return Err(format!("{} is a new database; choose its serializer with --format", args.db));
+                }
             }
-            other => {
-                println!("Invalid input '{}'. Please enter 'plain' or 'json'.", other);
+        Err(e) => {
+            return Err(format!("{:?}", e));
+        }
+    };
+
+    let opened = match key_from_env()? {
+        Some(key) => SlackbaseEngine::open_encrypted(&args.db, serializer, &key),
+        None => SlackbaseEngine::open(&args.db, serializer),
+    };
+    opened.map(|engine| Rc::new(RefCell::new(engine))).map_err(|e| open_error(&e))
+}
+
+/// Reads a line from stdin; `None` at end of input.
+fn stdin_line() -> io::Result<Option<String>> {
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line)? {
+        0 => Ok(None),
+        _ => Ok(Some(line)),
+    }
+}
+
+/// Runs the commands of a file or pipe, one per line, skipping blank lines
+/// and `#` comments, until the input ends or a command is `exit`. Commands
+/// that read lines of their own (`script begin`) read the ones that follow.
+fn run_lines(db: &Rc<RefCell<SlackbaseEngine>>, next_line: &mut NextLine) -> ExitCode {
+    loop {
+        let line = match next_line() {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Failed to read commands: {}", e);
+                return ExitCode::from(EXIT_FAILED);
             }
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        run_schedules(&mut db.borrow_mut(), "");
+        if let Flow::Exit = execute(db, &args, next_line) {
+            break;
+        }
+    }
+    exit_code()
+}
+
+/// The exit code after the commands run: `EXIT_FAILED` if any of them failed.
+fn exit_code() -> ExitCode {
+    if FAILED.with(|failed| failed.get()) { ExitCode::from(EXIT_FAILED) } else { ExitCode::SUCCESS }
+}
+
+pub fn run() -> ExitCode {
+    let args = Args::parse();
+    let interactive = args.command.is_empty() && args.file.is_none() && io::stdin().is_terminal();
+
+    let db = match open_db(&args, interactive) {
+        Ok(db) => db,
+        Err(msg) => {
+            eprintln!("Failed to open DB: {}", msg);
+            return ExitCode::from(EXIT_USAGE);
         }
+    };
+
+    // One-shot command: the shell has already split its arguments
+    if !args.command.is_empty() {
+        let command: Vec<&str> = args.command.iter().map(String::as_str).collect();
+        run_schedules(&mut db.borrow_mut(), "");
+        execute(&db, &command, &mut stdin_line);
+        return exit_code();
     }
 
-    // Then continue with opening DB and CLI loop as you had:
-    let db = Arc::new(
-        Mutex::new(SlackbaseEngine::open("slackbase.db", serializer).expect("Failed to open DB"))
-    );
+    if let Some(path) = &args.file {
+        let commands = match fs::read_to_string(path) {
+            Ok(commands) => commands,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path, e);
+                return ExitCode::from(EXIT_USAGE);
+            }
+        };
+        let mut lines = commands.lines();
+        return run_lines(&db, &mut || Ok(lines.next().map(str::to_string)));
+    }
+
+    // Piped commands are read a line at a time, so `script begin` can read
+    // the lines that follow it
+    if !interactive {
+        return run_lines(&db, &mut stdin_line);
+    }
 
     // CLI loop
+    let mut reader = LineReader::spawn();
     loop {
         print!("slackbase> ");
         io::stdout().flush().unwrap();
 
-        let mut input = String::new();
-        if io::stdin().read_line(&mut input).is_err() {
+        let input = loop {
+            match reader.next_line(SCHEDULE_TICK) {
+                Ok(line) => break line,
+                Err(RecvTimeoutError::Timeout) => {
+                    if run_schedules(&mut db.borrow_mut(), "\n") {
+                        print!("slackbase> ");
+                        io::stdout().flush().unwrap();
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break None,
+            }
+        };
+        let Some(input) = input else {
             break;
-        }
+        };
 
         let args: Vec<&str> = input.trim().split_whitespace().collect();
-        match args.as_slice() {
-            ["put", key, value] => {
-                let mut engine = db.lock().unwrap();
-                engine.put(key, value).unwrap();
-                println!("OK");
+        // Schedules that fell due while the line was typed run first
+        run_schedules(&mut db.borrow_mut(), "");
+        if let Flow::Exit = execute(&db, &args, &mut || Ok(reader.wait_line())) {
+            break;
+        }
+    }
+    exit_code()
+}
+
+/// Runs one command, printing its result. Failures are printed with `fail!`.
+/// `next_line` is the input the command came from, for commands that read
+/// more lines (`script begin`).
+fn execute(db: &Rc<RefCell<SlackbaseEngine>>, args: &[&str], next_line: &mut NextLine) -> Flow {
+    match args {
+        ["put", key, value] => {
+            let mut engine = db.borrow_mut();
+            match engine.put(key, value) {
+                Ok(_) => println!("OK"),
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            ["putex", key, value, ttl] => {
-                let ttl_secs: u64 = match ttl.parse() {
-                    Ok(n) => n,
-                    Err(_) => {
-                        println!("Invalid TTL (must be a number of seconds)");
-                        continue;
+        ["putex", key, value, ttl] => {
+            let ttl_secs: u64 = match ttl.parse() {
+                Ok(n) => n,
+                Err(_) => {
+                    fail!("Invalid TTL (must be a number of seconds)");
+                    return Flow::Continue;
+                }
+            };
+            let mut engine = db.borrow_mut();
+            match engine.putex(key, value, ttl_secs) {
+                Ok(_) => println!("OK (expires in {} seconds)", ttl_secs),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["get", key] => {
+            let mut engine = db.borrow_mut();
+            match engine.get(key) {
+                Some(val) => println!("{}", val),
+                None =>
+                    match engine.get_bytes(key.as_bytes()) {
+                        Some(bytes) => println!("(binary, {} bytes; use getbin)", bytes.len()),
+                        None => println!("(nil)"),
                     }
-                };
-                let mut engine = db.lock().unwrap();
-                engine.putex(key, value, ttl_secs).unwrap();
-                println!("OK (expires in {} seconds)", ttl_secs);
             }
+        }
+
+        ["putbin", key, encoding, data] => {
+            let Some(key) = parse_key(key) else {
+                fail!("Invalid hex key");
+                return Flow::Continue;
+            };
+            let Some(value) = decode_binary(encoding, data) else {
+                fail!("Invalid {} value (encoding is hex or base64)", encoding);
+                return Flow::Continue;
+            };
+            let mut engine = db.borrow_mut();
+            match engine.put_bytes(&key, &value) {
+                Ok(_) => println!("OK ({} bytes)", value.len()),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
 
-            ["get", key] => {
-                let mut engine = db.lock().unwrap();
-                match engine.get(key) {
-                    Some(val) => println!("{}", val),
-                    None => println!("(nil)"),
+        ["getbin", key, encoding @ ..] if encoding.len() <= 1 => {
+            let encoding = encoding.first().copied().unwrap_or("hex");
+            let Some(key) = parse_key(key) else {
+                fail!("Invalid hex key");
+                return Flow::Continue;
+            };
+            let mut engine = db.borrow_mut();
+            match engine.get_bytes(&key) {
+                Some(bytes) =>
+                    match encoding {
+                        "hex" => println!("{}", hex::encode(bytes)),
+                        "base64" => println!("{}", general_purpose::STANDARD.encode(bytes)),
+                        _ => fail!("Unknown encoding '{}' (hex|base64)", encoding),
+                    }
+                None => println!("(nil)"),
+            }
+        }
+
+        ["migrate-format", format] => {
+            let Some(target) = serialization::from_id(format) else {
+                fail!("Unknown format '{}' (plain|json|msgpack|cbor)", format);
+                return Flow::Continue;
+            };
+            let mut engine = db.borrow_mut();
+            match engine.migrate_format(target) {
+                Ok(n) => println!("Migrated {} keys to {}", n, format),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["compression"] => {
+            let engine = db.borrow();
+            match engine.compression() {
+                Some(c) => println!("{} (threshold {} bytes)", c.algorithm.as_str(), c.threshold),
+                None => println!("off"),
+            }
+        }
+
+        ["compression", "off"] => {
+            let mut engine = db.borrow_mut();
+            match engine.set_compression(None) {
+                Ok(()) => println!("OK"),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["compression", algorithm, threshold @ ..] if threshold.len() <= 1 => {
+            let Some(algorithm) = Algorithm::parse(algorithm) else {
+                fail!("Unknown compression '{}' (lz4|zstd|off)", algorithm);
+                return Flow::Continue;
+            };
+            let threshold = match threshold.first() {
+                Some(t) =>
+                    match t.parse::<usize>() {
+                        Ok(t) => t,
+                        Err(_) => {
+                            fail!("Invalid threshold '{}'", t);
+                            return Flow::Continue;
+                        }
+                    }
+                None => compression::DEFAULT_THRESHOLD,
+            };
+            let mut engine = db.borrow_mut();
+            match engine.set_compression(Some(Compression { algorithm, threshold })) {
+                Ok(()) => println!("OK"),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["codecs"] => {
+            let engine = db.borrow();
+            let pipeline = engine.codecs();
+            println!("Available: {}", codec::ids().join(", "));
+            println!("Pipeline: {}", if pipeline.is_empty() { "none".to_string() } else { pipeline.join(" > ") });
+        }
+
+        ["codecs", "none"] => {
+            let mut engine = db.borrow_mut();
+            match engine.set_codecs(&[]) {
+                Ok(()) => println!("OK"),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["codecs", "set", ids @ ..] if !ids.is_empty() => {
+            let mut engine = db.borrow_mut();
+            match engine.set_codecs(ids) {
+                Ok(()) => println!("OK"),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["rotate-key", "none"] => {
+            let mut engine = db.borrow_mut();
+            match engine.rotate_key(None) {
+                Ok(()) => println!("OK (decrypted)"),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["rotate-key", kind @ ("keyfile" | "passphrase"), secret, cipher @ ..] if cipher.len() <= 1 => {
+            let cipher = match cipher.first() {
+                Some(name) =>
+                    match CipherKind::parse(name) {
+                        Some(cipher) => cipher,
+                        None => {
+                            fail!("Unknown cipher '{}' (aes-256-gcm|chacha20-poly1305)", name);
+                            return Flow::Continue;
+                        }
+                    }
+                None => CipherKind::Aes256Gcm,
+            };
+            let source = match *kind {
+                "keyfile" => KeySource::KeyFile(secret.to_string()),
+                _ => KeySource::Passphrase(secret.to_string()),
+            };
+            let mut engine = db.borrow_mut();
+            match engine.rotate_key(Some(&EncryptionKey { source, cipher })) {
+                Ok(()) => println!("OK (encrypted with {})", cipher.as_str()),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["putenc", key, hex_value] => {
+            let data = match hex::decode(hex_value) {
+                Ok(data) => data,
+                Err(_) => {
+                    fail!("Invalid hex value");
+                    return Flow::Continue;
                 }
+            };
+            let mut engine = db.borrow_mut();
+            match engine.put_encoded(key, &data) {
+                Ok(_) => println!("OK"),
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            ["del", key] => {
-                let mut engine = db.lock().unwrap();
-                engine.delete(key).unwrap();
-                println!("OK");
+        ["getenc", key] => {
+            let mut engine = db.borrow_mut();
+            match engine.get_encoded(key) {
+                Ok(Some(data)) => println!("{}", hex::encode(data)),
+                Ok(None) => println!("(nil)"),
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            ["compact"] => {
-                let mut engine = db.lock().unwrap();
-                engine.compact().unwrap();
-                println!("Compaction complete. Old records removed.");
+        ["del", key] => {
+            let mut engine = db.borrow_mut();
+            match engine.delete(key) {
+                Ok(_) => println!("OK"),
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            ["snapshot", filename] => {
-                let mut engine = db.lock().unwrap();
-                engine.snapshot(filename).unwrap();
-                println!("Snapshot saved to {}", filename);
+        ["compact"] => {
+            let mut engine = db.borrow_mut();
+            match engine.compact() {
+                Ok(()) => println!("Compaction complete. Old records removed."),
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            ["restore", filename] => {
-                let mut engine = db.lock().unwrap();
-                engine.restore(filename).unwrap();
-                println!("Database restored from {}", filename);
+        ["snapshot", filename] => {
+            let mut engine = db.borrow_mut();
+            match engine.snapshot(filename) {
+                Ok(()) => println!("Snapshot saved to {}", filename),
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
+
+        ["restore", filename] => {
+            let mut engine = db.borrow_mut();
+            match engine.restore(filename) {
+                Ok(()) => println!("Database restored from {}", filename),
+                Err(e) => fail!("ERR: {}", open_error(&e)),
+            }
+        }
+
+        ["find", field, value] => {
+            let engine = db.borrow();
+            let keys = engine.sec_index.find(field, value);
+            if keys.is_empty() {
+                println!("No keys found with {} = {}", field, value);
+            } else {
+                println!("Keys with {} = {}:", field, value);
+                for k in keys {
+                    println!("- {}", k);
+                }
+            }
+        }
 
-            ["find", field, value] => {
-                let engine = db.lock().unwrap();
-                let keys = engine.sec_index.find(field, value);
-                if keys.is_empty() {
-                    println!("No keys found with {} = {}", field, value);
-                } else {
-                    println!("Keys with {} = {}:", field, value);
-                    for k in keys {
-                        println!("- {}", k);
+        ["index", "unique", field, prefix @ ..] if prefix.len() <= 1 => {
+            let mut engine = db.borrow_mut();
+            match engine.create_unique_index(field, prefix.first().copied()) {
+                Ok(_) => println!("OK (unique index on '{}')", field),
+                Err(Error::UniqueViolation { conflicts, .. }) => {
+                    fail!("Cannot create unique index on '{}': existing duplicates", field);
+                    let mut table = Table::new();
+                    table.add_row(Row::new(vec![Cell::new("Value"), Cell::new("Keys")]));
+                    for (value, keys) in conflicts {
+                        table.add_row(
+                            Row::new(vec![Cell::new(&value), Cell::new(&keys.join(", "))])
+                        );
                     }
+                    table.printstd();
                 }
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            ["batch", tail @ ..] => {
-                let mut ops = Vec::new();
-                let mut iter = tail.iter();
-                while let Some(&cmd) = iter.next() {
-                    match cmd {
-                        "put" => {
-                            let k = iter.next().expect("No key for put");
-                            let v = iter.next().expect("No value for put");
-                            ops.push(BatchOp::Put(k.to_string(), v.to_string()));
-                        }
-                        "del" => {
-                            let k = iter.next().expect("No key for del");
-                            ops.push(BatchOp::Del(k.to_string()));
+        ["index", "drop", field] => {
+            let mut engine = db.borrow_mut();
+            match engine.drop_unique_index(field) {
+                Ok(_) => println!("OK (dropped unique index on '{}')", field),
+                Err(_) => fail!("No unique index on '{}'", field),
+            }
+        }
+
+        ["index", "list"] => {
+            let engine = db.borrow();
+            let mut table = Table::new();
+            table.add_row(Row::new(vec![Cell::new("Unique field"), Cell::new("Key prefix")]));
+            for (field, prefix) in engine.sec_index.unique_constraints() {
+                table.add_row(
+                    Row::new(vec![Cell::new(&field), Cell::new(prefix.as_deref().unwrap_or("*"))])
+                );
+            }
+            table.printstd();
+        }
+
+        ["fts", "create", name, fields, opts @ ..] => {
+            let fields: Vec<&str> = fields.split(',').collect();
+            let mut prefix = None;
+            let mut stem = false;
+            let mut iter = opts.iter();
+            while let Some(&opt) = iter.next() {
+                match opt {
+                    "prefix" => {
+                        prefix = iter.next().copied();
+                    }
+                    "stem" => {
+                        stem = true;
+                    }
+                    other => eprintln!("Ignoring unknown option '{}'", other),
+                }
+            }
+            let mut engine = db.borrow_mut();
+            match engine.create_fulltext_index(name, &fields, prefix, stem) {
+                Ok(_) => println!("OK (full-text index '{}')", name),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["fts", "drop", name] => {
+            let mut engine = db.borrow_mut();
+            match engine.drop_fulltext_index(name) {
+                Ok(_) => println!("OK (dropped '{}')", name),
+                Err(_) => fail!("No full-text index '{}'", name),
+            }
+        }
+
+        ["fts", "list"] => {
+            let engine = db.borrow();
+            let mut table = Table::new();
+            table.add_row(
+                Row::new(
+                    vec![
+                        Cell::new("Name"),
+                        Cell::new("Fields"),
+                        Cell::new("Key prefix"),
+                        Cell::new("Stemming")
+                    ]
+                )
+            );
+            for (name, def) in engine.fts.definitions() {
+                table.add_row(
+                    Row::new(
+                        vec![
+                            Cell::new(&name),
+                            Cell::new(&def.fields.join(",")),
+                            Cell::new(def.prefix.as_deref().unwrap_or("*")),
+                            Cell::new(if def.stem { "yes" } else { "no" })
+                        ]
+                    )
+                );
+            }
+            table.printstd();
+        }
+
+        ["search", index, query @ ..] if !query.is_empty() => {
+            let engine = db.borrow();
+            match engine.search(index, &query.join(" "), SEARCH_LIMIT) {
+                Ok(hits) if hits.is_empty() => println!("(no results)"),
+                Ok(hits) => {
+                    let mut table = Table::new();
+                    table.add_row(Row::new(vec![Cell::new("Key"), Cell::new("Score")]));
+                    for hit in hits {
+                        table.add_row(
+                            Row::new(
+                                vec![Cell::new(&hit.key), Cell::new(&format!("{:.4}", hit.score))]
+                            )
+                        );
+                    }
+                    table.printstd();
+                }
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["vindex", "create", name, field, dims, opts @ ..] => {
+            let dims: usize = match dims.parse() {
+                Ok(n) => n,
+                Err(_) => {
+                    fail!("Invalid dimensions '{}'", dims);
+                    return Flow::Continue;
+                }
+            };
+            let mut def = VectorIndexDef {
+                field: field.to_string(),
+                prefix: None,
+                dims,
+                metric: Metric::Cosine,
+                kind: VectorIndexKind::Flat,
+            };
+            let mut iter = opts.iter();
+            while let Some(&opt) = iter.next() {
+                match opt {
+                    "flat" => {
+                        def.kind = VectorIndexKind::Flat;
+                    }
+                    "hnsw" => {
+                        def.kind = VectorIndexKind::Hnsw;
+                    }
+                    "prefix" => {
+                        def.prefix = iter.next().map(|p| p.to_string());
+                    }
+                    other =>
+                        match Metric::parse(other) {
+                            Some(m) => {
+                                def.metric = m;
+                            }
+                            None => eprintln!("Ignoring unknown option '{}'", other),
                         }
-                        other => println!("Unknown batch op: {}", other),
-                    }
-                }
-                let mut engine = db.lock().unwrap();
-                engine.batch(ops).unwrap();
-                println!("Batch OK");
-            }
-
-            ["scan"] => {
-                let mut engine = db.lock().unwrap();
-                for (k, v) in engine.scan(None, None) {
-                    match v {
-                        Some(val) => println!("{} => {}", k, val),
-                        None => println!("{} => (expired or deleted)", k),
-                    }
-                }
-            }
-
-            ["scan", prefix] => {
-                let mut engine = db.lock().unwrap();
-                for (k, v) in engine.scan(Some(prefix), None) {
-                    match v {
-                        Some(val) => println!("{} => {}", k, val),
-                        None => println!("{} => (expired or deleted)", k),
-                    }
-                }
-            }
-
-            ["scan", start, end] => {
-                let mut engine = db.lock().unwrap();
-                for (k, v) in engine.scan(None, Some((start, end))) {
-                    match v {
-                        Some(val) => println!("{} => {}", k, val),
-                        None => println!("{} => (expired or deleted)", k),
-                    }
-                }
-            }
-
-            ["stats"] => {
-                let engine = db.lock().unwrap();
-                println!("{}", engine.stats());
-            }
-
-            ["eval", tail @ ..] => {
-                let src = tail.join(" ");
-                let mut engine = db.lock().unwrap();
-                // Add name/desc as needed, or use None for now
-                match engine.eval_register(&src, None, None) {
-                    Ok(sha) => println!("Script cached, SHA1={}", sha),
-                    Err(e) => println!("Error compiling script: {:?}", e),
-                }
-            }
-
-            ["evalsha", sha, tail @ ..] => {
-                // syntax: evalsha <sha> key1 key2 … -- arg1 arg2 …
-                let mut split = tail.split(|&s| s == "--");
-                let keys = split.next().unwrap_or(&[]).to_vec();
-                let args = split.next().unwrap_or(&[]).to_vec();
-                let mut engine = db.lock().unwrap();
-                match engine.eval_sha(sha, &keys, &args) {
-                    Ok(val) => print_lua_value(&val),
-                    Err(e) => {
-                        use mlua::Error as LuaError;
-                        match &e {
-                            Error::Lua(lua_err) =>
-                                match lua_err {
-                                    LuaError::SyntaxError { message, incomplete_input, .. } => {
-                                        println!("Lua syntax error: {}{}", message, if
-                                            *incomplete_input
-                                        {
-                                            " (incomplete input)"
-                                        } else {
-                                            ""
-                                        });
-                                    }
-                                    LuaError::RuntimeError(msg) => {
-                                        println!("Lua runtime error: {}", msg);
-                                    }
-                                    LuaError::MemoryError(_) => {
-                                        println!("Lua out of memory!");
-                                    }
-                                    LuaError::CallbackError { traceback, cause } => {
-                                        println!(
-                                            "Lua callback error: {}\nTraceback:\n{}",
-                                            cause,
-                                            traceback
-                                        );
-                                    }
-                                    _ => println!("Other Lua error: {:?}", lua_err),
-                                }
-                            other => println!("Error: {:?}", other),
+                }
+            }
+            let mut engine = db.borrow_mut();
+            match engine.create_vector_index(name, def) {
+                Ok(_) => println!("OK (vector index '{}')", name),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["vindex", "drop", name] => {
+            let mut engine = db.borrow_mut();
+            match engine.drop_vector_index(name) {
+                Ok(_) => println!("OK (dropped '{}')", name),
+                Err(_) => fail!("No vector index '{}'", name),
+            }
+        }
+
+        ["vindex", "list"] => {
+            let engine = db.borrow();
+            let mut table = Table::new();
+            table.add_row(
+                Row::new(
+                    vec![
+                        Cell::new("Name"),
+                        Cell::new("Field"),
+                        Cell::new("Dims"),
+                        Cell::new("Metric"),
+                        Cell::new("Kind"),
+                        Cell::new("Key prefix"),
+                        Cell::new("Vectors")
+                    ]
+                )
+            );
+            for (name, def, count) in engine.vectors.definitions() {
+                table.add_row(
+                    Row::new(
+                        vec![
+                            Cell::new(&name),
+                            Cell::new(&def.field),
+                            Cell::new(&def.dims.to_string()),
+                            Cell::new(&format!("{:?}", def.metric)),
+                            Cell::new(&format!("{:?}", def.kind)),
+                            Cell::new(def.prefix.as_deref().unwrap_or("*")),
+                            Cell::new(&count.to_string())
+                        ]
+                    )
+                );
+            }
+            table.printstd();
+        }
+
+        ["vsearch", index, k, vec_src, filter @ ..] if filter.len() <= 1 => {
+            let k: usize = match k.parse() {
+                Ok(n) => n,
+                Err(_) => {
+                    fail!("Invalid k '{}'", k);
+                    return Flow::Continue;
+                }
+            };
+            let query = match vector::parse_vector(vec_src) {
+                Ok(v) => v,
+                Err(e) => {
+                    fail!("ERR: {:?}", e);
+                    return Flow::Continue;
+                }
+            };
+            let filter = match filter.first() {
+                Some(f) =>
+                    match f.split_once('=') {
+                        Some(pair) => Some(pair),
+                        None => {
+                            fail!("Filter must be field=value");
+                            return Flow::Continue;
                         }
                     }
+                None => None,
+            };
+            let engine = db.borrow();
+            match engine.vsearch(index, k, &query, filter) {
+                Ok(hits) if hits.is_empty() => println!("(no results)"),
+                Ok(hits) => {
+                    let mut table = Table::new();
+                    table.add_row(Row::new(vec![Cell::new("Key"), Cell::new("Distance")]));
+                    for hit in hits {
+                        table.add_row(
+                            Row::new(
+                                vec![Cell::new(&hit.key), Cell::new(&format!("{:.6}", hit.distance))]
+                            )
+                        );
+                    }
+                    table.printstd();
                 }
+                Err(e) => fail!("ERR: {:?}", e),
             }
-            ["script", "load", filename, name, desc @ ..] => {
-                let script_desc = if desc.is_empty() { None } else { Some(desc.join(" ")) };
-                let mut engine = db.lock().unwrap();
-                let mut manager = ScriptManager::new(&mut engine);
-                match manager.load_script_from_file(filename, name, script_desc.as_deref()) {
-                    Ok(sha) => println!("Script '{}' cached, SHA1={}", name, sha),
-                    Err(e) => println!("Error compiling script: {:?}", e),
+        }
+
+        ["geo", "create", name, lat_field, lon_field, opts @ ..] => {
+            let prefix = match opts {
+                [] => None,
+                ["prefix", p] => Some(p.to_string()),
+                _ => {
+                    fail!("Usage: geo create <name> <lat_field> <lon_field> [prefix <p>]");
+                    return Flow::Continue;
                 }
+            };
+            let def = GeoIndexDef {
+                lat_field: lat_field.to_string(),
+                lon_field: lon_field.to_string(),
+                prefix,
+            };
+            let mut engine = db.borrow_mut();
+            match engine.create_geo_index(name, def) {
+                Ok(_) => println!("OK (geo index '{}')", name),
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            ["script", "begin", name, desc @ ..] => {
-                let script_desc = if desc.is_empty() { None } else { Some(desc.join(" ")) };
-                let mut engine = db.lock().unwrap();
-                let mut manager = ScriptManager::new(&mut engine);
-                match manager.begin_script_interactive(name, script_desc.as_deref()) {
-                    Ok(sha) => println!("Script '{}' cached, SHA1={}", name, sha),
-                    Err(e) => println!("Error compiling script: {:?}", e),
-                }
+        ["geo", "drop", name] => {
+            let mut engine = db.borrow_mut();
+            match engine.drop_geo_index(name) {
+                Ok(_) => println!("OK (dropped '{}')", name),
+                Err(_) => fail!("No geo index '{}'", name),
             }
+        }
 
-            ["script", "list"] => {
-                let mut engine = db.lock().unwrap();
-                let manager = ScriptManager::new(&mut engine);
-                let scripts = manager.list_scripts();
-                let mut table = Table::new();
+        ["geo", "list"] => {
+            let engine = db.borrow();
+            let mut table = Table::new();
+            table.add_row(
+                Row::new(
+                    vec![
+                        Cell::new("Name"),
+                        Cell::new("Lat field"),
+                        Cell::new("Lon field"),
+                        Cell::new("Key prefix"),
+                        Cell::new("Points")
+                    ]
+                )
+            );
+            for (name, def, count) in engine.geo.definitions() {
                 table.add_row(
-                    Row::new(vec![Cell::new("SHA1"), Cell::new("Name"), Cell::new("Description")])
+                    Row::new(
+                        vec![
+                            Cell::new(&name),
+                            Cell::new(&def.lat_field),
+                            Cell::new(&def.lon_field),
+                            Cell::new(def.prefix.as_deref().unwrap_or("*")),
+                            Cell::new(&count.to_string())
+                        ]
+                    )
                 );
-                for meta in scripts {
+            }
+            table.printstd();
+        }
+
+        ["geoadd", index, key, lon, lat] => {
+            let (Ok(lon), Ok(lat)) = (lon.parse::<f64>(), lat.parse::<f64>()) else {
+                fail!("Longitude and latitude must be numbers");
+                return Flow::Continue;
+            };
+            let mut engine = db.borrow_mut();
+            match engine.geoadd(index, key, lon, lat) {
+                Ok(_) => println!("OK"),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["geopos", index, key] => {
+            let engine = db.borrow();
+            match engine.geopos(index, key) {
+                Ok(Some((lon, lat))) => println!("{:.6} {:.6}", lon, lat),
+                Ok(None) => println!("(nil)"),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["geodist", index, a, b, unit @ ..] if unit.len() <= 1 => {
+            let Some(unit) = GeoUnit::parse(unit.first().unwrap_or(&"m")) else {
+                fail!("Unit must be m, km, mi or ft");
+                return Flow::Continue;
+            };
+            let engine = db.borrow();
+            match engine.geodist(index, a, b, unit) {
+                Ok(Some(d)) => println!("{:.4}", d),
+                Ok(None) => println!("(nil)"),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["geohash", index, key] => {
+            let engine = db.borrow();
+            match engine.geohash(index, key) {
+                Ok(Some(hash)) => println!("{}", hash),
+                Ok(None) => println!("(nil)"),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["geosearch", index, lon, lat, rest @ ..] => {
+            let (Ok(lon), Ok(lat)) = (lon.parse::<f64>(), lat.parse::<f64>()) else {
+                fail!("Longitude and latitude must be numbers");
+                return Flow::Continue;
+            };
+            let (shape, unit, opts) = match rest {
+                ["radius", r, unit, opts @ ..] =>
+                    match r.parse() {
+                        Ok(r) => (GeoShape::Radius(r), unit, opts),
+                        Err(_) => {
+                            fail!("Invalid radius '{}'", r);
+                            return Flow::Continue;
+                        }
+                    }
+                ["box", w, h, unit, opts @ ..] =>
+                    match (w.parse(), h.parse()) {
+                        (Ok(width), Ok(height)) => (GeoShape::Box { width, height }, unit, opts),
+                        _ => {
+                            fail!("Invalid box size '{} {}'", w, h);
+                            return Flow::Continue;
+                        }
+                    }
+                _ => {
+                    fail!(
+                        "Usage: geosearch <index> <lon> <lat> radius <r> <unit> | box <w> <h> <unit> [count <n>] [asc|desc]"
+                    );
+                    return Flow::Continue;
+                }
+            };
+            let Some(unit) = GeoUnit::parse(unit) else {
+                fail!("Unit must be m, km, mi or ft");
+                return Flow::Continue;
+            };
+            let mut count = None;
+            let mut descending = false;
+            let mut iter = opts.iter();
+            while let Some(&opt) = iter.next() {
+                match opt {
+                    "asc" => {
+                        descending = false;
+                    }
+                    "desc" => {
+                        descending = true;
+                    }
+                    "count" => {
+                        count = iter.next().and_then(|n| n.parse().ok());
+                    }
+                    other => eprintln!("Ignoring unknown option '{}'", other),
+                }
+            }
+            let engine = db.borrow();
+            match engine.geosearch(index, (lon, lat), shape, unit, count, descending) {
+                Ok(hits) if hits.is_empty() => println!("(no results)"),
+                Ok(hits) => {
+                    let mut table = Table::new();
                     table.add_row(
                         Row::new(
                             vec![
-                                Cell::new(&meta.sha1),
-                                Cell::new(&meta.name),
-                                Cell::new(meta.desc.as_deref().unwrap_or(""))
+                                Cell::new("Key"),
+                                Cell::new("Distance"),
+                                Cell::new("Longitude"),
+                                Cell::new("Latitude")
                             ]
                         )
                     );
+                    for hit in hits {
+                        table.add_row(
+                            Row::new(
+                                vec![
+                                    Cell::new(&hit.key),
+                                    Cell::new(&format!("{:.4}", hit.distance)),
+                                    Cell::new(&format!("{:.6}", hit.lon)),
+                                    Cell::new(&format!("{:.6}", hit.lat))
+                                ]
+                            )
+                        );
+                    }
+                    table.printstd();
                 }
-                table.printstd();
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            ["script", "run", sha_or_name, tail @ ..] => {
-                let mut split = tail.split(|&s| s == "--");
-                let keys: Vec<String> = split
-                    .next()
-                    .unwrap_or(&[])
-                    .iter()
-                    .map(|s| s.to_string())
-                    .collect();
-                let args: Vec<String> = split
-                    .next()
-                    .unwrap_or(&[])
-                    .iter()
-                    .map(|s| s.to_string())
-                    .collect();
-                let mut engine = db.lock().unwrap();
-                let mut manager = ScriptManager::new(&mut engine);
-                match manager.run_script(sha_or_name, &keys, &args) {
-                    Ok(val) => print_lua_value(&val),
-                    Err(e) => println!("Error running script: {:?}", e),
+        ["agg", tail @ ..] => {
+            let query = match AggQuery::parse(&tail.join(" ")) {
+                Ok(q) => q,
+                Err(e) => {
+                    fail!("ERR: {:?}", e);
+                    return Flow::Continue;
+                }
+            };
+            let engine = db.borrow();
+            match engine.aggregate(&query) {
+                Ok(rows) if query.group_by.is_none() => {
+                    match rows.first().and_then(|r| r.value) {
+                        Some(v) => println!("{}", v),
+                        None => println!("(nil)"),
+                    }
                 }
+                Ok(rows) => {
+                    let mut table = Table::new();
+                    table.add_row(
+                        Row::new(vec![Cell::new("Group"), Cell::new("Value"), Cell::new("Count")])
+                    );
+                    for row in rows {
+                        table.add_row(
+                            Row::new(
+                                vec![
+                                    Cell::new(row.group.as_deref().unwrap_or("")),
+                                    Cell::new(
+                                        &row.value.map(|v| v.to_string()).unwrap_or_default()
+                                    ),
+                                    Cell::new(&row.count.to_string())
+                                ]
+                            )
+                        );
+                    }
+                    table.printstd();
+                }
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            ["script", "rename", old_name, new_name] => {
-                let mut engine = db.lock().unwrap();
-                let mut manager = ScriptManager::new(&mut engine);
-                match manager.rename_script(old_name, new_name) {
-                    Ok(()) => println!("Script '{}' renamed to '{}'", old_name, new_name),
-                    Err(_) => println!("Script name '{}' not found", old_name),
+        ["batch", tail @ ..] => {
+            let mut ops = Vec::new();
+            let mut iter = tail.iter();
+            while let Some(&cmd) = iter.next() {
+                match cmd {
+                    "put" => {
+                        let k = iter.next().expect("No key for put");
+                        let v = iter.next().expect("No value for put");
+                        ops.push(BatchOp::Put(k.to_string(), v.to_string()));
+                    }
+                    "del" => {
+                        let k = iter.next().expect("No key for del");
+                        ops.push(BatchOp::Del(k.to_string()));
+                    }
+                    other => fail!("Unknown batch op: {}", other),
                 }
             }
-            ["script", "remove", sha_or_name] => {
-                let mut engine = db.lock().unwrap();
-                let mut manager = ScriptManager::new(&mut engine);
-                match manager.remove_script(sha_or_name) {
-                    Ok(()) => println!("Script '{}' removed.", sha_or_name),
-                    Err(_) => println!("Script '{}' not found.", sha_or_name),
-                }
+            let mut engine = db.borrow_mut();
+            match engine.batch(ops) {
+                Ok(_) => println!("Batch OK"),
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            // JSON commands
-
-            ["json", "set", key, field, value] => {
-                let mut engine = db.lock().unwrap();
-                match engine.json_set_field(key, field, value) {
-                    Ok(_) => println!("OK"),
-                    Err(e) => println!("ERR: {:?}", e),
+        ["scan"] => {
+            let mut engine = db.borrow_mut();
+            for (k, v) in engine.scan(None, None) {
+                match v {
+                    Some(val) => println!("{} => {}", display_key(&k), val),
+                    None => println!("{} => (expired, deleted or binary)", display_key(&k)),
                 }
             }
+        }
 
-            ["json", "get", key, field] => {
-                let mut engine = db.lock().unwrap();
-                match engine.json_get_field(key, field) {
-                    Some(val) => println!("{}", val),
-                    None => println!("(nil)"),
+        ["scan", prefix] => {
+            let mut engine = db.borrow_mut();
+            for (k, v) in engine.scan(Some(prefix), None) {
+                match v {
+                    Some(val) => println!("{} => {}", display_key(&k), val),
+                    None => println!("{} => (expired, deleted or binary)", display_key(&k)),
                 }
             }
-            ["list", "push", key, value] => {
-                let mut engine = db.lock().unwrap();
-                match engine.list_push(key, value) {
-                    Ok(_) => println!("OK (pushed '{}' to list '{}')", value, key),
-                    Err(e) => println!("ERR: {:?}", e),
+        }
+
+        ["scan", start, end] => {
+            let mut engine = db.borrow_mut();
+            for (k, v) in engine.scan(None, Some((start, end))) {
+                match v {
+                    Some(val) => println!("{} => {}", display_key(&k), val),
+                    None => println!("{} => (expired, deleted or binary)", display_key(&k)),
                 }
             }
+        }
 
-            ["list", "show", key] | ["set", "show", key] => {
-                let mut engine = db.lock().unwrap();
-                match engine.get(key) {
-                    Some(val) => println!("{}", val),
-                    None => println!("(nil)"),
-                }
+        ["stats"] => {
+            let engine = db.borrow();
+            println!("{}", engine.stats());
+        }
+
+        ["eval", tail @ ..] => {
+            let src = tail.join(" ");
+            let mut engine = db.borrow_mut();
+            // Add name/desc as needed, or use None for now
+            match engine.eval_register(&src, None, None) {
+                Ok(sha) => println!("Script cached, SHA1={}", sha),
+                Err(e) => script_failed("Error compiling script", &e),
             }
+        }
 
-            ["set", "add", key, value] => {
-                let mut engine = db.lock().unwrap();
-                match engine.set_add(key, value) {
-                    Ok(_) => println!("OK (added '{}' to set '{}')", value, key),
-                    Err(e) => println!("ERR: {:?}", e),
+        ["evalsha", sha, tail @ ..] => {
+            // syntax: evalsha <sha> key1 key2 … -- arg1 arg2 …
+            let mut split = tail.split(|&s| s == "--");
+            let keys = split.next().unwrap_or(&[]).to_vec();
+            let args = split.next().unwrap_or(&[]).to_vec();
+            let mut engine = db.borrow_mut();
+            match engine.eval_sha(sha, &keys, &args) {
+                Ok(val) => print_lua_value(&val),
+                Err(e) => {
+                    use mlua::Error as LuaError;
+                    match &e {
+                        Error::Lua(lua_err) =>
+                            match lua_err {
+                                LuaError::SyntaxError { message, incomplete_input, .. } => {
+                                    fail!("Lua syntax error: {}{}", message, if
+                                        *incomplete_input
+                                    {
+                                        " (incomplete input)"
+                                    } else {
+                                        ""
+                                    });
+                                }
+                                LuaError::RuntimeError(msg) => {
+                                    fail!("Lua runtime error: {}", msg);
+                                }
+                                LuaError::MemoryError(_) => {
+                                    fail!("Lua out of memory!");
+                                }
+                                LuaError::CallbackError { traceback, cause } => {
+                                    fail!(
+                                        "Lua callback error: {}\nTraceback:\n{}",
+                                        cause,
+                                        traceback
+                                    );
+                                }
+                                _ => fail!("Other Lua error: {:?}", lua_err),
+                            }
+                        other => script_failed("Error", other),
+                    }
                 }
             }
+        }
+        ["script", "load", filename, name, desc @ ..] => {
+            let script_desc = if desc.is_empty() { None } else { Some(desc.join(" ")) };
+            let mut engine = db.borrow_mut();
+            let mut manager = ScriptManager::new(&mut engine);
+            match manager.load_script_from_file(filename, name, script_desc.as_deref()) {
+                Ok(sha) => println!("Script '{}' cached, SHA1={}", name, sha),
+                Err(e) => script_failed("Error compiling script", &e),
+            }
+        }
 
-            // Hash JSON commands
-            // Set field in a hash (JSON object)
-            ["hash", "set", key, field, value] => {
-                let mut engine = db.lock().unwrap();
-                match engine.hash_set(key, field, value) {
-                    Ok(_) => println!("OK (set '{}:{}')", key, field),
-                    Err(e) => println!("ERR: {:?}", e),
-                }
+        ["script", "begin", name, desc @ ..] => {
+            let script_desc = if desc.is_empty() { None } else { Some(desc.join(" ")) };
+            let mut engine = db.borrow_mut();
+            let mut manager = ScriptManager::new(&mut engine);
+            match manager.begin_script_interactive(name, script_desc.as_deref(), next_line) {
+                Ok(sha) => println!("Script '{}' cached, SHA1={}", name, sha),
+                Err(e) => script_failed("Error compiling script", &e),
             }
+        }
 
-            // Get field from a hash
-            ["hash", "get", key, field] => {
-                let mut engine = db.lock().unwrap();
-                match engine.hash_get(key, field) {
-                    Some(val) => println!("{}", val),
-                    None => println!("(nil)"),
+        ["script", "list"] => {
+            let mut engine = db.borrow_mut();
+            let manager = ScriptManager::new(&mut engine);
+            let scripts = manager.list_scripts();
+            let mut table = Table::new();
+            table.add_row(
+                Row::new(
+                    vec![
+                        Cell::new("SHA1"),
+                        Cell::new("Name"),
+                        Cell::new("Description"),
+                        Cell::new("Limits"),
+                        Cell::new("Functions"),
+                        Cell::new("Calls"),
+                        Cell::new("Errors"),
+                        Cell::new("Avg (ms)"),
+                        Cell::new("P99 (ms)"),
+                        Cell::new("Last error")
+                    ]
+                )
+            );
+            for meta in scripts {
+                let limit = |v: Option<String>| v.unwrap_or_else(|| "none".to_string());
+                let limits = format!(
+                    "instructions={} memory={} timeout={}",
+                    limit(meta.limits.max_instructions.map(|n| n.to_string())),
+                    limit(meta.limits.max_memory.map(|n| n.to_string())),
+                    limit(meta.limits.timeout_ms.map(|n| format!("{}ms", n)))
+                );
+                let stats = manager.script_stats(&meta.sha1).unwrap_or_default();
+                let ms = |us: u64| format!("{:.2}", (us as f64) / 1000.0);
+                table.add_row(
+                    Row::new(
+                        vec![
+                            Cell::new(&meta.sha1),
+                            Cell::new(&meta.name),
+                            Cell::new(meta.desc.as_deref().unwrap_or("")),
+                            Cell::new(&limits),
+                            Cell::new(""),
+                            Cell::new(&stats.calls.to_string()),
+                            Cell::new(&stats.errors.to_string()),
+                            Cell::new(&ms(stats.avg_us())),
+                            Cell::new(&ms(stats.p99_us())),
+                            Cell::new(stats.last_error.as_deref().unwrap_or(""))
+                        ]
+                    )
+                );
+            }
+            // Libraries run within the limits of the script that requires them
+            for lib in manager.list_libraries() {
+                for v in &lib.versions {
+                    table.add_row(
+                        Row::new(
+                            vec![
+                                Cell::new(&v.sha1),
+                                Cell::new(&format!("{}@{} (library)", lib.name, v.version)),
+                                Cell::new(v.desc.as_deref().unwrap_or("")),
+                                Cell::new("caller's"),
+                                Cell::new(&v.functions.join(", ")),
+                                Cell::new(""),
+                                Cell::new(""),
+                                Cell::new(""),
+                                Cell::new(""),
+                                Cell::new("")
+                            ]
+                        )
+                    );
                 }
             }
+            table.printstd();
+        }
+
+        ["library", "load", filename, name, desc @ ..] => {
+            let lib_desc = if desc.is_empty() { None } else { Some(desc.join(" ")) };
+            let mut engine = db.borrow_mut();
+            let mut manager = ScriptManager::new(&mut engine);
+            match manager.load_library_from_file(filename, name, lib_desc.as_deref()) {
+                Ok(version) => println!("Library '{}' version {} loaded", name, version),
+                Err(e) => script_failed("Error loading library", &e),
+            }
+        }
 
-            // Delete field from a hash
-            ["hash", "del", key, field] => {
-                let mut engine = db.lock().unwrap();
-                match engine.hash_del(key, field) {
-                    Ok(_) => println!("OK (deleted '{}:{}')", key, field),
-                    Err(e) => println!("ERR: {:?}", e),
+        ["library", "remove", name, version @ ..] if version.len() <= 1 => {
+            let version = match version.first().map(|v| v.parse::<u32>()) {
+                None => None,
+                Some(Ok(v)) => Some(v),
+                Some(Err(_)) => {
+                    fail!("Usage: library remove <name> [version]");
+                    return Flow::Continue;
                 }
+            };
+            let mut engine = db.borrow_mut();
+            let id = match version {
+                Some(v) => format!("{}@{}", name, v),
+                None => name.to_string(),
+            };
+            match engine.library_remove(name, version) {
+                Ok(()) => println!("Library '{}' removed.", id),
+                Err(_) => fail!("Library '{}' not found.", id),
+            }
+        }
+
+        ["script", "run", sha_or_name, tail @ ..] => {
+            let mut split = tail.split(|&s| s == "--");
+            let keys: Vec<String> = split
+                .next()
+                .unwrap_or(&[])
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            let args: Vec<String> = split
+                .next()
+                .unwrap_or(&[])
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            let mut engine = db.borrow_mut();
+            let mut manager = ScriptManager::new(&mut engine);
+            match manager.run_script(sha_or_name, &keys, &args) {
+                Ok(val) => print_lua_value(&val),
+                Err(e) => script_failed("Error running script", &e),
             }
+        }
 
-            // Get all fields/values from a hash
-            ["hash", "getall", key] => {
-                let mut engine = db.lock().unwrap();
-                match engine.hash_getall(key) {
-                    Some(map) => {
-                        for (k, v) in map {
-                            println!("{}: {}", k, v);
+        ["script", "limits", sha_or_name, opts @ ..] if opts.len() % 2 == 0 => {
+            let mut engine = db.borrow_mut();
+            let sha = engine.script_names
+                .get(*sha_or_name)
+                .cloned()
+                .unwrap_or_else(|| sha_or_name.to_string());
+            let Some(mut limits) = engine.script_meta.get(&sha).map(|m| m.limits.clone()) else {
+                fail!("Script '{}' not found.", sha_or_name);
+                return Flow::Continue;
+            };
+            let mut valid = true;
+            for pair in opts.chunks(2) {
+                let value = match pair[1] {
+                    "none" => None,
+                    v =>
+                        match v.parse::<u64>() {
+                            Ok(n) => Some(n),
+                            Err(_) => {
+                                valid = false;
+                                break;
+                            }
                         }
+                };
+                match pair[0] {
+                    "instructions" => {
+                        limits.max_instructions = value;
+                    }
+                    "memory" => {
+                        limits.max_memory = value.map(|n| n as usize);
+                    }
+                    "timeout" => {
+                        limits.timeout_ms = value;
+                    }
+                    _ => {
+                        valid = false;
+                        break;
                     }
-                    None => println!("(nil)"),
                 }
             }
+            if !valid {
+                fail!(
+                    "Usage: script limits <sha_or_name> [instructions <n>|none] [memory <bytes>|none] [timeout <ms>|none]"
+                );
+                return Flow::Continue;
+            }
+            match engine.set_script_limits(sha_or_name, limits) {
+                Ok(()) => println!("OK"),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
 
-            // List commands
-            ["list", "lpush", key, value] => {
-                let mut engine = db.lock().unwrap();
-                match engine.list_lpush(key, value) {
-                    Ok(_) => println!("OK (lpush '{}' to '{}')", value, key),
-                    Err(e) => println!("ERR: {:?}", e),
+        ["script", "rename", old_name, new_name] => {
+            let mut engine = db.borrow_mut();
+            let mut manager = ScriptManager::new(&mut engine);
+            match manager.rename_script(old_name, new_name) {
+                Ok(()) => println!("Script '{}' renamed to '{}'", old_name, new_name),
+                Err(_) => fail!("Script name '{}' not found", old_name),
+            }
+        }
+        ["script", "remove", sha_or_name] => {
+            let mut engine = db.borrow_mut();
+            let mut manager = ScriptManager::new(&mut engine);
+            match manager.remove_script(sha_or_name) {
+                Ok(()) => println!("Script '{}' removed.", sha_or_name),
+                Err(_) => fail!("Script '{}' not found.", sha_or_name),
+            }
+        }
+
+        ["script", "slowlog", "reset"] => {
+            db.borrow_mut().slow_log.reset();
+            println!("Slow log cleared.");
+        }
+
+        ["script", "slowlog", "threshold", ms] => {
+            match ms.parse::<u64>() {
+                Ok(ms) => {
+                    db.borrow_mut().slow_log.threshold_ms = ms;
+                    println!("Slow log threshold set to {} ms", ms);
                 }
+                Err(_) => fail!("Invalid threshold '{}'", ms),
             }
-            ["list", "rpush", key, value] => {
-                let mut engine = db.lock().unwrap();
-                match engine.list_rpush(key, value) {
-                    Ok(_) => println!("OK (rpush '{}' to '{}')", value, key),
-                    Err(e) => println!("ERR: {:?}", e),
+        }
+
+        ["script", "slowlog", count @ ..] if count.len() <= 1 => {
+            let count = match count.first().map(|c| c.parse::<usize>()) {
+                None => 10,
+                Some(Ok(n)) => n,
+                Some(Err(_)) => {
+                    fail!("Invalid count '{}'", count[0]);
+                    return Flow::Continue;
                 }
+            };
+            let engine = db.borrow();
+            if engine.slow_log.is_empty() {
+                println!("Slow log is empty.");
+                return Flow::Continue;
             }
-            ["list", "lpop", key] => {
-                let mut engine = db.lock().unwrap();
-                match engine.list_lpop(key) {
-                    Some(val) => println!("{}", val),
-                    None => println!("(nil)"),
+            let mut table = Table::new();
+            table.add_row(
+                Row::new(
+                    vec![
+                        Cell::new("ID"),
+                        Cell::new("Started"),
+                        Cell::new("Duration (ms)"),
+                        Cell::new("Script"),
+                        Cell::new("KEYS"),
+                        Cell::new("ARGV"),
+                        Cell::new("Outcome")
+                    ]
+                )
+            );
+            for entry in engine.slow_log.entries(count) {
+                table.add_row(
+                    Row::new(
+                        vec![
+                            Cell::new(&entry.id.to_string()),
+                            Cell::new(&schedule::format_timestamp(entry.started_at)),
+                            Cell::new(&format!("{:.2}", (entry.duration_us as f64) / 1000.0)),
+                            Cell::new(&entry.script),
+                            Cell::new(&entry.keys.join(" ")),
+                            Cell::new(&entry.args.join(" ")),
+                            Cell::new(&entry.outcome)
+                        ]
+                    )
+                );
+            }
+            table.printstd();
+        }
+
+        ["trigger", "create", name, event, pattern, script] => {
+            let Some(event) = TriggerEvent::parse(event) else {
+                fail!("Unknown event '{}' (before_put|after_put|after_delete|on_expire)", event);
+                return Flow::Continue;
+            };
+            let def = TriggerDef {
+                event,
+                pattern: pattern.to_string(),
+                script: script.to_string(),
+            };
+            let mut engine = db.borrow_mut();
+            match engine.create_trigger(name, def) {
+                Ok(_) => println!("OK (trigger '{}')", name),
+                Err(Error::NotFound) => fail!("Script '{}' not found.", script),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["trigger", "drop", name] => {
+            let mut engine = db.borrow_mut();
+            match engine.drop_trigger(name) {
+                Ok(_) => println!("OK (dropped '{}')", name),
+                Err(_) => fail!("No trigger '{}'", name),
+            }
+        }
+
+        ["trigger", "list"] => {
+            let engine = db.borrow();
+            let mut table = Table::new();
+            table.add_row(
+                Row::new(
+                    vec![
+                        Cell::new("Name"),
+                        Cell::new("Event"),
+                        Cell::new("Key pattern"),
+                        Cell::new("Script")
+                    ]
+                )
+            );
+            for (name, def) in engine.triggers.definitions() {
+                table.add_row(
+                    Row::new(
+                        vec![
+                            Cell::new(&name),
+                            Cell::new(def.event.as_str()),
+                            Cell::new(&def.pattern),
+                            Cell::new(&def.script)
+                        ]
+                    )
+                );
+            }
+            table.printstd();
+        }
+
+        ["prefix", "set", prefix, format, content @ ..] if content.len() <= 1 => {
+            let content_type = match content.first() {
+                None => ContentType::Any,
+                Some(c) =>
+                    match ContentType::parse(c) {
+                        Some(t) => t,
+                        None => {
+                            fail!("Unknown content type '{}' (any|json|object|array)", c);
+                            return Flow::Continue;
+                        }
+                    }
+            };
+            let mut engine = db.borrow_mut();
+            match engine.set_prefix_rule(prefix, format, content_type) {
+                Ok(_) => println!("OK ('{}*' uses {}, {})", prefix, format, content_type.as_str()),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["prefix", "drop", prefix] => {
+            let mut engine = db.borrow_mut();
+            match engine.remove_prefix_rule(prefix) {
+                Ok(_) => println!("OK (dropped '{}')", prefix),
+                Err(_) => fail!("No rule for prefix '{}'", prefix),
+            }
+        }
+
+        ["prefix", "list"] => {
+            let engine = db.borrow();
+            let mut table = Table::new();
+            table.add_row(
+                Row::new(
+                    vec![Cell::new("Prefix"), Cell::new("Serializer"), Cell::new("Content type")]
+                )
+            );
+            for (prefix, rule) in engine.prefixes.definitions() {
+                table.add_row(
+                    Row::new(
+                        vec![
+                            Cell::new(&prefix),
+                            Cell::new(&rule.serializer),
+                            Cell::new(rule.content_type.as_str())
+                        ]
+                    )
+                );
+            }
+            table.printstd();
+        }
+
+        ["schedule", "add", name, "every", interval, script, tail @ ..] => {
+            let spec = match ScheduleSpec::parse_interval(interval) {
+                Ok(spec) => spec,
+                Err(e) => {
+                    fail!("ERR: {:?}", e);
+                    return Flow::Continue;
                 }
+            };
+            let (keys, args) = split_keys_args(tail);
+            let mut engine = db.borrow_mut();
+            match engine.add_schedule(name, spec, script, keys, args) {
+                Ok(_) => println!("OK (schedule '{}')", name),
+                Err(Error::NotFound) => fail!("Script '{}' not found.", script),
+                Err(e) => fail!("ERR: {:?}", e),
             }
-            ["list", "rpop", key] => {
-                let mut engine = db.lock().unwrap();
-                match engine.list_rpop(key) {
-                    Some(val) => println!("{}", val),
-                    None => println!("(nil)"),
+        }
+
+        ["schedule", "add", name, "cron", m, h, dom, mon, dow, script, tail @ ..] => {
+            let expr = [*m, *h, *dom, *mon, *dow].join(" ");
+            let spec = match ScheduleSpec::parse_cron(&expr) {
+                Ok(spec) => spec,
+                Err(e) => {
+                    fail!("ERR: {:?}", e);
+                    return Flow::Continue;
                 }
+            };
+            let (keys, args) = split_keys_args(tail);
+            let mut engine = db.borrow_mut();
+            match engine.add_schedule(name, spec, script, keys, args) {
+                Ok(_) => println!("OK (schedule '{}')", name),
+                Err(Error::NotFound) => fail!("Script '{}' not found.", script),
+                Err(e) => fail!("ERR: {:?}", e),
             }
-            ["list", "range", key, start, end] => {
-                let mut engine = db.lock().unwrap();
-                let s = start.parse().unwrap_or(0);
-                let e = end.parse().unwrap_or(0);
-                match engine.list_range(key, s, e) {
-                    Some(items) if !items.is_empty() => {
-                        for item in items {
-                            println!("{}", item);
-                        }
+        }
+
+        ["schedule", "remove", name] => {
+            let mut engine = db.borrow_mut();
+            match engine.remove_schedule(name) {
+                Ok(_) => println!("OK (removed '{}')", name),
+                Err(_) => fail!("No schedule '{}'", name),
+            }
+        }
+
+        ["schedule", "run"] => {
+            let mut engine = db.borrow_mut();
+            match engine.run_due_schedules() {
+                Ok(ran) if ran.is_empty() => println!("No schedules due."),
+                Ok(ran) => {
+                    for (name, record) in &ran {
+                        println!("[schedule '{}'] finished in {} ms: {}", name, record.duration_ms, record.outcome);
                     }
-                    _ => println!("(nil)"),
                 }
+                Err(e) => fail!("ERR: {:?}", e),
             }
-            ["list", "len", key] => {
-                let mut engine = db.lock().unwrap();
-                let len = engine.list_len(key);
-                println!("{}", len);
+        }
+
+        ["schedule", "list"] => {
+            let engine = db.borrow();
+            let mut table = Table::new();
+            table.add_row(
+                Row::new(
+                    vec![
+                        Cell::new("Name"),
+                        Cell::new("Schedule"),
+                        Cell::new("Script"),
+                        Cell::new("Keys"),
+                        Cell::new("Args"),
+                        Cell::new("Next run (UTC)"),
+                        Cell::new("Last run (UTC)"),
+                        Cell::new("Duration"),
+                        Cell::new("Outcome")
+                    ]
+                )
+            );
+            for (name, sched) in engine.schedules.definitions() {
+                let last = sched.last_run.as_ref();
+                table.add_row(
+                    Row::new(
+                        vec![
+                            Cell::new(&name),
+                            Cell::new(&sched.spec.to_string()),
+                            Cell::new(&sched.script),
+                            Cell::new(&sched.keys.join(" ")),
+                            Cell::new(&sched.args.join(" ")),
+                            Cell::new(
+                                &sched.next_run.map(schedule::format_timestamp).unwrap_or_default()
+                            ),
+                            Cell::new(
+                                &last
+                                    .map(|r| schedule::format_timestamp(r.started_at))
+                                    .unwrap_or_default()
+                            ),
+                            Cell::new(
+                                &last.map(|r| format!("{} ms", r.duration_ms)).unwrap_or_default()
+                            ),
+                            Cell::new(last.map(|r| r.outcome.as_str()).unwrap_or(""))
+                        ]
+                    )
+                );
             }
+            table.printstd();
+        }
+
+        // JSON commands
 
-            ["exit"] | ["quit"] => {
-                break;
+        ["json", "set", key, field, value] => {
+            let mut engine = db.borrow_mut();
+            match engine.json_set_field(key, field, value) {
+                Ok(_) => println!("OK"),
+                Err(e) => fail!("ERR: {:?}", e),
             }
+        }
 
-            _ =>
-                println!(
-                    "Usage: \
-                put <key> <value> | \
-                putex <key> <value> <ttl_secs> | \
-                get <key> | del <key> | compact | \
-                snapshot <file> | restore <file> | \
-                batch ... | scan [prefix] | scan <start> <end> | \
-                stats | eval <lua_src> | evalsha <sha> [keys] -- [args] | exit"
-                ),
+        ["json", "get", key, field] => {
+            let mut engine = db.borrow_mut();
+            match engine.json_get_field(key, field) {
+                Some(val) => println!("{}", val),
+                None => println!("(nil)"),
+            }
+        }
+        ["list", "push", key, value] => {
+            let mut engine = db.borrow_mut();
+            match engine.list_push(key, value) {
+                Ok(_) => println!("OK (pushed '{}' to list '{}')", value, key),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        ["list", "show", key] | ["set", "show", key] => {
+            let mut engine = db.borrow_mut();
+            match engine.get(key) {
+                Some(val) => println!("{}", val),
+                None => println!("(nil)"),
+            }
+        }
+
+        ["set", "add", key, value] => {
+            let mut engine = db.borrow_mut();
+            match engine.set_add(key, value) {
+                Ok(_) => println!("OK (added '{}' to set '{}')", value, key),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        // Hash JSON commands
+        // Set field in a hash (JSON object)
+        ["hash", "set", key, field, value] => {
+            let mut engine = db.borrow_mut();
+            match engine.hash_set(key, field, value) {
+                Ok(_) => println!("OK (set '{}:{}')", key, field),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        // Get field from a hash
+        ["hash", "get", key, field] => {
+            let mut engine = db.borrow_mut();
+            match engine.hash_get(key, field) {
+                Some(val) => println!("{}", val),
+                None => println!("(nil)"),
+            }
+        }
+
+        // Delete field from a hash
+        ["hash", "del", key, field] => {
+            let mut engine = db.borrow_mut();
+            match engine.hash_del(key, field) {
+                Ok(_) => println!("OK (deleted '{}:{}')", key, field),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+
+        // Get all fields/values from a hash
+        ["hash", "getall", key] => {
+            let mut engine = db.borrow_mut();
+            match engine.hash_getall(key) {
+                Some(map) => {
+                    for (k, v) in map {
+                        println!("{}: {}", k, v);
+                    }
+                }
+                None => println!("(nil)"),
+            }
+        }
+
+        // List commands
+        ["list", "lpush", key, value] => {
+            let mut engine = db.borrow_mut();
+            match engine.list_lpush(key, value) {
+                Ok(_) => println!("OK (lpush '{}' to '{}')", value, key),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
+        }
+        ["list", "rpush", key, value] => {
+            let mut engine = db.borrow_mut();
+            match engine.list_rpush(key, value) {
+                Ok(_) => println!("OK (rpush '{}' to '{}')", value, key),
+                Err(e) => fail!("ERR: {:?}", e),
+            }
         }
+        ["list", "lpop", key] => {
+            let mut engine = db.borrow_mut();
+            match engine.list_lpop(key) {
+                Some(val) => println!("{}", val),
+                None => println!("(nil)"),
+            }
+        }
+        ["list", "rpop", key] => {
+            let mut engine = db.borrow_mut();
+            match engine.list_rpop(key) {
+                Some(val) => println!("{}", val),
+                None => println!("(nil)"),
+            }
+        }
+        ["list", "range", key, start, end] => {
+            let mut engine = db.borrow_mut();
+            let s = start.parse().unwrap_or(0);
+            let e = end.parse().unwrap_or(0);
+            match engine.list_range(key, s, e) {
+                Some(items) if !items.is_empty() => {
+                    for item in items {
+                        println!("{}", item);
+                    }
+                }
+                _ => println!("(nil)"),
+            }
+        }
+        ["list", "len", key] => {
+            let mut engine = db.borrow_mut();
+            let len = engine.list_len(key);
+            println!("{}", len);
+        }
+
+        ["exit"] | ["quit"] => {
+            return Flow::Exit;
+        }
+
+        _ =>
+            fail!(
+                "Usage: \
+            put <key> <value> | \
+            putex <key> <value> <ttl_secs> | \
+            get <key> | del <key> | putbin <key|hex:key> hex|base64 <data> | getbin <key|hex:key> [hex|base64] | putenc <key> <hex> | getenc <key> | compact | migrate-format <format> | \
+            compression [lz4|zstd [threshold_bytes] | off] | codecs | codecs set <id> [id..] | codecs none | \
+            rotate-key keyfile <path> | passphrase <p> [aes-256-gcm|chacha20-poly1305] | rotate-key none | \
+            snapshot <file> | restore <file> | \
+            batch ... | scan [prefix] | scan <start> <end> | \
+            index unique <field> [prefix] | index drop <field> | index list | \
+            fts create <name> <field,..> [prefix <p>] [stem] | fts drop <name> | fts list | \
+            search <index> <query> | \
+            vindex create <name> <field> <dims> [cosine|dot|l2] [flat|hnsw] [prefix <p>] | \
+            vindex drop <name> | vindex list | vsearch <index> <k> <v1,v2,..> [field=value] | \
+            geo create <name> <lat_field> <lon_field> [prefix <p>] | geo drop <name> | geo list | \
+            geoadd <index> <key> <lon> <lat> | geopos <index> <key> | geohash <index> <key> | \
+            geodist <index> <key1> <key2> [m|km|mi|ft] | \
+            geosearch <index> <lon> <lat> radius <r> <unit> | box <w> <h> <unit> [count <n>] [asc|desc] | \
+            agg <func> [field] [by <field>] [where <field>=<value>] [prefix <p>] | \
+            trigger create <name> <event> <pattern> <script> | trigger drop <name> | trigger list | \
+            schedule add <name> every <n>[s|m|h|d] | cron <m> <h> <dom> <mon> <dow> <script> [keys] [-- args] | \
+            schedule remove <name> | schedule run | schedule list | \
+            prefix set <prefix> <format> [any|json|object|array] | prefix drop <prefix> | prefix list | \
+            library load <file> <name> [desc] | library remove <name> [version] | \
+            script slowlog [count] | script slowlog reset | script slowlog threshold <ms> | \
+            stats | eval <lua_src> | evalsha <sha> [keys] -- [args] | exit"
+            ),
     }
+    Flow::Continue
 }
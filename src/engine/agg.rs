@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use crate::types::{ Error, Result };
+
+/// Aggregate function applied to a JSON field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggFunc {
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "count" => Some(AggFunc::Count),
+            "sum" => Some(AggFunc::Sum),
+            "avg" => Some(AggFunc::Avg),
+            "min" => Some(AggFunc::Min),
+            "max" => Some(AggFunc::Max),
+            _ => None,
+        }
+    }
+}
+
+/// An aggregation over JSON documents, optionally narrowed by a key prefix
+/// and/or a `field=value` secondary index lookup.
+///
+/// Textual form (used by the CLI and Lua):
+/// `<func> [field] [by <group_field>] [where <field>=<value>] [prefix <key_prefix>]`
+#[derive(Debug, Clone)]
+pub struct AggQuery {
+    pub func: AggFunc,
+    /// Field to aggregate. Optional for `count` (counts documents).
+    pub field: Option<String>,
+    pub group_by: Option<String>,
+    pub filter: Option<(String, String)>,
+    pub prefix: Option<String>,
+}
+
+impl AggQuery {
+    /// Parses e.g. `count by status` or `sum amount where country=DE`.
+    pub fn parse(src: &str) -> Result<Self> {
+        let mut tokens = src.split_whitespace().peekable();
+        let func = tokens
+            .next()
+            .and_then(AggFunc::parse)
+            .ok_or_else(|| Error::InvalidQuery("expected count|sum|avg|min|max".into()))?;
+
+        let mut field = None;
+        if let Some(&tok) = tokens.peek() && !matches!(tok, "by" | "where" | "prefix") {
+            field = Some(tok.to_string());
+            tokens.next();
+        }
+        if func != AggFunc::Count && field.is_none() {
+            return Err(Error::InvalidQuery(format!("{:?} needs a field", func).to_lowercase()));
+        }
+
+        let mut query = AggQuery { func, field, group_by: None, filter: None, prefix: None };
+        while let Some(tok) = tokens.next() {
+            let arg = tokens
+                .next()
+                .ok_or_else(|| Error::InvalidQuery(format!("missing argument after '{}'", tok)))?;
+            match tok {
+                "by" => {
+                    query.group_by = Some(arg.to_string());
+                }
+                "where" => {
+                    let (f, v) = arg
+                        .split_once('=')
+                        .ok_or_else(|| Error::InvalidQuery("where expects field=value".into()))?;
+                    query.filter = Some((f.to_string(), v.to_string()));
+                }
+                "prefix" => {
+                    query.prefix = Some(arg.to_string());
+                }
+                other => {
+                    return Err(Error::InvalidQuery(format!("unexpected '{}'", other)));
+                }
+            }
+        }
+        Ok(query)
+    }
+}
+
+/// One result row. `group` is `None` when the query has no `by` clause.
+#[derive(Debug, Clone)]
+pub struct AggRow {
+    pub group: Option<String>,
+    /// Aggregated value; `None` if no numeric values were found (avg/min/max).
+    pub value: Option<f64>,
+    /// Number of documents that contributed to this row.
+    pub count: usize,
+}
+
+#[derive(Default)]
+struct Accumulator {
+    count: usize,
+    sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl Accumulator {
+    fn add(&mut self, x: f64) {
+        self.count += 1;
+        self.sum += x;
+        self.min = Some(self.min.map_or(x, |m| m.min(x)));
+        self.max = Some(self.max.map_or(x, |m| m.max(x)));
+    }
+
+    fn finish(&self, func: AggFunc) -> Option<f64> {
+        match func {
+            AggFunc::Count => Some(self.count as f64),
+            AggFunc::Sum => Some(self.sum),
+            AggFunc::Avg if self.count > 0 => Some(self.sum / (self.count as f64)),
+            AggFunc::Avg => None,
+            AggFunc::Min => self.min,
+            AggFunc::Max => self.max,
+        }
+    }
+}
+
+/// Folds `(group, raw field value)` pairs into result rows.
+/// For `count` the raw value is ignored; for the other functions
+/// non-numeric values are skipped.
+pub fn fold<'a, I>(func: AggFunc, grouped: bool, items: I) -> Vec<AggRow>
+    where I: IntoIterator<Item = (Option<&'a str>, Option<&'a str>)>
+{
+    let mut groups: BTreeMap<Option<String>, Accumulator> = BTreeMap::new();
+    if !grouped {
+        groups.insert(None, Accumulator::default());
+    }
+    for (group, raw) in items {
+        let acc = groups.entry(group.map(|g| g.to_string())).or_default();
+        match func {
+            AggFunc::Count => acc.add(0.0),
+            _ => {
+                if let Some(x) = raw.and_then(|r| r.parse::<f64>().ok()) {
+                    acc.add(x);
+                }
+            }
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(group, acc)| AggRow { group, value: acc.finish(func), count: acc.count })
+        .collect()
+}
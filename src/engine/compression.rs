@@ -0,0 +1,90 @@
+use std::io::Read;
+use serde::{ Deserialize, Serialize };
+use crate::types::{ Error, Result };
+use crate::serialization::codec::ValueCodec;
+
+// Default size, in bytes of the serialized value, from which values are compressed
+pub const DEFAULT_THRESHOLD: usize = 256;
+// zstd level; favours speed, like the rest of the write path
+const ZSTD_LEVEL: i32 = 3;
+// Largest value decompressed on read, so a corrupt length or zstd frame
+// can't make a read allocate gigabytes. Larger values are stored uncompressed.
+const MAX_DECODED_LEN: usize = 256 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    Lz4,
+    Zstd,
+}
+
+impl Algorithm {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "lz4" => Some(Algorithm::Lz4),
+            "zstd" => Some(Algorithm::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Algorithm::Lz4 => "lz4",
+            Algorithm::Zstd => "zstd",
+        }
+    }
+}
+
+/// How new values are compressed, persisted in the manifest. This is the
+/// compression stage of the engine's codec pipeline; records name the
+/// codecs they went through, so changing it never affects reading older ones.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub algorithm: Algorithm,
+    /// Serialized values shorter than this are stored as they are.
+    pub threshold: usize,
+}
+
+impl ValueCodec for Compression {
+    fn id(&self) -> &str {
+        self.algorithm.as_str()
+    }
+
+    /// Compresses `data` if it is at least the threshold and compressing shrinks it.
+    fn encode(&self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        if data.len() < self.threshold || data.len() > MAX_DECODED_LEN {
+            return Ok(None);
+        }
+        let compressed = match self.algorithm {
+            Algorithm::Lz4 => lz4_flex::block::compress_prepend_size(data),
+            Algorithm::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)?,
+        };
+        if compressed.len() >= data.len() {
+            return Ok(None);
+        }
+        Ok(Some(compressed))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self.algorithm {
+            Algorithm::Lz4 => {
+                let (len, rest) = data.split_first_chunk::<4>().ok_or(Error::InvalidRecord)?;
+                let raw_len = u32::from_le_bytes(*len) as usize;
+                if raw_len > MAX_DECODED_LEN {
+                    return Err(Error::InvalidRecord);
+                }
+                lz4_flex::block::decompress(rest, raw_len).map_err(|_| Error::InvalidRecord)
+            }
+            Algorithm::Zstd => {
+                let mut raw = Vec::new();
+                zstd::stream::read::Decoder::new(data)
+                    .and_then(|d| d.take(MAX_DECODED_LEN as u64 + 1).read_to_end(&mut raw))
+                    .map_err(|_| Error::InvalidRecord)?;
+                if raw.len() > MAX_DECODED_LEN {
+                    return Err(Error::InvalidRecord);
+                }
+                Ok(raw)
+            }
+        }
+    }
+}
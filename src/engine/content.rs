@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use serde::{ Deserialize, Serialize };
+
+/// What the values under a prefix must be.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentType {
+    /// Anything the serializer accepts.
+    Any,
+    /// Any JSON value.
+    Json,
+    /// A JSON object.
+    Object,
+    /// A JSON array.
+    Array,
+}
+
+impl ContentType {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "any" => Some(ContentType::Any),
+            "json" => Some(ContentType::Json),
+            "object" => Some(ContentType::Object),
+            "array" => Some(ContentType::Array),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentType::Any => "any",
+            ContentType::Json => "json",
+            ContentType::Object => "object",
+            ContentType::Array => "array",
+        }
+    }
+
+    pub fn accepts(self, value: &str) -> bool {
+        if self == ContentType::Any {
+            return true;
+        }
+        match serde_json::from_str::<serde_json::Value>(value) {
+            Ok(v) =>
+                match self {
+                    ContentType::Object => v.is_object(),
+                    ContentType::Array => v.is_array(),
+                    _ => true,
+                }
+            Err(_) => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PrefixRule {
+    /// `Serializer::id` values under the prefix are written with.
+    pub serializer: String,
+    pub content_type: ContentType,
+}
+
+/// Serializers and content types by key prefix, persisted to `<db>.prefixes`.
+/// Keys under no prefix use the database's serializer and accept anything.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PrefixRegistry {
+    rules: BTreeMap<String, PrefixRule>,
+}
+
+impl PrefixRegistry {
+    /// Adds or replaces the rule for `prefix`.
+    pub fn set(&mut self, prefix: &str, rule: PrefixRule) {
+        self.rules.insert(prefix.to_string(), rule);
+    }
+
+    pub fn remove(&mut self, prefix: &str) -> bool {
+        self.rules.remove(prefix).is_some()
+    }
+
+    /// The rule with the longest prefix of `key`, if any.
+    pub fn rule_for(&self, key: &str) -> Option<(&str, &PrefixRule)> {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, rule)| (prefix.as_str(), rule))
+    }
+
+    pub fn definitions(&self) -> Vec<(String, PrefixRule)> {
+        self.rules
+            .iter()
+            .map(|(prefix, rule)| (prefix.clone(), rule.clone()))
+            .collect()
+    }
+}
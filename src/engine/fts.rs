@@ -0,0 +1,358 @@
+use std::collections::{ BTreeMap, HashMap };
+use serde::{ Serialize, Deserialize };
+use crate::types::{ Error, Result };
+
+// BM25 tuning
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+// Position gap between fields so phrases can't match across them
+const FIELD_GAP: u32 = 100;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+/// Declaration of a full-text index: which JSON string fields of which keys to index.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FtsIndexDef {
+    pub fields: Vec<String>,
+    pub prefix: Option<String>,
+    pub stem: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DocEntry {
+    len: u32,
+    terms: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FtsIndex {
+    def: FtsIndexDef,
+    // term => key => positions
+    postings: BTreeMap<String, HashMap<String, Vec<u32>>>,
+    // key => token count and distinct terms (for removal)
+    docs: HashMap<String, DocEntry>,
+    total_len: u64,
+}
+
+/// A ranked search result.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub key: String,
+    pub score: f64,
+}
+
+/// Named inverted indexes over JSON string fields, ranked with BM25.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FullTextIndex {
+    indexes: HashMap<String, FtsIndex>,
+}
+
+enum Clause {
+    Term(String),
+    Prefix(String),
+    Phrase(Vec<(u32, String)>),
+}
+
+/// Lowercases and splits text into (position, token), dropping stop words
+/// but keeping their positions so phrases line up.
+fn tokenize(text: &str, stem: bool) -> Vec<(u32, String)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .enumerate()
+        .filter_map(|(pos, t)| {
+            let t = t.to_lowercase();
+            if STOP_WORDS.contains(&t.as_str()) {
+                return None;
+            }
+            let t = if stem { stem_word(&t) } else { t };
+            Some((pos as u32, t))
+        })
+        .collect()
+}
+
+/// Light English suffix stripping (plural, -ing, -ed, -ly).
+fn stem_word(word: &str) -> String {
+    let has_vowel = |s: &str| s.chars().any(|c| "aeiouy".contains(c));
+    if word.len() <= 3 {
+        return word.to_string();
+    }
+    if let Some(s) = word.strip_suffix("sses") {
+        return format!("{}ss", s);
+    }
+    if let Some(s) = word.strip_suffix("ies") {
+        return format!("{}y", s);
+    }
+    for suffix in ["ing", "ed", "ly"] {
+        if let Some(s) = word.strip_suffix(suffix) && s.len() >= 3 && has_vowel(s) {
+            // running -> run, stopped -> stop
+            let mut tail = s.chars().rev();
+            if let (Some(last), Some(prev)) = (tail.next(), tail.next())
+                && last == prev
+                && !"aeioulsz".contains(last)
+            {
+                return s[..s.len() - last.len_utf8()].to_string();
+            }
+            return s.to_string();
+        }
+    }
+    if word.ends_with('s') && !word.ends_with("ss") && !word.ends_with("us") {
+        return word[..word.len() - 1].to_string();
+    }
+    word.to_string()
+}
+
+/// Parses a query: bare words, `prefix*` terms and `"quoted phrases"`.
+/// All clauses must match.
+fn parse_query(query: &str, stem: bool) -> Vec<Clause> {
+    let mut clauses = Vec::new();
+    for (i, part) in query.split('"').enumerate() {
+        if i % 2 == 1 {
+            let tokens = tokenize(part, stem);
+            match tokens.len() {
+                0 => {}
+                1 => clauses.push(Clause::Term(tokens[0].1.clone())),
+                _ => clauses.push(Clause::Phrase(tokens)),
+            }
+            continue;
+        }
+        for word in part.split_whitespace() {
+            if let Some(p) = word.strip_suffix('*') {
+                let p = p.to_lowercase();
+                if !p.is_empty() {
+                    clauses.push(Clause::Prefix(p));
+                }
+                continue;
+            }
+            for (_, t) in tokenize(word, stem) {
+                clauses.push(Clause::Term(t));
+            }
+        }
+    }
+    clauses
+}
+
+impl FtsIndex {
+    fn applies_to(&self, key: &str) -> bool {
+        self.def.prefix.as_deref().is_none_or(|p| key.starts_with(p))
+    }
+
+    fn remove_doc(&mut self, key: &str) {
+        if let Some(doc) = self.docs.remove(key) {
+            self.total_len -= doc.len as u64;
+            for term in doc.terms {
+                if let Some(keys) = self.postings.get_mut(&term) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        self.postings.remove(&term);
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_doc(&mut self, key: &str, json: &str) {
+        let Ok(serde_json::Value::Object(map)) = serde_json::from_str::<serde_json::Value>(json) else {
+            return;
+        };
+        let mut positions: HashMap<String, Vec<u32>> = HashMap::new();
+        let mut offset = 0u32;
+        let mut len = 0u32;
+        for field in &self.def.fields {
+            let Some(serde_json::Value::String(text)) = map.get(field) else {
+                continue;
+            };
+            let tokens = tokenize(text, self.def.stem);
+            len += tokens.len() as u32;
+            let mut last = 0;
+            for (pos, term) in tokens {
+                positions.entry(term).or_default().push(offset + pos);
+                last = pos;
+            }
+            offset += last + FIELD_GAP;
+        }
+        if positions.is_empty() {
+            return;
+        }
+        let mut terms = Vec::with_capacity(positions.len());
+        for (term, pos) in positions {
+            self.postings.entry(term.clone()).or_default().insert(key.to_string(), pos);
+            terms.push(term);
+        }
+        self.total_len += len as u64;
+        self.docs.insert(key.to_string(), DocEntry { len, terms });
+    }
+
+    fn bm25(&self, term: &str, key: &str) -> f64 {
+        let Some(keys) = self.postings.get(term) else {
+            return 0.0;
+        };
+        let Some(tf) = keys.get(key).map(|p| p.len() as f64) else {
+            return 0.0;
+        };
+        let n = self.docs.len() as f64;
+        let df = keys.len() as f64;
+        let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+        let avg_len = (self.total_len as f64) / n.max(1.0);
+        let len = self.docs.get(key).map(|d| d.len as f64).unwrap_or(0.0);
+        (idf * tf * (K1 + 1.0)) / (tf + K1 * (1.0 - B + (B * len) / avg_len.max(1.0)))
+    }
+
+    fn phrase_matches(&self, key: &str, tokens: &[(u32, String)]) -> bool {
+        let lists: Option<Vec<&Vec<u32>>> = tokens
+            .iter()
+            .map(|(_, t)| self.postings.get(t).and_then(|keys| keys.get(key)))
+            .collect();
+        let Some(lists) = lists else {
+            return false;
+        };
+        let base = tokens[0].0;
+        lists[0].iter().any(|&start| {
+            tokens
+                .iter()
+                .zip(&lists)
+                .skip(1)
+                .all(|((qpos, _), positions)| positions.contains(&(start + qpos - base)))
+        })
+    }
+
+    /// Scores every document matching all clauses.
+    fn search(&self, clauses: &[Clause]) -> Vec<SearchHit> {
+        let mut scores: Option<HashMap<String, f64>> = None;
+        for clause in clauses {
+            let mut matched: HashMap<String, f64> = HashMap::new();
+            match clause {
+                Clause::Term(t) => {
+                    for key in self.postings.get(t).into_iter().flat_map(|m| m.keys()) {
+                        matched.insert(key.clone(), self.bm25(t, key));
+                    }
+                }
+                Clause::Prefix(p) => {
+                    for (term, keys) in self.postings.range(p.clone()..) {
+                        if !term.starts_with(p.as_str()) {
+                            break;
+                        }
+                        for key in keys.keys() {
+                            *matched.entry(key.clone()).or_default() += self.bm25(term, key);
+                        }
+                    }
+                }
+                Clause::Phrase(tokens) => {
+                    let first = &tokens[0].1;
+                    for key in self.postings.get(first).into_iter().flat_map(|m| m.keys()) {
+                        if self.phrase_matches(key, tokens) {
+                            let score = tokens
+                                .iter()
+                                .map(|(_, t)| self.bm25(t, key))
+                                .sum();
+                            matched.insert(key.clone(), score);
+                        }
+                    }
+                }
+            }
+            scores = Some(match scores {
+                None => matched,
+                Some(prev) =>
+                    prev
+                        .into_iter()
+                        .filter_map(|(k, s)| matched.get(&k).map(|m| (k, s + m)))
+                        .collect(),
+            });
+        }
+        scores
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, score)| SearchHit { key, score })
+            .collect()
+    }
+}
+
+impl FullTextIndex {
+    pub fn new() -> Self {
+        Self { indexes: HashMap::new() }
+    }
+
+    /// Declares a new, empty index. The engine feeds it the existing documents.
+    pub fn create(&mut self, name: &str, def: FtsIndexDef) -> Result<()> {
+        if self.indexes.contains_key(name) {
+            return Err(Error::InvalidQuery(format!("full-text index '{}' already exists", name)));
+        }
+        let index = FtsIndex {
+            def,
+            postings: BTreeMap::new(),
+            docs: HashMap::new(),
+            total_len: 0,
+        };
+        self.indexes.insert(name.to_string(), index);
+        Ok(())
+    }
+
+    pub fn drop_index(&mut self, name: &str) -> bool {
+        self.indexes.remove(name).is_some()
+    }
+
+    pub fn definitions(&self) -> Vec<(String, FtsIndexDef)> {
+        let mut out: Vec<_> = self.indexes
+            .iter()
+            .map(|(name, idx)| (name.clone(), idx.def.clone()))
+            .collect();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
+    /// True if any index covers `key`.
+    pub fn covers(&self, key: &str) -> bool {
+        self.indexes.values().any(|idx| idx.applies_to(key))
+    }
+
+    /// Re-indexes `key` in every applicable index (None removes it).
+    pub fn update(&mut self, key: &str, new_json: Option<&str>) {
+        for idx in self.indexes.values_mut() {
+            if !idx.applies_to(key) {
+                continue;
+            }
+            idx.remove_doc(key);
+            if let Some(json) = new_json {
+                idx.add_doc(key, json);
+            }
+        }
+    }
+
+    /// Indexes a document into one index only (used when building a new index).
+    pub fn add_to(&mut self, name: &str, key: &str, json: &str) {
+        if let Some(idx) = self.indexes.get_mut(name) && idx.applies_to(key) {
+            idx.remove_doc(key);
+            idx.add_doc(key, json);
+        }
+    }
+
+    /// Drops documents whose key no longer exists.
+    pub fn retain<F: Fn(&str) -> bool>(&mut self, keep: F) {
+        for idx in self.indexes.values_mut() {
+            let stale: Vec<String> = idx.docs
+                .keys()
+                .filter(|k| !keep(k))
+                .cloned()
+                .collect();
+            for key in stale {
+                idx.remove_doc(&key);
+            }
+        }
+    }
+
+    /// Runs a query against index `name`, best matches first.
+    pub fn search(&self, name: &str, query: &str) -> Result<Vec<SearchHit>> {
+        let idx = self.indexes.get(name).ok_or(Error::NotFound)?;
+        let clauses = parse_query(query, idx.def.stem);
+        if clauses.is_empty() {
+            return Err(Error::InvalidQuery("empty search query".into()));
+        }
+        let mut hits = idx.search(&clauses);
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.key.cmp(&b.key)));
+        Ok(hits)
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use serde::{ Serialize, Deserialize };
+use crate::types::{ Error, Result };
+
+// Interleaved geohash precision: 26 bits each for lon and lat (52 bits total)
+const STEP_MAX: u32 = 26;
+const EARTH_RADIUS_M: f64 = 6_372_797.560856;
+const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Distance unit for geo commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeoUnit {
+    M,
+    Km,
+    Mi,
+    Ft,
+}
+
+impl GeoUnit {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "m" => Some(GeoUnit::M),
+            "km" => Some(GeoUnit::Km),
+            "mi" => Some(GeoUnit::Mi),
+            "ft" => Some(GeoUnit::Ft),
+            _ => None,
+        }
+    }
+
+    /// Converts a distance in meters to this unit.
+    pub fn convert(self, meters: f64) -> f64 {
+        meters / self.meters()
+    }
+
+    fn meters(self) -> f64 {
+        match self {
+            GeoUnit::M => 1.0,
+            GeoUnit::Km => 1000.0,
+            GeoUnit::Mi => 1609.34,
+            GeoUnit::Ft => 0.3048,
+        }
+    }
+}
+
+/// Search area around a centre point, in the query's unit.
+#[derive(Debug, Clone, Copy)]
+pub enum GeoShape {
+    Radius(f64),
+    Box {
+        width: f64,
+        height: f64,
+    },
+}
+
+/// Declaration of a geo index over latitude/longitude fields of JSON documents.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeoIndexDef {
+    pub lat_field: String,
+    pub lon_field: String,
+    pub prefix: Option<String>,
+}
+
+/// A geosearch result; `distance` is in the query's unit.
+#[derive(Debug, Clone)]
+pub struct GeoHit {
+    pub key: String,
+    pub distance: f64,
+    pub lon: f64,
+    pub lat: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GeoIndex {
+    def: GeoIndexDef,
+    // key => (lon, lat)
+    points: HashMap<String, (f64, f64)>,
+    // 52-bit geohash => keys, ordered so a cell is a contiguous range
+    cells: BTreeMap<u64, HashSet<String>>,
+}
+
+/// Great-circle distance in meters.
+pub fn haversine(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2 - lon1).to_radians() / 2.0).sin();
+    2.0 * EARTH_RADIUS_M * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+/// Interleaves `step` bits of longitude and latitude (longitude first).
+fn encode(lon: f64, lat: f64, step: u32) -> u64 {
+    let (mut lon_min, mut lon_max) = (-180.0, 180.0);
+    let (mut lat_min, mut lat_max) = (-90.0, 90.0);
+    let mut bits = 0u64;
+    for _ in 0..step {
+        let mid = (lon_min + lon_max) / 2.0;
+        bits <<= 1;
+        if lon >= mid {
+            bits |= 1;
+            lon_min = mid;
+        } else {
+            lon_max = mid;
+        }
+        let mid = (lat_min + lat_max) / 2.0;
+        bits <<= 1;
+        if lat >= mid {
+            bits |= 1;
+            lat_min = mid;
+        } else {
+            lat_max = mid;
+        }
+    }
+    bits
+}
+
+/// Standard 11-character base32 geohash.
+pub fn geohash_string(lon: f64, lat: f64) -> String {
+    // 55 bits for 11 characters; the extra 3 bits are zero padding
+    let bits = encode(lon, lat, STEP_MAX) << 3;
+    (0..11)
+        .map(|i| BASE32[((bits >> (50 - i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+/// Coarsest precision whose cells are still at least `radius_m` across at `lat`.
+fn estimate_step(radius_m: f64, lat: f64) -> u32 {
+    let mut step = STEP_MAX;
+    while step > 1 {
+        let cells = (1u64 << step) as f64;
+        let height = (180.0 / cells) * 111_320.0;
+        let width = (360.0 / cells) * 111_320.0 * lat.to_radians().cos().abs().max(0.01);
+        if height >= radius_m && width >= radius_m {
+            break;
+        }
+        step -= 1;
+    }
+    step
+}
+
+impl GeoIndex {
+    fn applies_to(&self, key: &str) -> bool {
+        self.def.prefix.as_deref().is_none_or(|p| key.starts_with(p))
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((lon, lat)) = self.points.remove(key) {
+            let hash = encode(lon, lat, STEP_MAX);
+            if let Some(set) = self.cells.get_mut(&hash) {
+                set.remove(key);
+                if set.is_empty() {
+                    self.cells.remove(&hash);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, key: &str, lon: f64, lat: f64) {
+        self.remove(key);
+        self.points.insert(key.to_string(), (lon, lat));
+        self.cells.entry(encode(lon, lat, STEP_MAX)).or_default().insert(key.to_string());
+    }
+
+    fn extract(&self, json: &str) -> Option<(f64, f64)> {
+        let value: serde_json::Value = serde_json::from_str(json).ok()?;
+        // Accept numbers or numeric strings (hash fields are stored as strings)
+        let num = |field: &str| {
+            let v = value.get(field)?;
+            v.as_f64().or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+        };
+        let (lon, lat) = (num(&self.def.lon_field)?, num(&self.def.lat_field)?);
+        valid_coords(lon, lat).then_some((lon, lat))
+    }
+
+    /// Keys inside the 3x3 block of cells (at a precision matched to `radius_m`)
+    /// around the centre; callers filter these by exact distance.
+    fn candidates(&self, lon: f64, lat: f64, radius_m: f64) -> HashSet<&String> {
+        let step = estimate_step(radius_m, lat);
+        let cells = (1u64 << step) as f64;
+        let (dlon, dlat) = (360.0 / cells, 180.0 / cells);
+        let shift = 2 * (STEP_MAX - step);
+        let mut ranges = HashSet::new();
+        for i in -1..=1 {
+            for j in -1..=1 {
+                let nlat = lat + (j as f64) * dlat;
+                if !(-90.0..=90.0).contains(&nlat) {
+                    continue;
+                }
+                let mut nlon = lon + (i as f64) * dlon;
+                if nlon > 180.0 {
+                    nlon -= 360.0;
+                } else if nlon < -180.0 {
+                    nlon += 360.0;
+                }
+                ranges.insert(encode(nlon, nlat, step));
+            }
+        }
+        let mut out = HashSet::new();
+        for cell in ranges {
+            let start = cell << shift;
+            let end = (cell + 1) << shift;
+            for (_, keys) in self.cells.range(start..end) {
+                out.extend(keys.iter());
+            }
+        }
+        out
+    }
+}
+
+pub fn valid_coords(lon: f64, lat: f64) -> bool {
+    (-180.0..=180.0).contains(&lon) && (-90.0..=90.0).contains(&lat)
+}
+
+/// Named geo indexes over latitude/longitude fields of JSON documents.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct GeoIndexes {
+    indexes: HashMap<String, GeoIndex>,
+}
+
+impl GeoIndexes {
+    pub fn create(&mut self, name: &str, def: GeoIndexDef) -> Result<()> {
+        if self.indexes.contains_key(name) {
+            return Err(Error::InvalidQuery(format!("geo index '{}' already exists", name)));
+        }
+        let index = GeoIndex { def, points: HashMap::new(), cells: BTreeMap::new() };
+        self.indexes.insert(name.to_string(), index);
+        Ok(())
+    }
+
+    pub fn drop_index(&mut self, name: &str) -> bool {
+        self.indexes.remove(name).is_some()
+    }
+
+    pub fn definition(&self, name: &str) -> Option<&GeoIndexDef> {
+        self.indexes.get(name).map(|idx| &idx.def)
+    }
+
+    pub fn definitions(&self) -> Vec<(String, GeoIndexDef, usize)> {
+        let mut out: Vec<_> = self.indexes
+            .iter()
+            .map(|(name, idx)| (name.clone(), idx.def.clone(), idx.points.len()))
+            .collect();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
+    pub fn covers(&self, key: &str) -> bool {
+        self.indexes.values().any(|idx| idx.applies_to(key))
+    }
+
+    /// Re-indexes `key` in every applicable index (None removes it).
+    pub fn update(&mut self, key: &str, new_json: Option<&str>) {
+        for idx in self.indexes.values_mut() {
+            if !idx.applies_to(key) {
+                continue;
+            }
+            match new_json.and_then(|j| idx.extract(j)) {
+                Some((lon, lat)) => idx.insert(key, lon, lat),
+                None => idx.remove(key),
+            }
+        }
+    }
+
+    /// Indexes a document into one index only (used when building a new index).
+    pub fn add_to(&mut self, name: &str, key: &str, json: &str) {
+        if let Some(idx) = self.indexes.get_mut(name)
+            && idx.applies_to(key)
+            && let Some((lon, lat)) = idx.extract(json)
+        {
+            idx.insert(key, lon, lat);
+        }
+    }
+
+    pub fn retain<F: Fn(&str) -> bool>(&mut self, keep: F) {
+        for idx in self.indexes.values_mut() {
+            let stale: Vec<String> = idx.points
+                .keys()
+                .filter(|k| !keep(k))
+                .cloned()
+                .collect();
+            for key in stale {
+                idx.remove(&key);
+            }
+        }
+    }
+
+    /// Indexed (lon, lat) of `key`.
+    pub fn position(&self, name: &str, key: &str) -> Result<Option<(f64, f64)>> {
+        let idx = self.indexes.get(name).ok_or(Error::NotFound)?;
+        Ok(idx.points.get(key).copied())
+    }
+
+    /// Members within `shape` of (`lon`, `lat`), nearest first.
+    pub fn search(
+        &self,
+        name: &str,
+        lon: f64,
+        lat: f64,
+        shape: GeoShape,
+        unit: GeoUnit
+    ) -> Result<Vec<GeoHit>> {
+        let idx = self.indexes.get(name).ok_or(Error::NotFound)?;
+        if !valid_coords(lon, lat) {
+            return Err(Error::InvalidQuery(format!("invalid coordinates {},{}", lon, lat)));
+        }
+        let radius_m = match shape {
+            GeoShape::Radius(r) => r * unit.meters(),
+            GeoShape::Box { width, height } => {
+                (width * width + height * height).sqrt() * unit.meters() / 2.0
+            }
+        };
+        let mut hits = Vec::new();
+        for key in idx.candidates(lon, lat, radius_m) {
+            let (plon, plat) = idx.points[key];
+            let dist = haversine(lon, lat, plon, plat);
+            let inside = match shape {
+                GeoShape::Radius(_) => dist <= radius_m,
+                GeoShape::Box { width, height } => {
+                    // Compare north-south and east-west offsets separately
+                    let dy = haversine(lon, lat, lon, plat);
+                    let dx = haversine(lon, plat, plon, plat);
+                    dy <= (height * unit.meters()) / 2.0 && dx <= (width * unit.meters()) / 2.0
+                }
+            };
+            if inside {
+                hits.push(GeoHit { key: key.clone(), distance: unit.convert(dist), lon: plon, lat: plat });
+            }
+        }
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance).then_with(|| a.key.cmp(&b.key)));
+        Ok(hits)
+    }
+}
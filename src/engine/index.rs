@@ -1,15 +1,38 @@
 use std::collections::{ HashMap, HashSet };
 use serde::{Serialize, Deserialize};
+use crate::types::{ Error, Result };
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct SecondaryIndex {
     // field => value => set of keys
     index: HashMap<String, HashMap<String, HashSet<String>>>,
+    // unique field => key prefix the constraint applies to (None = all keys)
+    #[serde(default)]
+    unique: HashMap<String, Option<String>>,
+}
+
+/// Top-level fields of a JSON object as (field, indexed value) pairs.
+/// Strings are indexed raw, everything else by its JSON text.
+pub fn indexed_values(json: &str) -> Vec<(String, String)> {
+    match serde_json::from_str::<serde_json::Value>(json) {
+        Ok(serde_json::Value::Object(map)) =>
+            map
+                .into_iter()
+                .map(|(field, v)| {
+                    let strval = match v {
+                        serde_json::Value::String(s) => s,
+                        _ => v.to_string(),
+                    };
+                    (field, strval)
+                })
+                .collect(),
+        _ => Vec::new(),
+    }
 }
 
 impl SecondaryIndex {
     pub fn new() -> Self {
-        Self { index: HashMap::new() }
+        Self { index: HashMap::new(), unique: HashMap::new() }
     }
 
     pub fn clear(&mut self) {
@@ -20,51 +43,46 @@ impl SecondaryIndex {
     pub fn update(&mut self, key: &str, old_json: Option<&str>, new_json: Option<&str>) {
         // Remove all previous values for this key if old_json is given
         if let Some(s) = old_json {
-            if let Ok(val) = serde_json::from_str::<serde_json::Value>(s) {
-                if let Some(map) = val.as_object() {
-                    for (field, v) in map {
-                        let strval: String = match v {
-                            serde_json::Value::String(s) => s.clone(),
-                            _ => v.to_string(),
-                        };
-                        if let Some(valmap) = self.index.get_mut(field) {
-                            if let Some(set) = valmap.get_mut(&strval) {
-                                set.remove(key);
-                                // Clean up empty sets and maps
-                                if set.is_empty() {
-                                    valmap.remove(&strval);
-                                }
-                            }
-                            if valmap.is_empty() {
-                                self.index.remove(field);
-                            }
+            for (field, strval) in indexed_values(s) {
+                if let Some(valmap) = self.index.get_mut(&field) {
+                    if let Some(set) = valmap.get_mut(&strval) {
+                        set.remove(key);
+                        // Clean up empty sets and maps
+                        if set.is_empty() {
+                            valmap.remove(&strval);
                         }
                     }
+                    if valmap.is_empty() {
+                        self.index.remove(&field);
+                    }
                 }
             }
         }
 
         // Add new values for this key if new_json is given
         if let Some(s) = new_json {
-            if let Ok(val) = serde_json::from_str::<serde_json::Value>(s) {
-                if let Some(map) = val.as_object() {
-                    for (field, v) in map {
-                        let strval: String = match v {
-                            serde_json::Value::String(s) => s.clone(),
-                            _ => v.to_string(),
-                        };
-                        self.index
-                            .entry(field.clone())
-                            .or_default()
-                            .entry(strval)
-                            .or_default()
-                            .insert(key.to_string());
-                    }
-                }
+            for (field, strval) in indexed_values(s) {
+                self.index
+                    .entry(field)
+                    .or_default()
+                    .entry(strval)
+                    .or_default()
+                    .insert(key.to_string());
             }
         }
     }
 
+    /// Drops every key for which `keep` returns false.
+    pub fn retain<F: Fn(&str) -> bool>(&mut self, keep: F) {
+        for valmap in self.index.values_mut() {
+            for set in valmap.values_mut() {
+                set.retain(|k| keep(k));
+            }
+            valmap.retain(|_, set| !set.is_empty());
+        }
+        self.index.retain(|_, valmap| !valmap.is_empty());
+    }
+
     /// Called on delete.
     pub fn remove(&mut self, key: &str, old_json: Option<&str>) {
         self.update(key, old_json, None);
@@ -77,4 +95,77 @@ impl SecondaryIndex {
             .map(|set| set.iter().cloned().collect())
             .unwrap_or_default()
     }
+
+    /// Declares `field` unique among keys starting with `prefix`.
+    /// Fails with the conflicting keys if existing live documents already violate it.
+    pub fn add_unique<F>(&mut self, field: &str, prefix: Option<&str>, is_live: F) -> Result<()>
+        where F: Fn(&str) -> bool
+    {
+        let mut conflicts = Vec::new();
+        if let Some(valmap) = self.index.get(field) {
+            for (value, keys) in valmap {
+                let mut holders: Vec<String> = keys
+                    .iter()
+                    .filter(|k| prefix.is_none_or(|p| k.starts_with(p)) && is_live(k))
+                    .cloned()
+                    .collect();
+                if holders.len() > 1 {
+                    holders.sort();
+                    conflicts.push((value.clone(), holders));
+                }
+            }
+        }
+        if !conflicts.is_empty() {
+            conflicts.sort();
+            return Err(Error::UniqueViolation { field: field.to_string(), conflicts });
+        }
+        self.unique.insert(
+            field.to_string(),
+            prefix.map(|p| p.to_string())
+        );
+        Ok(())
+    }
+
+    /// Drops a unique constraint. Returns false if the field wasn't unique.
+    pub fn remove_unique(&mut self, field: &str) -> bool {
+        self.unique.remove(field).is_some()
+    }
+
+    /// Whether any unique constraint is defined.
+    pub fn has_unique(&self) -> bool {
+        !self.unique.is_empty()
+    }
+
+    /// Unique constraints (field, prefix) that apply to `key`.
+    pub fn unique_fields_for(&self, key: &str) -> Vec<(String, Option<String>)> {
+        self.unique
+            .iter()
+            .filter(|(_, prefix)| prefix.as_deref().is_none_or(|p| key.starts_with(p)))
+            .map(|(field, prefix)| (field.clone(), prefix.clone()))
+            .collect()
+    }
+
+    /// All unique constraints as (field, prefix).
+    pub fn unique_constraints(&self) -> Vec<(String, Option<String>)> {
+        let mut out: Vec<_> = self.unique
+            .iter()
+            .map(|(f, p)| (f.clone(), p.clone()))
+            .collect();
+        out.sort();
+        out
+    }
+
+    /// Inverts the postings of one field: key => indexed value.
+    /// Lets aggregations read field values without deserializing documents.
+    pub fn field_values(&self, field: &str) -> HashMap<String, String> {
+        let mut out = HashMap::new();
+        if let Some(valmap) = self.index.get(field) {
+            for (value, keys) in valmap {
+                for key in keys {
+                    out.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        out
+    }
 }
@@ -1,15 +1,34 @@
 use crate::storage::file as storage;
+use crate::storage::file::IndexEntry;
+use crate::storage::crypto::{ self, Cipher, EncryptionInfo, EncryptionKey };
 use crate::engine::wal::WAL;
 use crate::types::{ Result, Error };
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::ops::Bound;
 use std::fs::{ self, OpenOptions };
 use std::io::{ self, Write };
-use crate::serialization::Serializer;
+use crate::serialization::{ self, Serializer };
 use base64::{ engine::general_purpose, Engine };
 use crate::engine::batch::BatchOp;
 use std::time::{ SystemTime, UNIX_EPOCH };
-use crate::types::ScriptMeta;
-use crate::engine::index::SecondaryIndex;
+use crate::types::{ LibraryMeta, LibraryVersion, ScriptLimits, ScriptMeta };
+use crate::engine::index::{ self, SecondaryIndex };
+use crate::engine::agg::{ self, AggQuery, AggRow };
+use crate::engine::fts::{ FullTextIndex, FtsIndexDef, SearchHit };
+use crate::engine::vector::{ VectorIndexes, VectorIndexDef, VectorHit };
+use crate::engine::geo::{ self, GeoIndexes, GeoIndexDef, GeoHit, GeoShape, GeoUnit };
+use crate::engine::txn::{ ScriptTxn, TxnWrite };
+use crate::engine::sandbox;
+use crate::engine::manifest::{ self, Manifest };
+use crate::engine::content::{ ContentType, PrefixRegistry, PrefixRule };
+use crate::engine::compression::{ Algorithm, Compression };
+use crate::serialization::codec::{ self, CHAIN_SEPARATOR, ValueCodec };
+use std::sync::Arc;
+use crate::engine::lua_api;
+use crate::engine::trigger::{ TriggerCall, TriggerDef, TriggerEvent, Triggers };
+use crate::engine::schedule::{ RunRecord, Schedule, ScheduleSpec, Schedules };
+use crate::engine::script_stats::{ ScriptStats, SlowEntry, SlowLog };
 use lru::LruCache;
 
 // For Lua scripting support
@@ -17,14 +36,28 @@ use mlua::{ Lua, Function, Value };
 use sha1::{ Sha1, Digest };
 use hex;
 use serde_json;
+use serde::{ Serialize, de::DeserializeOwned };
 
 pub struct SlackbaseEngine {
     db_path: String,
-    index: HashMap<String, (u64, usize)>,
+    // Key -> (offset, length, expiry) of its latest record, in key order for scans
+    index: BTreeMap<String, IndexEntry>,
     pub sec_index: SecondaryIndex,
+    pub fts: FullTextIndex,
+    pub vectors: VectorIndexes,
+    pub geo: GeoIndexes,
+    pub triggers: Triggers,
+    pub schedules: Schedules,
+    pub prefixes: PrefixRegistry,
     wal: WAL,
     write_buffer: Vec<String>,
     serializer: Box<dyn Serializer>,
+    // How new values are compressed, from the manifest
+    compression: Option<Compression>,
+    // Codecs values go through after compression, from the manifest
+    codecs: Vec<Arc<dyn ValueCodec>>,
+    // Seals every record and sidecar, for an encrypted database
+    cipher: Option<Cipher>,
     pub lru: LruCache<String, String>,
 
     pub read_ops: usize,
@@ -32,16 +65,90 @@ pub struct SlackbaseEngine {
     pub hits: usize,
     pub misses: usize,
 
-    lua: Lua,
     pub scripts: HashMap<String, Function<'static>>,
     pub script_meta: HashMap<String, ScriptMeta>, // sha1 → meta
     pub script_names: HashMap<String, String>, // name → sha1
+    pub libraries: HashMap<String, LibraryMeta>, // name → versions
+    library_chunks: HashMap<String, Function<'static>>, // sha1 → compiled library
+    pub script_stats: HashMap<String, ScriptStats>, // sha1 → counters
+    pub slow_log: SlowLog,
+    // Writes of the script currently running, if any
+    txn: Option<ScriptTxn>,
+    // Triggers currently running, to stop runaway recursion
+    trigger_depth: usize,
+    // Set while a committed unit is being applied; triggers don't fire then
+    applying: bool,
+    // Expired keys (with their last value) whose on_expire triggers are due
+    expired: Vec<(String, Option<String>)>,
+    // Compiled scripts borrow the Lua state, so it is boxed (its address must
+    // survive the engine being moved out of `open`) and declared last (dropped
+    // after `scripts`).
+    lua: Box<Lua>,
 }
 
 impl SlackbaseEngine {
     /// Opens the database, recovers from WAL, and loads scripts.
     pub fn open(db_path: &str, serializer: Box<dyn Serializer>) -> Result<Self> {
-        let wal = WAL::open(&format!("{}.wal", db_path))?;
+        finish_swap(db_path)?;
+        Self::open_with_cipher(db_path, serializer, None)
+    }
+
+    /// Opens an encrypted database with `key`, failing with `Error::WrongKey`
+    /// if it isn't the database's. A new database is encrypted with it; an
+    /// existing plaintext one has to be encrypted with `rotate_key` first.
+    pub fn open_encrypted(db_path: &str, serializer: Box<dyn Serializer>, key: &EncryptionKey) -> Result<Self> {
+        finish_swap(db_path)?;
+        let cipher = match Manifest::load(db_path)? {
+            Some(Manifest { encryption: Some(info), .. }) => crypto::unlock(&key.source, &info)?,
+            existing => {
+                if fs::metadata(db_path).is_ok_and(|m| m.len() > 0) {
+                    return Err(
+                        Error::InvalidQuery(
+                            format!("'{}' isn't encrypted; open it without a key and encrypt it with rotate_key", db_path)
+                        )
+                    );
+                }
+                let mut m = match existing {
+                    Some(m) => m,
+                    None => Manifest::new(serializer.id(), unix_now()?),
+                };
+                let (cipher, info) = crypto::setup(key)?;
+                m.encryption = Some(info);
+                m.save(db_path)?;
+                cipher
+            }
+        };
+        Self::open_with_cipher(db_path, serializer, Some(cipher))
+    }
+
+    /// Opens the database with an already unlocked cipher (or none).
+    fn open_with_cipher(db_path: &str, serializer: Box<dyn Serializer>, cipher: Option<Cipher>) -> Result<Self> {
+        // Refuse to read values with a serializer they weren't written with,
+        // or encrypted files with the wrong key
+        let (compression, codec_ids) = match Manifest::load(db_path)? {
+            Some(m) if m.serializer != serializer.id() => {
+                return Err(Error::SerializerMismatch {
+                    stored: m.serializer,
+                    requested: serializer.id().to_string(),
+                });
+            }
+            Some(m) => {
+                check_key(m.encryption.as_ref(), cipher.as_ref(), db_path)?;
+                // Encrypted before the format version marked it; mark it now
+                if m.encryption.is_some() && m.format_version < manifest::FORMAT_VERSION {
+                    m.save(db_path)?;
+                }
+                (m.compression, m.codecs)
+            }
+            None => {
+                check_key(None, cipher.as_ref(), db_path)?;
+                Manifest::new(serializer.id(), unix_now()?).save(db_path)?;
+                (None, Vec::new())
+            }
+        };
+        let codecs = resolve_codecs(&codec_ids)?;
+        let wal = WAL::open(&format!("{}.wal", db_path), cipher.clone())?;
+        storage::truncate_torn_tail(db_path, cipher.as_ref())?;
         let use_hint = {
             let hint_meta = fs::metadata(&format!("{}.hint", db_path)).ok();
             let db_meta = fs::metadata(db_path).ok();
@@ -50,48 +157,67 @@ impl SlackbaseEngine {
                 _ => false,
             }
         };
-        let index = if use_hint {
-            storage::load_hint(db_path)?
+        // A hint that doesn't load (e.g. torn while being saved) is rebuilt
+        let hint = if use_hint { storage::load_hint(db_path, cipher.as_ref()).ok() } else { None };
+        let index = if let Some(hint) = hint {
+            hint
         } else {
-            let idx = storage::build_offset_index(db_path)?;
-            let _ = storage::save_hint(db_path, &idx);
+            let idx = storage::build_offset_index(db_path, cipher.as_ref())?;
+            let _ = storage::save_hint(db_path, &idx, cipher.as_ref());
             idx
         };
 
-        let lua = Lua::new();
+        let lua = Box::new(sandbox::new_lua()?);
         let scripts = HashMap::new();
         let script_meta = HashMap::new();
         let script_names = HashMap::new();
-        let sec_index = {
-            let path = format!("{}.secindex", db_path);
-            if let Ok(data) = std::fs::read(&path) {
-                serde_json::from_slice(&data).unwrap_or_else(|_| SecondaryIndex::new())
-            } else {
-                SecondaryIndex::new()
-            }
-        };
+        let sec_index: SecondaryIndex = load_sidecar(db_path, "secindex", cipher.as_ref())?;
+        let fts: FullTextIndex = load_sidecar(db_path, "ftsindex", cipher.as_ref())?;
+        let vectors: VectorIndexes = load_sidecar(db_path, "vecindex", cipher.as_ref())?;
+        let geo: GeoIndexes = load_sidecar(db_path, "geoindex", cipher.as_ref())?;
+        let triggers: Triggers = load_sidecar(db_path, "triggers", cipher.as_ref())?;
+        let schedules: Schedules = load_sidecar(db_path, "schedules", cipher.as_ref())?;
+        let prefixes: PrefixRegistry = load_sidecar(db_path, "prefixes", cipher.as_ref())?;
         let lru = LruCache::new(std::num::NonZeroUsize::new(1024).unwrap());
 
         let mut engine = Self {
             db_path: db_path.to_string(),
             index,
             sec_index,
+            fts,
+            vectors,
+            geo,
+            triggers,
+            schedules,
+            prefixes,
             wal,
             write_buffer: Vec::new(),
             serializer,
+            compression,
+            codecs,
+            cipher,
             lru,
             read_ops: 0,
             write_ops: 0,
             hits: 0,
             misses: 0,
-            lua,
             scripts,
             script_meta,
             script_names,
+            libraries: HashMap::new(),
+            library_chunks: HashMap::new(),
+            script_stats: HashMap::new(),
+            slow_log: SlowLog::default(),
+            txn: None,
+            trigger_depth: 0,
+            applying: false,
+            expired: Vec::new(),
+            lua,
         };
 
         engine.recover_from_wal()?;
         engine.load_scripts_from_disk()?;
+        engine.load_libraries_from_disk()?;
 
         Ok(engine)
     }
@@ -107,35 +233,205 @@ impl SlackbaseEngine {
 
     /// Internal put logic supporting TTL.
     fn put_internal(&mut self, key: &str, value: &str, expires_at: Option<u64>) -> Result<()> {
+        let before = self.triggers.any(TriggerEvent::BeforePut, key);
+        let after = self.triggers.any(TriggerEvent::AfterPut, key);
+        if (before || after) && self.txn.is_none() {
+            // The write and whatever its triggers write commit as one unit
+            return self.in_txn(|engine| engine.put_internal(key, value, expires_at));
+        }
+        let value = if before {
+            let changed = self.run_triggers(TriggerEvent::BeforePut, key, Some(value), None)?;
+            Cow::Owned(changed.unwrap_or_default())
+        } else {
+            Cow::Borrowed(value)
+        };
+        let old_value = if after { self.get(key) } else { None };
+        self.put_value(key, &value, expires_at)?;
+        if after {
+            self.run_triggers(TriggerEvent::AfterPut, key, Some(&value), old_value.as_deref())?;
+        }
+        Ok(())
+    }
+
+    /// Buffers the put in the running script's transaction, or writes it.
+    fn put_value(&mut self, key: &str, value: &str, expires_at: Option<u64>) -> Result<()> {
+        self.check_content(key, value)?;
+        if let Some(txn) = &self.txn {
+            // Inside a script: check against the script's own writes, then buffer
+            self.check_unique_in_txn(txn, key, value)?;
+            if let Some(txn) = self.txn.as_mut() {
+                txn.put(key, value, expires_at);
+            }
+            return Ok(());
+        }
+        self.check_unique(&[(key, Some(value))])?;
+        self.write_put(key, value, expires_at)
+    }
+
+    /// Appends a put record and updates the in-memory state. Constraints must already be checked.
+    fn write_put(&mut self, key: &str, value: &str, expires_at: Option<u64>) -> Result<()> {
+        self.write_record(key, value.as_bytes(), Some(value), expires_at)
+    }
+
+    /// Appends a put record of `value`, which is `text` if it is UTF-8.
+    /// Binary values are left out of the derived indexes and the LRU cache.
+    fn write_record(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        text: Option<&str>,
+        expires_at: Option<u64>
+    ) -> Result<()> {
         self.write_ops += 1;
+        // Encode first, so a value the serializer rejects changes nothing
+        let record = self.encode_put(key, value, expires_at)?;
 
-        // --- Secondary index update
+        // --- Secondary and full-text index update
         let old_val = self.get(key);
-        let old_json = old_val.as_deref();
-        let new_json = Some(value);
-        self.sec_index.update(key, old_json, new_json);
-        self.save_sec_index().ok();
+        self.reindex(key, old_val.as_deref(), text);
 
-        // --- Serialize value ---
-        let encoded = self.serializer.serialize(value)?;
-        let encoded_str = general_purpose::STANDARD.encode(&encoded);
-        let record = match expires_at {
-            Some(ts) => format!("put\t{}\t{}\t{}", key, encoded_str, ts),
-            None => format!("put\t{}\t{}\t", key, encoded_str),
-        };
         // --- Write to WAL and buffer
         self.write_buffer.push(record.clone());
         self.flush_buffer()?;
-        let (offset, len) = storage::append_record(&self.db_path, &record)?;
-        self.index.insert(key.to_string(), (offset, len));
-        storage::save_hint(&self.db_path, &self.index)?;
+        let (offset, len) = storage::append_record(&self.db_path, &record, self.cipher.as_ref())?;
+        self.index.insert(key.to_string(), (offset, len, expires_at));
+        storage::save_hint(&self.db_path, &self.index, self.cipher.as_ref())?;
 
         // --- LRU cache: insert or update ---
-        self.lru.put(key.to_string(), value.to_string());
+        // Values with a TTL aren't cached so reads always see their expiry.
+        match (text, expires_at) {
+            (Some(text), None) => {
+                self.lru.put(key.to_string(), text.to_string());
+            }
+            _ => {
+                self.lru.pop(key);
+            }
+        }
 
         Ok(())
     }
 
+    /// Serializes a put into its log record:
+    /// `put\tkey\tbase64_value\texpiry?\tcodec_chain`.
+    fn encode_put(&self, key: &str, value: &[u8], expires_at: Option<u64>) -> Result<String> {
+        let serializer = self.serializer_for_key(key);
+        Ok(format!("put\t{}\t{}", key, self.encode_fields(serializer.as_ref(), value, expires_at)?))
+    }
+
+    /// The fields of a put record after the key. The value goes through
+    /// `serializer` and then the codec pipeline; the chain field lists the
+    /// serializer and each codec that encoded it, e.g. `msgpack+zstd`.
+    fn encode_fields(&self, serializer: &dyn Serializer, value: &[u8], expires_at: Option<u64>) -> Result<String> {
+        let mut encoded = serializer.serialize(value)?;
+        let mut chain = serializer.id().to_string();
+        let compression = self.compression.as_ref().map(|c| c as &dyn ValueCodec);
+        for codec in compression.into_iter().chain(self.codecs.iter().map(|c| c.as_ref())) {
+            if let Some(out) = codec.encode(&encoded)? {
+                encoded = out;
+                chain.push(CHAIN_SEPARATOR);
+                chain.push_str(codec.id());
+            }
+        }
+        let encoded_str = general_purpose::STANDARD.encode(&encoded);
+        let expiry = expires_at.map(|ts| ts.to_string()).unwrap_or_default();
+        Ok(format!("{}\t{}\t{}", encoded_str, expiry, chain))
+    }
+
+    /// Decodes the fields of a put record after the key back into its value.
+    fn decode_fields(&self, fields: &[&str]) -> Result<Vec<u8>> {
+        let bytes = self.decode_codecs(fields)?;
+        let serializer = fields.get(2).and_then(|chain| chain.split(CHAIN_SEPARATOR).next());
+        self.serializer_by_id(serializer).deserialize(&bytes)
+    }
+
+    /// Undoes the codecs in a put record's chain, leaving the value in its serializer's form.
+    fn decode_codecs(&self, fields: &[&str]) -> Result<Vec<u8>> {
+        let mut bytes = general_purpose::STANDARD.decode(fields[0]).map_err(|_| Error::InvalidRecord)?;
+        let chain: Vec<&str> = fields.get(2).copied().unwrap_or("").split(CHAIN_SEPARATOR).collect();
+        for id in chain.iter().skip(1).rev() {
+            let codec = codec::from_id(id).ok_or_else(|| Error::UnknownCodec { id: id.to_string() })?;
+            bytes = codec.decode(&bytes)?;
+        }
+        Ok(bytes)
+    }
+
+    /// The serializer new values of `key` are written with: its prefix's, or the database's.
+    fn serializer_for_key(&self, key: &str) -> Box<dyn Serializer> {
+        self.prefixes
+            .rule_for(key)
+            .and_then(|(_, rule)| serialization::from_id(&rule.serializer))
+            .unwrap_or_else(|| self.serializer.box_clone())
+    }
+
+    /// The serializer a record was written with. Records from before
+    /// serializer ids were stored use the database's.
+    fn serializer_by_id(&self, id: Option<&str>) -> Box<dyn Serializer> {
+        id.filter(|id| !id.is_empty() && *id != self.serializer.id())
+            .and_then(serialization::from_id)
+            .unwrap_or_else(|| self.serializer.box_clone())
+    }
+
+    /// Rejects a value that isn't of its prefix's content type.
+    fn check_content(&self, key: &str, value: &str) -> Result<()> {
+        match self.prefixes.rule_for(key) {
+            Some((prefix, rule)) if !rule.content_type.accepts(value) => {
+                Err(Error::InvalidContent {
+                    key: key.to_string(),
+                    expected: format!("{} (prefix '{}')", rule.content_type.as_str(), prefix),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Keeps the derived indexes (secondary, full-text, vector, geo) in step with a key's value.
+    fn reindex(&mut self, key: &str, old_value: Option<&str>, new_value: Option<&str>) {
+        self.sec_index.update(key, old_value, new_value);
+        self.save_sec_index().ok();
+        if self.fts.covers(key) {
+            self.fts.update(key, new_value);
+            self.save_fts_index().ok();
+        }
+        if self.vectors.covers(key) {
+            self.vectors.update(key, new_value);
+            self.save_vector_index().ok();
+        }
+        if self.geo.covers(key) {
+            self.geo.update(key, new_value);
+            self.save_geo_index().ok();
+        }
+    }
+
+    /// Drops an expired key from memory and the derived indexes.
+    /// The record itself is purged by the next compaction.
+    fn expire_key(&mut self, key: &str, old_value: Option<&str>) {
+        self.index.remove(key);
+        self.lru.pop(key);
+        self.reindex(key, old_value, None);
+        storage::save_hint(&self.db_path, &self.index, self.cipher.as_ref()).ok();
+        if self.triggers.any(TriggerEvent::OnExpire, key) {
+            self.expired.push((key.to_string(), old_value.map(str::to_string)));
+        }
+    }
+
+    /// Runs the on_expire triggers of keys expired since the last call, each as
+    /// its own unit. Deferred while a script or commit is in progress, since
+    /// expiry is noticed on reads.
+    fn fire_expired(&mut self) {
+        if self.txn.is_some() || self.applying {
+            return;
+        }
+        while !self.expired.is_empty() {
+            let (key, old_value) = self.expired.remove(0);
+            let res = self.in_txn(|engine| {
+                engine.run_triggers(TriggerEvent::OnExpire, &key, None, old_value.as_deref())
+            });
+            if let Err(e) = res {
+                eprintln!("Warning: on_expire trigger for '{}' failed: {:?}", key, e);
+            }
+        }
+    }
+
     /// Puts a key-value pair.
     pub fn put(&mut self, key: &str, value: &str) -> Result<()> {
         self.put_internal(key, value, None)
@@ -143,13 +439,17 @@ impl SlackbaseEngine {
 
     /// Puts a key-value pair with TTL.
     pub fn putex(&mut self, key: &str, value: &str, ttl_secs: u64) -> Result<()> {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let now = unix_now()?;
         self.put_internal(key, value, Some(now + ttl_secs))
     }
 
-    /// Gets a value by key.
+    /// Gets a value by key. Binary (non-UTF-8) values read as `None`; use `get_bytes`.
     pub fn get(&mut self, key: &str) -> Option<String> {
         self.read_ops += 1;
+        // Inside a script, its own pending writes win
+        if let Some(pending) = self.txn.as_ref().and_then(|txn| txn.get(key)) {
+            return pending.map(|v| v.to_string());
+        }
         // 1. Fast path: check LRU cache first
         if let Some(val) = self.lru.get(key) {
             self.hits += 1;
@@ -157,8 +457,41 @@ impl SlackbaseEngine {
         }
 
         // 2. Fall back to disk/index
-        let (offset, len) = *self.index.get(key)?;
-        let raw = storage::read_record_slice(&self.db_path, offset, len).ok().flatten()?;
+        let (bytes, has_ttl) = self.read_stored(key)?;
+        let value = String::from_utf8(bytes).ok()?;
+
+        // 3. Store in LRU cache for future fast lookup (TTL values are re-read to check expiry)
+        if !has_ttl {
+            self.lru.put(key.to_string(), value.clone());
+        }
+        self.hits += 1;
+        Some(value)
+    }
+
+    /// Gets a value as bytes, including binary values `get` can't return.
+    pub fn get_bytes(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let key = key_from_bytes(key);
+        self.read_ops += 1;
+        if let Some(pending) = self.txn.as_ref().and_then(|txn| txn.get(&key)) {
+            return pending.map(|v| v.as_bytes().to_vec());
+        }
+        if let Some(val) = self.lru.get(&key) {
+            self.hits += 1;
+            return Some(val.as_bytes().to_vec());
+        }
+        let (bytes, has_ttl) = self.read_stored(&key)?;
+        if !has_ttl && let Ok(text) = std::str::from_utf8(&bytes) {
+            self.lru.put(key, text.to_string());
+        }
+        self.hits += 1;
+        Some(bytes)
+    }
+
+    /// Reads and decodes a key's record, and whether it has a TTL. An
+    /// expired key is dropped (firing its on_expire triggers) and reads as missing.
+    fn read_stored(&mut self, key: &str) -> Option<(Vec<u8>, bool)> {
+        let (offset, len, _) = *self.index.get(key)?;
+        let raw = storage::read_record_slice(&self.db_path, offset, len, self.cipher.as_ref()).ok().flatten()?;
         let parts: Vec<&str> = raw.split('\t').collect();
 
         if parts.len() < 3 || parts[0] != "put" {
@@ -166,26 +499,95 @@ impl SlackbaseEngine {
             return None;
         }
 
-        let encoded_str = parts[2];
+        let decoded = self.decode_fields(&parts[2..]).ok();
+        let mut has_ttl = false;
         if parts.len() >= 4 && !parts[3].is_empty() {
             let expires_at: u64 = parts[3].parse().ok()?;
-            if SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() > expires_at {
+            if unix_now().ok()? > expires_at {
                 self.misses += 1;
+                let old_value = decoded.and_then(|b| String::from_utf8(b).ok());
+                self.expire_key(key, old_value.as_deref());
+                self.fire_expired();
                 return None;
             }
+            has_ttl = true;
         }
+        Some((decoded?, has_ttl))
+    }
 
-        let bytes = general_purpose::STANDARD.decode(encoded_str).ok()?;
-        let value = self.serializer.deserialize(&bytes).ok()?;
+    /// Puts a value as bytes. UTF-8 values are ordinary puts. Binary values
+    /// are stored as they are, but can't be indexed, checked against a
+    /// content type other than `any`, passed to triggers or written by a script.
+    pub fn put_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let key = key_from_bytes(key);
+        if let Ok(text) = std::str::from_utf8(value) {
+            return self.put(&key, text);
+        }
+        if self.txn.is_some() {
+            return Err(Error::InvalidQuery("binary values can't be written inside a script".to_string()));
+        }
+        if let Some((prefix, rule)) = self.prefixes.rule_for(&key)
+            && rule.content_type != ContentType::Any
+        {
+            return Err(Error::InvalidContent {
+                key,
+                expected: format!("{} (prefix '{}')", rule.content_type.as_str(), prefix),
+            });
+        }
+        if self.triggers.any(TriggerEvent::BeforePut, &key) || self.triggers.any(TriggerEvent::AfterPut, &key) {
+            return Err(Error::InvalidQuery(format!("'{}' has put triggers, which can't take binary values", key)));
+        }
+        self.write_record(&key, value, None, None)
+    }
 
-        // 3. Store in LRU cache for future fast lookup
-        self.lru.put(key.to_string(), value.clone());
-        self.hits += 1;
-        Some(value)
+    /// Puts any serializable value, stored as JSON through the configured serializer.
+    pub fn put_typed<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<()> {
+        let json = serde_json::to_string(value)?;
+        self.put(key, &json)
+    }
+
+    /// Gets a value written with `put_typed` (or any JSON value) as `T`.
+    /// A value that doesn't decode as `T` is an `Error::Serde`.
+    pub fn get_typed<T: DeserializeOwned>(&mut self, key: &str) -> Result<Option<T>> {
+        self.get(key)
+            .map(|json| serde_json::from_str(&json).map_err(Error::Serde))
+            .transpose()
+    }
+
+    /// Puts a value given in the serializer's encoded form (e.g. CBOR bytes
+    /// from a device). It is decoded first, so malformed input is rejected.
+    pub fn put_encoded(&mut self, key: &str, data: &[u8]) -> Result<()> {
+        let value = self.serializer_for_key(key).deserialize(data)?;
+        self.put_bytes(key.as_bytes(), &value)
+    }
+
+    /// Gets a value in the serializer's encoded form, as it is stored.
+    pub fn get_encoded(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.get_bytes(key.as_bytes())
+            .map(|value| self.serializer_for_key(key).serialize(&value))
+            .transpose()
     }
 
     /// Deletes a key.
     pub fn delete(&mut self, key: &str) -> Result<()> {
+        let after = self.triggers.any(TriggerEvent::AfterDelete, key);
+        if after && self.txn.is_none() {
+            return self.in_txn(|engine| engine.delete(key));
+        }
+        let old_value = if after { self.get(key) } else { None };
+        match self.txn.as_mut() {
+            Some(txn) => txn.delete(key),
+            None => self.write_delete(key)?,
+        }
+        // Only deleting an existing key fires after_delete
+        if let Some(old_value) = old_value {
+            self.run_triggers(TriggerEvent::AfterDelete, key, None, Some(&old_value))?;
+        }
+        Ok(())
+    }
+
+    /// Appends a delete record and updates the in-memory state.
+    fn write_delete(&mut self, key: &str) -> Result<()> {
         self.write_ops += 1;
         // 1. Get the old value BEFORE removal for index update!
         let old_val = self.get(key);
@@ -193,126 +595,699 @@ impl SlackbaseEngine {
         let record = format!("del\t{}", key);
         self.write_buffer.push(record.clone());
         self.flush_buffer()?;
-        let (_off, _len) = storage::append_record(&self.db_path, &record)?;
+        let (_off, _len) = storage::append_record(&self.db_path, &record, self.cipher.as_ref())?;
         self.index.remove(key);
 
-        // 2. Update the secondary index
-        self.sec_index.remove(key, old_val.as_deref());
-        self.save_sec_index().ok(); // <-- persist index after delete
+        // 2. Update the secondary and full-text indexes
+        self.reindex(key, old_val.as_deref(), None);
 
         // --- LRU cache: remove deleted key ---
         self.lru.pop(key);
 
-        storage::save_hint(&self.db_path, &self.index)?;
+        storage::save_hint(&self.db_path, &self.index, self.cipher.as_ref())?;
         Ok(())
     }
 
     /// Compacts the database log and reindexes.
     pub fn compact(&mut self) -> Result<()> {
+        self.compact_as(None)
+    }
+
+    /// Re-encrypts the database under `key` by compacting it. `None` decrypts
+    /// it, and a plaintext database is encrypted the same way. The sidecar
+    /// files are rewritten under the new key too. The new files are staged
+    /// next to the old ones and only replace them once the manifest holds the
+    /// new key, so a crash leaves the database readable with one of the keys.
+    pub fn rotate_key(&mut self, key: Option<&EncryptionKey>) -> Result<()> {
+        if self.txn.is_some() {
+            return Err(Error::InvalidQuery("can't change the key while a script is running".to_string()));
+        }
+        match key {
+            Some(key) => {
+                let (cipher, info) = crypto::setup(key)?;
+                self.compact_as(Some((Some(cipher), Some(info))))
+            }
+            None => self.compact_as(Some((None, None))),
+        }
+    }
+
+    /// Compacts, re-encrypting with `rekey` (the new cipher and its manifest entry) if given.
+    fn compact_as(&mut self, rekey: Option<(Option<Cipher>, Option<EncryptionInfo>)>) -> Result<()> {
         self.flush_buffer()?;
-        storage::compact_log(&self.db_path)?;
+        let cipher = match &rekey {
+            Some((cipher, _)) => cipher.clone(),
+            None => self.cipher.clone(),
+        };
+        match rekey {
+            None => storage::compact_log(&self.db_path, self.cipher.as_ref(), cipher.as_ref())?,
+            Some((_, info)) => {
+                let data = staged(&self.db_path);
+                storage::compact_log_to(&self.db_path, &data, self.cipher.as_ref(), cipher.as_ref())?;
+                fsync_file(&data)?;
+                for ext in SIDECARS {
+                    let path = format!("{}.{}", self.db_path, ext);
+                    let plain = match fs::read(&path) {
+                        Ok(sealed) => open_sidecar(self.cipher.as_ref(), sealed)?,
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                        Err(e) => return Err(Error::Io(e)),
+                    };
+                    fs::write(staged(&path), seal_sidecar(cipher.as_ref(), plain))?;
+                    fsync_file(&staged(&path))?;
+                }
+                // Every unit in the WAL is in the data file by now, and the
+                // WAL is sealed with the old key
+                self.wal.clear()?;
+                // The new manifest is the commit point; `finish_swap` then
+                // moves the staged files into place
+                let mut m = Manifest::load(&self.db_path)?.unwrap_or_else(|| Manifest::new(self.serializer.id(), 0));
+                m.encryption = info;
+                m.swap_pending = true;
+                m.save(&self.db_path)?;
+                finish_swap(&self.db_path)?;
+                self.cipher = cipher.clone();
+            }
+        }
 
-        self.index = storage::build_offset_index(&self.db_path)?;
-        storage::save_hint(&self.db_path, &self.index)?;
+        self.index = storage::build_offset_index(&self.db_path, cipher.as_ref())?;
+        storage::save_hint(&self.db_path, &self.index, cipher.as_ref())?;
 
         self.wal.clear()?;
-        *self = SlackbaseEngine::open(&self.db_path, self.serializer.box_clone())?;
+        *self = Self::open_with_cipher(&self.db_path, self.serializer.box_clone(), cipher)?;
+
+        // Compaction drops expired records; forget them in the derived indexes too
+        let index = &self.index;
+        self.sec_index.retain(|k| index.contains_key(k));
+        self.fts.retain(|k| index.contains_key(k));
+        self.vectors.retain(|k| index.contains_key(k));
+        self.geo.retain(|k| index.contains_key(k));
+        self.save_sec_index()?;
+        self.save_fts_index()?;
+        self.save_vector_index()?;
+        self.save_geo_index()?;
         Ok(())
     }
 
+    /// The serializer recorded in a database's manifest, or `None` if the
+    /// database doesn't exist yet or predates manifests.
+    pub fn stored_serializer(db_path: &str) -> Result<Option<Box<dyn Serializer>>> {
+        let Some(m) = Manifest::load(db_path)? else {
+            return Ok(None);
+        };
+        serialization::from_id(&m.serializer)
+            .map(Some)
+            .ok_or_else(|| Error::InvalidQuery(format!("unknown serializer '{}' in manifest", m.serializer)))
+    }
+
+    /// Opens a database with the serializer in its manifest, or `default` if it has none.
+    pub fn open_auto(db_path: &str, default: Box<dyn Serializer>) -> Result<Self> {
+        let serializer = Self::stored_serializer(db_path)?.unwrap_or(default);
+        Self::open(db_path, serializer)
+    }
+
+    /// Re-encodes every live value with `target` (except keys under a prefix
+    /// rule, which keep their prefix's serializer) and records it in the
+    /// manifest. Expired and overwritten records are dropped, as in `compact`.
+    /// Every value is converted before anything is written, so a value the
+    /// target can't hold (e.g. non-JSON text for `json`) leaves the database
+    /// as it was. Returns the number of keys migrated.
+    pub fn migrate_format(&mut self, target: Box<dyn Serializer>) -> Result<usize> {
+        if self.txn.is_some() {
+            return Err(Error::InvalidQuery("can't migrate while a script is running".to_string()));
+        }
+        self.flush_buffer()?;
+        let now = unix_now()?;
+        let mut keys: Vec<String> = self.index.keys().cloned().collect();
+        keys.sort();
+
+        let mut records = Vec::with_capacity(keys.len());
+        for key in keys {
+            let (offset, len, _) = self.index[&key];
+            let Some(raw) = storage::read_record_slice(&self.db_path, offset, len, self.cipher.as_ref())? else {
+                continue;
+            };
+            let parts: Vec<&str> = raw.split('\t').collect();
+            if parts.len() < 3 || parts[0] != "put" {
+                continue;
+            }
+            let expires_at = parts.get(3).and_then(|s| s.parse::<u64>().ok());
+            if expires_at.is_some_and(|ts| now > ts) {
+                continue;
+            }
+            let fail = |e: Error| {
+                Error::InvalidQuery(format!("can't migrate '{}' to {}: {:?}", key, target.id(), e))
+            };
+            let value = self.decode_fields(&parts[2..]).map_err(fail)?;
+            // Keys under a prefix rule keep the prefix's serializer
+            let serializer = match self.prefixes.rule_for(&key) {
+                Some(_) => self.serializer_for_key(&key),
+                None => target.box_clone(),
+            };
+            let fields = self.encode_fields(serializer.as_ref(), &value, expires_at).map_err(fail)?;
+            records.push(format!("put\t{}\t{}", key, fields));
+        }
+
+        // Stage the new data file. The manifest naming the new serializer is
+        // the commit point; `finish_swap` then moves the file into place, so
+        // values are never read with the wrong serializer after a crash
+        let staged_path = staged(&self.db_path);
+        let mut file = fs::File::create(&staged_path)?;
+        for record in &records {
+            writeln!(file, "{}", crypto::seal_line(self.cipher.as_ref(), record))?;
+        }
+        file.sync_all()?;
+        // Every unit in the WAL is in the data file by now
+        self.wal.clear()?;
+        let mut m = Manifest::load(&self.db_path)?.unwrap_or_else(|| Manifest::new(target.id(), now));
+        m.serializer = target.id().to_string();
+        m.swap_pending = true;
+        m.save(&self.db_path)?;
+        finish_swap(&self.db_path)?;
+
+        self.index = storage::build_offset_index(&self.db_path, self.cipher.as_ref())?;
+        storage::save_hint(&self.db_path, &self.index, self.cipher.as_ref())?;
+        self.wal.clear()?;
+        *self = Self::open_with_cipher(&self.db_path, target, self.cipher.clone())?;
+        Ok(records.len())
+    }
+
     /// Executes a batch of operations atomically.
     pub fn batch(&mut self, ops: Vec<BatchOp>) -> Result<()> {
+        let writes: Vec<(&str, Option<&str>)> = ops
+            .iter()
+            .map(|op| match op {
+                BatchOp::Put(k, v) => (k.as_str(), Some(v.as_str())),
+                BatchOp::Del(k) => (k.as_str(), None),
+            })
+            .collect();
+        for (key, value) in &writes {
+            if let Some(value) = value {
+                self.check_content(key, value)?;
+            }
+        }
+        self.check_unique(&writes)?;
+
+        // Constraints were checked against the batch as a whole, so an op may
+        // pass through a transient conflict (e.g. two keys swapping a value).
+        let ops = ops
+            .into_iter()
+            .map(|op| match op {
+                BatchOp::Put(k, v) => (k, Some((v, None))),
+                BatchOp::Del(k) => (k, None),
+            })
+            .collect();
+        self.apply_unit(ops)
+    }
+
+    /// Logs `ops` to the WAL as one BEGIN/END unit, applies them, then clears
+    /// the WAL. A crash before the END line drops the whole unit; a crash after
+    /// it is finished by `recover_from_wal` on the next open.
+    fn apply_unit(&mut self, ops: Vec<(String, TxnWrite)>) -> Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
         self.flush_buffer()?;
         self.wal.append("BEGIN")?;
-        for op in &ops {
-            match op {
-                BatchOp::Put(k, v) => self.wal.append(&format!("put\t{}\t{}", k, v))?,
-                BatchOp::Del(k) => self.wal.append(&format!("del\t{}", k))?,
-            }
+        for (key, write) in &ops {
+            let record = match write {
+                Some((value, expires_at)) => self.encode_put(key, value.as_bytes(), *expires_at)?,
+                None => format!("del\t{}", key),
+            };
+            self.wal.append(&record)?;
         }
         self.wal.append("END")?;
+        self.wal.sync()?;
+
+        self.applying = true;
+        let res = ops.into_iter().try_for_each(|(key, write)| match write {
+            Some((value, expires_at)) => self.write_put(&key, &value, expires_at),
+            None => self.write_delete(&key),
+        });
+        self.applying = false;
+        res?;
         self.wal.flush()?;
-        for op in ops {
-            match op {
-                BatchOp::Put(k, v) => self.put(&k, &v)?,
-                BatchOp::Del(k) => self.delete(&k)?,
+        self.wal.clear()?;
+        self.fire_expired();
+        Ok(())
+    }
+
+    /// Runs `f` with writes buffered in a transaction, then commits them as
+    /// one unit; if `f` fails nothing is written.
+    fn in_txn<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.txn = Some(ScriptTxn::default());
+        let res = f(self);
+        let txn = self.txn.take().unwrap_or_default();
+        let out = res?;
+        self.apply_unit(txn.into_ops())?;
+        Ok(out)
+    }
+
+    /// Rejects writes that would give a unique field a value that another live key
+    /// already holds. `writes` are (key, new value or None for delete) in order, and
+    /// are checked as a whole so a batch sees its own writes.
+    fn check_unique(&self, writes: &[(&str, Option<&str>)]) -> Result<()> {
+        if !self.sec_index.has_unique() {
+            return Ok(());
+        }
+        // Final state per key, later writes win; `order` keeps errors stable
+        let mut pending: HashMap<&str, Option<&str>> = HashMap::new();
+        let mut order = Vec::new();
+        for &(key, value) in writes {
+            if pending.insert(key, value).is_none() {
+                order.push(key);
+            }
+        }
+
+        let mut claimed: HashMap<(String, String), &str> = HashMap::new();
+        for key in order {
+            let Some(json) = pending[key] else {
+                continue;
+            };
+            // Keys rewritten by this same batch are judged by their pending value
+            let conflicts = self.unique_holders(key, json, |h| pending.contains_key(h));
+            for (field, strval, _, mut holders) in conflicts {
+                if let Some(other) = claimed.insert((field.clone(), strval.clone()), key) {
+                    holders.push(other.to_string());
+                }
+                if !holders.is_empty() {
+                    holders.sort();
+                    return Err(Error::UniqueViolation { field, conflicts: vec![(strval, holders)] });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a put made inside a script against the log and the script's own
+    /// pending writes, which replace whatever the log holds for their keys.
+    fn check_unique_in_txn(&self, txn: &ScriptTxn, key: &str, json: &str) -> Result<()> {
+        if !self.sec_index.has_unique() {
+            return Ok(());
+        }
+        let conflicts = self.unique_holders(key, json, |h| txn.get(h).is_some());
+        for (field, strval, prefix, mut holders) in conflicts {
+            for (other, value) in txn.pending() {
+                let Some(value) = value else {
+                    continue;
+                };
+                if
+                    other != key &&
+                    prefix.as_deref().is_none_or(|p| other.starts_with(p)) &&
+                    index::indexed_values(value).iter().any(|(f, v)| *f == field && *v == strval)
+                {
+                    holders.push(other.to_string());
+                }
+            }
+            if !holders.is_empty() {
+                holders.sort();
+                return Err(Error::UniqueViolation { field, conflicts: vec![(strval, holders)] });
             }
         }
         Ok(())
     }
 
-    /// Recovers completed batches from WAL on startup.
+    /// For each unique field `json` sets on `key`: (field, value, constraint prefix,
+    /// other live keys holding that value). Keys for which `overridden` is true
+    /// have a pending value and are left to the caller.
+    fn unique_holders(
+        &self,
+        key: &str,
+        json: &str,
+        overridden: impl Fn(&str) -> bool
+    ) -> Vec<(String, String, Option<String>, Vec<String>)> {
+        let constraints = self.sec_index.unique_fields_for(key);
+        if constraints.is_empty() {
+            return Vec::new();
+        }
+        let mut out = Vec::new();
+        for (field, strval) in index::indexed_values(json) {
+            let Some((_, prefix)) = constraints.iter().find(|(f, _)| *f == field) else {
+                continue;
+            };
+            let holders: Vec<String> = self.sec_index
+                .find(&field, &strval)
+                .into_iter()
+                .filter(|h| {
+                    h != key &&
+                        prefix.as_deref().is_none_or(|p| h.starts_with(p)) &&
+                        !overridden(h) &&
+                        self.is_live(h)
+                })
+                .collect();
+            out.push((field, strval, prefix.clone(), holders));
+        }
+        out
+    }
+
+    /// Declares `field` unique among JSON documents whose key starts with `prefix`.
+    /// Fails with the conflicting keys if existing documents already violate it.
+    pub fn create_unique_index(&mut self, field: &str, prefix: Option<&str>) -> Result<()> {
+        let mut sec_index = std::mem::take(&mut self.sec_index);
+        let res = sec_index.add_unique(field, prefix, |k| self.is_live(k));
+        self.sec_index = sec_index;
+        res?;
+        self.save_sec_index()
+    }
+
+    /// Drops the unique constraint on `field`.
+    pub fn drop_unique_index(&mut self, field: &str) -> Result<()> {
+        if !self.sec_index.remove_unique(field) {
+            return Err(Error::NotFound);
+        }
+        self.save_sec_index()
+    }
+
+    /// Finishes units (batches, script commits) whose END reached the WAL,
+    /// then clears it. Replaying a unit that was already applied is harmless
+    /// since every op sets or removes an absolute value.
     fn recover_from_wal(&mut self) -> Result<()> {
-        let entries = self.wal.iter()?; // Expects WAL to provide all lines as Vec<String>
+        let entries = self.wal.iter()?;
         let mut in_tx = false;
-        let mut batch = Vec::<String>::new();
+        let mut unit = Vec::<String>::new();
+        let mut units = Vec::new();
 
         for entry in entries {
             match entry.as_str() {
                 "BEGIN" => {
                     in_tx = true;
-                    batch.clear();
+                    unit.clear();
                 }
                 "END" if in_tx => {
-                    for op in &batch {
-                        if op.starts_with("put\t") {
-                            let parts: Vec<&str> = op.splitn(3, '\t').collect();
-                            if parts.len() == 3 {
-                                self.put(parts[1], parts[2])?;
-                            }
-                        } else if op.starts_with("del\t") {
-                            let parts: Vec<&str> = op.splitn(2, '\t').collect();
-                            if parts.len() == 2 {
-                                self.delete(parts[1])?;
-                            }
-                        }
-                    }
+                    units.push(std::mem::take(&mut unit));
                     in_tx = false;
-                    batch.clear();
                 }
-                _ if in_tx => batch.push(entry.clone()),
+                _ if in_tx => unit.push(entry),
                 _ => {}
             }
         }
+        if units.is_empty() {
+            return Ok(());
+        }
+
+        for unit in units {
+            for op in unit {
+                let Some((kind, key, rest)) = storage::parse_record(&op) else {
+                    continue;
+                };
+                match kind {
+                    "put" => {
+                        let (value, expires_at) = self.decode_wal_put(rest);
+                        let text = std::str::from_utf8(&value).ok();
+                        self.write_record(key, &value, text, expires_at)?;
+                    }
+                    "del" => self.write_delete(key)?,
+                    _ => {}
+                }
+            }
+        }
+        self.wal.flush()?;
+        self.wal.clear()?;
         Ok(())
     }
 
+    /// Decodes the part of a WAL put after the key. Older WALs stored the raw value.
+    fn decode_wal_put(&self, rest: &str) -> (Vec<u8>, Option<u64>) {
+        let fields: Vec<&str> = rest.split('\t').collect();
+        let expires_at = fields.get(1).and_then(|s| s.parse::<u64>().ok());
+        match self.decode_fields(&fields).ok() {
+            Some(value) if rest.contains('\t') => (value, expires_at),
+            _ => (rest.as_bytes().to_vec(), None),
+        }
+    }
+
     /// Saves script metadata and source to disk.
     pub fn save_scripts_to_disk(&self) -> Result<()> {
-        let meta_path = format!("{}.scripts", self.db_path);
         let script_list: Vec<ScriptMeta> = self.script_meta.values().cloned().collect();
-        let serialized = serde_json::to_string_pretty(&script_list)?;
-        fs::write(meta_path, serialized)?;
-        Ok(())
+        self.save_sidecar("scripts", &script_list)
     }
 
-    /// Loads script metadata and source from disk.
+    /// Loads script metadata and source from disk and recompiles each script.
+    /// Entries whose source is missing, doesn't match the stored SHA1 or
+    /// fails to compile are skipped with a warning.
     pub fn load_scripts_from_disk(&mut self) -> Result<()> {
-        let meta_path = format!("{}.scripts", self.db_path);
-        if let Ok(data) = fs::read_to_string(&meta_path) {
-            let metas: Vec<ScriptMeta> = serde_json::from_str(&data)?;
+        if let Some(data) = self.read_sidecar("scripts")? {
+            let metas: Vec<ScriptMeta> = serde_json::from_slice(&data)?;
             for meta in metas {
-                // self.eval_register(&meta.source, Some(&meta.name), meta.desc.as_deref())?;
+                if meta.source.is_empty() {
+                    eprintln!(
+                        "Warning: script '{}' ({}) has no stored source; register it again",
+                        meta.name,
+                        meta.sha1
+                    );
+                    continue;
+                }
+                if script_sha1(&meta.source) != meta.sha1 {
+                    eprintln!(
+                        "Warning: script '{}' ({}) does not match its SHA1; skipping",
+                        meta.name,
+                        meta.sha1
+                    );
+                    continue;
+                }
+                if let Err(e) = self.compile_script(&meta.sha1, &meta.source, &meta.name) {
+                    eprintln!("Warning: script '{}' failed to compile: {:?}", meta.name, e);
+                    continue;
+                }
+                if meta.name != meta.sha1 {
+                    self.script_names.insert(meta.name.clone(), meta.sha1.clone());
+                }
+                self.script_meta.insert(meta.sha1.clone(), meta);
             }
         }
         Ok(())
     }
 
-    pub fn save_sec_index(&self) -> Result<()> {
-        let path = format!("{}.secindex", self.db_path);
-        let data = serde_json::to_vec(&self.sec_index)?;
-        std::fs::write(path, data)?;
+    /// Writes a JSON sidecar (`<db>.<ext>`), sealed if the database is encrypted.
+    fn save_sidecar<T: Serialize>(&self, ext: &str, value: &T) -> Result<()> {
+        let path = format!("{}.{}", self.db_path, ext);
+        let data = serde_json::to_vec(value)?;
+        std::fs::write(path, seal_sidecar(self.cipher.as_ref(), data))?;
         Ok(())
     }
 
+    /// Reads and, if the database is encrypted, opens a sidecar; `None` if there
+    /// is none. Unlike `load_sidecar`, the caller parses it, so a bad file is an error.
+    fn read_sidecar(&self, ext: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(format!("{}.{}", self.db_path, ext)) {
+            Ok(data) => Ok(Some(open_sidecar(self.cipher.as_ref(), data)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    pub fn save_sec_index(&self) -> Result<()> {
+        self.save_sidecar("secindex", &self.sec_index)
+    }
+
     pub fn load_sec_index(&mut self) -> Result<()> {
         let path = format!("{}.secindex", self.db_path);
         if let Ok(data) = std::fs::read(&path) {
-            self.sec_index = serde_json::from_slice(&data)?;
+            self.sec_index = serde_json::from_slice(&open_sidecar(self.cipher.as_ref(), data)?)?;
         }
         Ok(())
     }
 
+    pub fn save_fts_index(&self) -> Result<()> {
+        self.save_sidecar("ftsindex", &self.fts)
+    }
+
+    /// Creates a full-text index over the given JSON string fields of keys
+    /// starting with `prefix`, and indexes the existing documents.
+    pub fn create_fulltext_index(
+        &mut self,
+        name: &str,
+        fields: &[&str],
+        prefix: Option<&str>,
+        stem: bool
+    ) -> Result<()> {
+        let def = FtsIndexDef {
+            fields: fields
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+            prefix: prefix.map(|p| p.to_string()),
+            stem,
+        };
+        self.fts.create(name, def)?;
+        let keys: Vec<String> = self.index
+            .keys()
+            .filter(|k| prefix.is_none_or(|p| k.starts_with(p)))
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(value) = self.get(&key) {
+                self.fts.add_to(name, &key, &value);
+            }
+        }
+        self.save_fts_index()
+    }
+
+    pub fn drop_fulltext_index(&mut self, name: &str) -> Result<()> {
+        if !self.fts.drop_index(name) {
+            return Err(Error::NotFound);
+        }
+        self.save_fts_index()
+    }
+
+    /// Full-text search: words, `prefix*` terms and `"phrases"`, all of which
+    /// must match. Returns at most `limit` live keys ranked by BM25.
+    pub fn search(&self, index_name: &str, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let hits = self.fts.search(index_name, query)?;
+        Ok(
+            hits
+                .into_iter()
+                .filter(|hit| self.is_live(&hit.key))
+                .take(limit)
+                .collect()
+        )
+    }
+
+    pub fn save_vector_index(&self) -> Result<()> {
+        self.save_sidecar("vecindex", &self.vectors)
+    }
+
+    /// Creates a vector index over a JSON number-array field and indexes existing documents.
+    pub fn create_vector_index(&mut self, name: &str, def: VectorIndexDef) -> Result<()> {
+        let prefix = def.prefix.clone();
+        self.vectors.create(name, def)?;
+        let keys: Vec<String> = self.index
+            .keys()
+            .filter(|k| prefix.as_deref().is_none_or(|p| k.starts_with(p)))
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(value) = self.get(&key) {
+                self.vectors.add_to(name, &key, &value);
+            }
+        }
+        self.save_vector_index()
+    }
+
+    pub fn drop_vector_index(&mut self, name: &str) -> Result<()> {
+        if !self.vectors.drop_index(name) {
+            return Err(Error::NotFound);
+        }
+        self.save_vector_index()
+    }
+
+    /// The `k` nearest live documents to `query`. A `field=value` filter is resolved
+    /// through the secondary index first and only those keys are compared.
+    pub fn vsearch(
+        &self,
+        index_name: &str,
+        k: usize,
+        query: &[f32],
+        filter: Option<(&str, &str)>
+    ) -> Result<Vec<VectorHit>> {
+        let allowed: Option<HashSet<String>> = filter.map(|(field, value)| {
+            self.sec_index.find(field, value).into_iter().collect()
+        });
+        let hits = self.vectors.search(index_name, query, k, allowed.as_ref())?;
+        Ok(
+            hits
+                .into_iter()
+                .filter(|hit| self.is_live(&hit.key))
+                .take(k)
+                .collect()
+        )
+    }
+
+    pub fn save_geo_index(&self) -> Result<()> {
+        self.save_sidecar("geoindex", &self.geo)
+    }
+
+    /// Creates a geo index over latitude/longitude fields and indexes existing documents.
+    pub fn create_geo_index(&mut self, name: &str, def: GeoIndexDef) -> Result<()> {
+        let prefix = def.prefix.clone();
+        self.geo.create(name, def)?;
+        let keys: Vec<String> = self.index
+            .keys()
+            .filter(|k| prefix.as_deref().is_none_or(|p| k.starts_with(p)))
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(value) = self.get(&key) {
+                self.geo.add_to(name, &key, &value);
+            }
+        }
+        self.save_geo_index()
+    }
+
+    pub fn drop_geo_index(&mut self, name: &str) -> Result<()> {
+        if !self.geo.drop_index(name) {
+            return Err(Error::NotFound);
+        }
+        self.save_geo_index()
+    }
+
+    /// Sets the index's longitude/latitude fields on the JSON document at `key`
+    /// (creating it if needed) in a single write.
+    pub fn geoadd(&mut self, index_name: &str, key: &str, lon: f64, lat: f64) -> Result<()> {
+        let def = self.geo.definition(index_name).cloned().ok_or(Error::NotFound)?;
+        if !geo::valid_coords(lon, lat) {
+            return Err(Error::InvalidQuery(format!("invalid coordinates {},{}", lon, lat)));
+        }
+        if def.prefix.as_deref().is_some_and(|p| !key.starts_with(p)) {
+            return Err(Error::InvalidQuery(format!("'{}' is outside the index prefix", key)));
+        }
+        let mut obj = self
+            .get(key)
+            .and_then(|s|
+                serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&s).ok()
+            )
+            .unwrap_or_default();
+        obj.insert(def.lon_field, serde_json::json!(lon));
+        obj.insert(def.lat_field, serde_json::json!(lat));
+        let new_json = serde_json::to_string(&obj)?;
+        self.put(key, &new_json)
+    }
+
+    /// Indexed (longitude, latitude) of a live key.
+    pub fn geopos(&self, index_name: &str, key: &str) -> Result<Option<(f64, f64)>> {
+        let pos = self.geo.position(index_name, key)?;
+        Ok(pos.filter(|_| self.is_live(key)))
+    }
+
+    /// Distance between two indexed keys.
+    pub fn geodist(&self, index_name: &str, a: &str, b: &str, unit: GeoUnit) -> Result<Option<f64>> {
+        match (self.geopos(index_name, a)?, self.geopos(index_name, b)?) {
+            (Some((lon1, lat1)), Some((lon2, lat2))) => {
+                Ok(Some(unit.convert(geo::haversine(lon1, lat1, lon2, lat2))))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Standard 11-character geohash of an indexed key.
+    pub fn geohash(&self, index_name: &str, key: &str) -> Result<Option<String>> {
+        Ok(self.geopos(index_name, key)?.map(|(lon, lat)| geo::geohash_string(lon, lat)))
+    }
+
+    /// Indexed keys within `shape` of `center` (lon, lat), sorted by distance
+    /// (farthest first if `descending`) and truncated to `count`.
+    pub fn geosearch(
+        &self,
+        index_name: &str,
+        center: (f64, f64),
+        shape: GeoShape,
+        unit: GeoUnit,
+        count: Option<usize>,
+        descending: bool
+    ) -> Result<Vec<GeoHit>> {
+        let (lon, lat) = center;
+        let mut hits: Vec<GeoHit> = self.geo
+            .search(index_name, lon, lat, shape, unit)?
+            .into_iter()
+            .filter(|hit| self.is_live(&hit.key))
+            .collect();
+        if descending {
+            hits.reverse();
+        }
+        if let Some(n) = count {
+            hits.truncate(n);
+        }
+        Ok(hits)
+    }
+
     /// Set a field inside a JSON object (at key). Creates object if needed.
     /// value may be raw JSON or string.
     pub fn json_set_field(&mut self, key: &str, field: &str, value: &str) -> Result<()> {
@@ -330,9 +1305,7 @@ impl SlackbaseEngine {
         if let serde_json::Value::Object(ref mut map) = root {
             map.insert(field.to_string(), new_val);
             let new_json = serde_json::to_string(&root)?;
-            // --- update index on JSON field set ---
-            self.sec_index.update(key, old_val.as_deref(), Some(&new_json));
-            self.save_sec_index().ok(); // <-- persist index after update
+            // put() updates the secondary index once the write is accepted
             self.put(key, &new_json)
         } else {
             // If not an object, overwrite with a new object
@@ -340,8 +1313,6 @@ impl SlackbaseEngine {
             map.insert(field.to_string(), new_val);
             let root = serde_json::Value::Object(map);
             let new_json = serde_json::to_string(&root)?;
-            self.sec_index.update(key, old_val.as_deref(), Some(&new_json));
-            self.save_sec_index().ok(); // <-- persist index after update
             self.put(key, &new_json)
         }
     }
@@ -392,7 +1363,7 @@ impl SlackbaseEngine {
     /// Saves a crash-safe snapshot (fsyncs after copy).
     pub fn snapshot(&mut self, snapshot_path: &str) -> Result<()> {
         self.flush_buffer()?;
-        storage::save_hint(&self.db_path, &self.index)?;
+        storage::save_hint(&self.db_path, &self.index, self.cipher.as_ref())?;
         fs::copy(&self.db_path, snapshot_path).map_err(Error::Io)?;
         fsync_file(snapshot_path)?;
         let wal_src = format!("{}.wal", &self.db_path);
@@ -407,11 +1378,16 @@ impl SlackbaseEngine {
             fs::copy(&hint_src, &hint_dst).ok();
             fsync_file(&hint_dst).ok();
         }
+        let manifest_dst = manifest::path(snapshot_path);
+        fs::copy(manifest::path(&self.db_path), &manifest_dst)?;
+        fsync_file(&manifest_dst)?;
         Ok(())
     }
 
-    /// Restores from a snapshot.
+    /// Restores from a snapshot. An encrypted snapshot must be under the database's current key.
     pub fn restore(&mut self, snapshot_path: &str) -> Result<()> {
+        let snapshot_encryption = Manifest::load(snapshot_path)?.and_then(|m| m.encryption);
+        check_key(snapshot_encryption.as_ref(), self.cipher.as_ref(), snapshot_path)?;
         fs::copy(snapshot_path, &self.db_path).map_err(Error::Io)?;
         let wal_src = format!("{}.wal", snapshot_path);
         let hint_src = format!("{}.hint", snapshot_path);
@@ -421,7 +1397,15 @@ impl SlackbaseEngine {
         if fs::metadata(&hint_src).is_ok() {
             fs::copy(&hint_src, &format!("{}.hint", &self.db_path)).ok();
         }
-        *self = SlackbaseEngine::open(&self.db_path, self.serializer.box_clone())?;
+        // A snapshot keeps the serializer it was taken with
+        let serializer = match Self::stored_serializer(snapshot_path)? {
+            Some(serializer) => {
+                fs::copy(manifest::path(snapshot_path), manifest::path(&self.db_path))?;
+                serializer
+            }
+            None => self.serializer.box_clone(),
+        };
+        *self = Self::open_with_cipher(&self.db_path, serializer, self.cipher.clone())?;
         Ok(())
     }
 
@@ -433,6 +1417,11 @@ impl SlackbaseEngine {
     ) -> Vec<(String, Option<String>)> {
         let mut result = Vec::new();
         let mut keys: Vec<String> = self.index.keys().cloned().collect();
+        // Inside a script, include keys it created and skip keys it deleted
+        if let Some(txn) = &self.txn {
+            keys.extend(txn.keys().filter(|k| !self.index.contains_key(*k)).cloned());
+            keys.retain(|k| txn.get(k) != Some(None));
+        }
         keys.sort();
         for key in keys {
             if let Some(pfx) = prefix {
@@ -451,6 +1440,104 @@ impl SlackbaseEngine {
         result
     }
 
+    /// Returns up to `count` live entries with keys after `cursor` (in key order)
+    /// and the cursor for the next page, or `None` once the keys are exhausted.
+    /// The page's keys are a range of the sorted index starting at the cursor,
+    /// and only their values are read, so a page costs about the same however
+    /// many keys the database holds.
+    pub fn scan_page(
+        &mut self,
+        cursor: Option<&str>,
+        prefix: Option<&str>,
+        count: usize
+    ) -> (Vec<(String, String)>, Option<String>) {
+        let count = count.max(1);
+        let page: Vec<String> = {
+            let after = |k: &str| cursor.is_none_or(|c| k > c);
+            let matches = |k: &str| prefix.is_none_or(|p| k.starts_with(p));
+            let deleted = |k: &str| self.txn.as_ref().is_some_and(|txn| txn.get(k) == Some(None));
+            // Keys with the prefix are contiguous, from the prefix itself
+            let start = match (cursor, prefix) {
+                (Some(c), Some(p)) if p > c => Bound::Included(p),
+                (Some(c), _) => Bound::Excluded(c),
+                (None, Some(p)) => Bound::Included(p),
+                (None, None) => Bound::Unbounded,
+            };
+            let mut keys: Vec<&String> = self.index
+                .range::<str, _>((start, Bound::Unbounded))
+                .map(|(k, _)| k)
+                .take_while(|k| matches(k))
+                .filter(|k| !deleted(k))
+                .take(count + 1)
+                .collect();
+            // Inside a script, include keys it created
+            if let Some(txn) = &self.txn {
+                keys.extend(
+                    txn.keys().filter(|k| !self.index.contains_key(*k) && after(k) && matches(k) && !deleted(k))
+                );
+                keys.sort_unstable();
+                keys.truncate(count + 1);
+            }
+            keys.into_iter().cloned().collect()
+        };
+
+        let next = if page.len() > count { page.get(count - 1).cloned() } else { None };
+        let entries = page
+            .into_iter()
+            .take(count)
+            .filter_map(|key| self.get(&key).map(|value| (key, value)))
+            .collect();
+        (entries, next)
+    }
+
+    /// Runs an aggregation over JSON documents. Field values are read from the
+    /// secondary index postings and expiry from the key index, so the log is never read.
+    pub fn aggregate(&self, query: &AggQuery) -> Result<Vec<AggRow>> {
+        let candidates: Vec<String> = match &query.filter {
+            Some((field, value)) => self.sec_index.find(field, value),
+            None => self.index.keys().cloned().collect(),
+        };
+        let values = query.field.as_ref().map(|f| self.sec_index.field_values(f));
+        let groups = query.group_by.as_ref().map(|f| self.sec_index.field_values(f));
+
+        let mut items = Vec::new();
+        for key in &candidates {
+            if let Some(pfx) = &query.prefix && !key.starts_with(pfx.as_str()) {
+                continue;
+            }
+            // Documents missing the aggregated or grouping field don't contribute
+            let raw = match &values {
+                Some(map) => match map.get(key) {
+                    Some(v) => Some(v.as_str()),
+                    None => continue,
+                }
+                None => None,
+            };
+            let group = match &groups {
+                Some(map) => match map.get(key) {
+                    Some(g) => Some(g.as_str()),
+                    None => continue,
+                }
+                None => None,
+            };
+            if !self.is_live(key) {
+                continue;
+            }
+            items.push((group, raw));
+        }
+        Ok(agg::fold(query.func, query.group_by.is_some(), items))
+    }
+
+    /// True if `key` has a present, non-expired record. Answered from the index,
+    /// without reading the log.
+    fn is_live(&self, key: &str) -> bool {
+        match self.index.get(key) {
+            Some(&(_, _, Some(expires_at))) => unix_now().unwrap_or(0) <= expires_at,
+            Some(_) => true,
+            None => false,
+        }
+    }
+
     /// Returns human-readable statistics.
     pub fn stats(&self) -> String {
         let db_size = fs
@@ -466,7 +1553,7 @@ impl SlackbaseEngine {
             .map(|m| m.len())
             .unwrap_or(0);
         let total = db_size + wal_size + hint_size;
-        format!(
+        let mut stats = format!(
             "Reads: {}\nWrites: {}\nHits: {}\nMisses: {}\n\
             Total keys: {}\nDB size: {} bytes\nWAL size: {} bytes\nHint size: {} bytes\nTotal disk usage: {} bytes",
             self.read_ops,
@@ -478,6 +1565,90 @@ impl SlackbaseEngine {
             wal_size,
             hint_size,
             total
+        );
+
+        stats.push_str(&self.storage_stats());
+
+        let (calls, errors) = self.script_stats
+            .values()
+            .fold((0, 0), |(c, e), s| (c + s.calls, e + s.errors));
+        stats.push_str(
+            &format!(
+                "\nScript calls: {}\nScript errors: {}\nSlow log: {} entries (threshold {} ms)",
+                calls,
+                errors,
+                self.slow_log.len(),
+                self.slow_log.threshold_ms
+            )
+        );
+        let mut per_script: Vec<(&String, &ScriptStats)> = self.script_stats
+            .iter()
+            .filter_map(|(sha, s)| self.script_meta.get(sha).map(|m| (&m.name, s)))
+            .collect();
+        per_script.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, s) in per_script {
+            stats.push_str(
+                &format!(
+                    "\n  {}: {} calls, {} errors, avg {:.2} ms, p99 {:.2} ms",
+                    name,
+                    s.calls,
+                    s.errors,
+                    (s.avg_us() as f64) / 1000.0,
+                    (s.p99_us() as f64) / 1000.0
+                )
+            );
+        }
+        stats
+    }
+
+    /// The storage lines of `stats`: encryption, the codec pipeline, and
+    /// the ratio of serialized to stored size over every live value.
+    fn storage_stats(&self) -> String {
+        let encryption = match &self.cipher {
+            Some(cipher) => cipher.kind().as_str(),
+            None => "off",
+        };
+        let setting = match &self.compression {
+            Some(c) => format!("{} (threshold {} bytes)", c.algorithm.as_str(), c.threshold),
+            None => "off".to_string(),
+        };
+        let codecs = match self.codecs() {
+            ids if ids.is_empty() => "none".to_string(),
+            ids => ids.join(", "),
+        };
+        let spans: Vec<(u64, usize)> = self.index
+            .values()
+            .map(|&(offset, len, _)| (offset, len))
+            .collect();
+        let records = storage::read_record_slices(&self.db_path, &spans, self.cipher.as_ref()).unwrap_or_default();
+        let (mut raw, mut stored, mut compressed) = (0usize, 0usize, 0usize);
+        for record in &records {
+            let parts: Vec<&str> = record.split('\t').collect();
+            if parts.len() < 3 || parts[0] != "put" {
+                continue;
+            }
+            let size = base64_decoded_len(parts[2]);
+            stored += size;
+            let mut chain = parts.get(4).map_or("", |c| *c).split(CHAIN_SEPARATOR).skip(1);
+            if chain.any(|id| Algorithm::parse(id).is_some()) {
+                compressed += 1;
+            }
+            raw += match self.decode_codecs(&parts[2..]) {
+                Ok(bytes) => bytes.len(),
+                Err(_) => size,
+            };
+        }
+        let ratio = if stored == 0 { 1.0 } else { (raw as f64) / (stored as f64) };
+        format!(
+            "\nEncryption: {}\nCompression: {}\nCodecs: {}\nCompressed values: {} of {}\nCompression ratio: {:.2} ({} bytes serialized, {} bytes stored)",
+            encryption,
+            setting,
+            codecs,
+            compressed,
+            records.len(),
+            ratio,
+            raw,
+            stored
         )
     }
 
@@ -615,6 +1786,53 @@ impl SlackbaseEngine {
         }
     }
 
+    /// Sets a hash field to any serializable value (`hash_set` stores strings).
+    pub fn hash_set_typed<T: Serialize + ?Sized>(&mut self, key: &str, field: &str, value: &T) -> Result<()> {
+        let mut obj = self
+            .get(key)
+            .and_then(|s|
+                serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&s).ok()
+            )
+            .unwrap_or_default();
+        obj.insert(field.to_string(), serde_json::to_value(value)?);
+        let new_json = serde_json::to_string(&obj)?;
+        self.put(key, &new_json)
+    }
+
+    pub fn hash_get_typed<T: DeserializeOwned>(&mut self, key: &str, field: &str) -> Result<Option<T>> {
+        self.hash_get(key, field)
+            .map(|json| serde_json::from_str(&json).map_err(Error::Serde))
+            .transpose()
+    }
+
+    /// Appends any serializable value to the list at key (`list_rpush` stores strings).
+    pub fn list_rpush_typed<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<()> {
+        let mut items = match self.get(key).and_then(|s| serde_json::from_str(&s).ok()) {
+            Some(serde_json::Value::Array(items)) => items,
+            _ => Vec::new(),
+        };
+        items.push(serde_json::to_value(value)?);
+        let new_json = serde_json::to_string(&items)?;
+        self.put(key, &new_json)
+    }
+
+    /// `list_range`, with each item decoded as `T`.
+    pub fn list_range_typed<T: DeserializeOwned>(
+        &mut self,
+        key: &str,
+        start: isize,
+        end: isize
+    ) -> Result<Option<Vec<T>>> {
+        let Some(items) = self.list_range(key, start, end) else {
+            return Ok(None);
+        };
+        items
+            .iter()
+            .map(|json| serde_json::from_str(json).map_err(Error::Serde))
+            .collect::<Result<Vec<T>>>()
+            .map(Some)
+    }
+
     // Get list length
     pub fn list_len(&mut self, key: &str) -> usize {
         self.get(key)
@@ -630,23 +1848,20 @@ impl SlackbaseEngine {
         name: Option<&str>,
         desc: Option<&str>
     ) -> Result<String> {
-        let mut hasher = Sha1::new();
-        hasher.update(src.as_bytes());
-        let sha = hex::encode(hasher.finalize());
-
-        if !self.scripts.contains_key(&sha) {
-            let func = self.lua
-                .load(src)
-                .into_function()
-                .map_err(|_| Error::InvalidRecord)?;
-            let func_static: Function<'static> = unsafe { std::mem::transmute(func) };
-            self.scripts.insert(sha.clone(), func_static);
-        }
+        let sha = script_sha1(src);
+        self.compile_script(&sha, src, name.unwrap_or(&sha))?;
 
+        // Re-registering the same source keeps its configured limits
+        let limits = self.script_meta
+            .get(&sha)
+            .map(|m| m.limits.clone())
+            .unwrap_or_default();
         let meta = ScriptMeta {
             name: name.map(|s| s.to_string()).unwrap_or_else(|| sha.clone()),
             sha1: sha.clone(),
             desc: desc.map(|s| s.to_string()),
+            source: src.to_string(),
+            limits,
         };
         self.script_meta.insert(sha.clone(), meta.clone());
 
@@ -660,6 +1875,196 @@ impl SlackbaseEngine {
         Ok(sha)
     }
 
+    /// Sets the per-call limits of a script (by name or SHA).
+    pub fn set_script_limits(&mut self, name_or_sha: &str, limits: ScriptLimits) -> Result<()> {
+        let sha = self.script_names
+            .get(name_or_sha)
+            .cloned()
+            .unwrap_or_else(|| name_or_sha.to_string());
+        let meta = self.script_meta.get_mut(&sha).ok_or(Error::NotFound)?;
+        meta.limits = limits;
+        self.save_scripts_to_disk()
+    }
+
+    /// Compiles `src` into the script cache under `sha` (no-op if already cached).
+    /// `chunk_name` is what error messages and tracebacks call the script.
+    fn compile_script(&mut self, sha: &str, src: &str, chunk_name: &str) -> Result<()> {
+        if !self.scripts.contains_key(sha) {
+            let func = self.lua
+                .load(src)
+                .set_name(format!("={}", chunk_name))
+                .into_function()
+                .map_err(sandbox::script_error)?;
+            let func_static: Function<'static> = unsafe { std::mem::transmute(func) };
+            self.scripts.insert(sha.to_string(), func_static);
+        }
+        Ok(())
+    }
+
+    /// Registers a library: a chunk returning a table of functions that scripts
+    /// load with `require(name)`. New source becomes the next version;
+    /// registering the latest version's source again is a no-op. Returns the version.
+    pub fn library_register(&mut self, name: &str, src: &str, desc: Option<&str>) -> Result<u32> {
+        if name.is_empty() || name.contains('@') {
+            return Err(Error::InvalidQuery(format!("invalid library name '{}'", name)));
+        }
+        let sha = script_sha1(src);
+        let latest = self.libraries.get(name).and_then(|lib| lib.versions.last());
+        if let Some(latest) = latest.filter(|v| v.sha1 == sha) {
+            return Ok(latest.version);
+        }
+        let last = self.libraries.get(name).map_or(0, |lib| lib.last_version);
+        let version = latest.map_or(last, |v| v.version.max(last)) + 1;
+
+        let chunk = self.compile_library(&sha, src, &format!("{}@{}", name, version))?;
+        let functions = self.library_exports(&chunk)?;
+        self.library_chunks.insert(sha.clone(), chunk);
+
+        let lib = self.libraries
+            .entry(name.to_string())
+            .or_insert_with(|| LibraryMeta {
+                name: name.to_string(),
+                versions: Vec::new(),
+                last_version: 0,
+            });
+        lib.last_version = version;
+        lib.versions.push(LibraryVersion {
+            version,
+            sha1: sha,
+            desc: desc.map(|s| s.to_string()),
+            source: src.to_string(),
+            functions,
+        });
+        self.save_libraries_to_disk()?;
+        Ok(version)
+    }
+
+    /// Removes one version of a library, or all of them. A library with no
+    /// versions left stays behind empty so its version numbers aren't reused.
+    pub fn library_remove(&mut self, name: &str, version: Option<u32>) -> Result<()> {
+        let lib = self.libraries
+            .get_mut(name)
+            .filter(|lib| !lib.versions.is_empty())
+            .ok_or(Error::NotFound)?;
+        let before = lib.versions.len();
+        match version {
+            Some(v) => lib.versions.retain(|lv| lv.version != v),
+            None => lib.versions.clear(),
+        }
+        if lib.versions.len() == before {
+            return Err(Error::NotFound);
+        }
+        let live: HashSet<&String> = self.libraries
+            .values()
+            .flat_map(|lib| lib.versions.iter().map(|v| &v.sha1))
+            .collect();
+        self.library_chunks.retain(|sha, _| live.contains(sha));
+        self.save_libraries_to_disk()
+    }
+
+    /// The compiled chunk for `name` (latest version unless one is given),
+    /// with the `name@version` id it is cached under while a script runs.
+    pub fn library_chunk(&self, name: &str, version: Option<u32>) -> Option<(String, Function<'static>)> {
+        let lib = self.libraries.get(name)?;
+        let entry = match version {
+            Some(v) => lib.versions.iter().find(|lv| lv.version == v)?,
+            None => lib.versions.last()?,
+        };
+        let chunk = self.library_chunks.get(&entry.sha1)?.clone();
+        Some((format!("{}@{}", name, entry.version), chunk))
+    }
+
+    pub fn save_libraries_to_disk(&self) -> Result<()> {
+        let mut libs: Vec<&LibraryMeta> = self.libraries.values().collect();
+        libs.sort_by(|a, b| a.name.cmp(&b.name));
+        self.save_sidecar("libraries", &libs)
+    }
+
+    /// Loads and recompiles stored libraries. Like scripts, versions whose
+    /// source doesn't match their SHA1 or fails to compile are skipped with a warning.
+    pub fn load_libraries_from_disk(&mut self) -> Result<()> {
+        let Some(data) = self.read_sidecar("libraries")? else {
+            return Ok(());
+        };
+        let libs: Vec<LibraryMeta> = serde_json::from_slice(&data)?;
+        for mut lib in libs {
+            // Files written before `last_version` existed only have the versions.
+            let newest = lib.versions.iter().map(|v| v.version).max().unwrap_or(0);
+            lib.last_version = lib.last_version.max(newest);
+            let mut versions = Vec::new();
+            for v in std::mem::take(&mut lib.versions) {
+                let id = format!("{}@{}", lib.name, v.version);
+                if script_sha1(&v.source) != v.sha1 {
+                    eprintln!("Warning: library '{}' does not match its SHA1; skipping", id);
+                    continue;
+                }
+                match self.compile_library(&v.sha1, &v.source, &id) {
+                    Ok(chunk) => {
+                        self.library_chunks.insert(v.sha1.clone(), chunk);
+                        versions.push(v);
+                    }
+                    Err(e) => eprintln!("Warning: library '{}' failed to compile: {:?}", id, e),
+                }
+            }
+            lib.versions = versions;
+            if lib.last_version > 0 {
+                self.libraries.insert(lib.name.clone(), lib);
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_library(&self, sha: &str, src: &str, chunk_name: &str) -> Result<Function<'static>> {
+        if let Some(chunk) = self.library_chunks.get(sha) {
+            return Ok(chunk.clone());
+        }
+        let func = self.lua
+            .load(src)
+            .set_name(format!("={}", chunk_name))
+            .into_function()
+            .map_err(sandbox::script_error)?;
+        Ok(unsafe { std::mem::transmute::<Function<'_>, Function<'static>>(func) })
+    }
+
+    /// Runs a library chunk once (in the sandbox, with default limits and
+    /// any writes discarded) and returns the names of the functions it exports.
+    fn library_exports(&mut self, chunk: &Function<'static>) -> Result<Vec<String>> {
+        let engine_ptr = self as *mut SlackbaseEngine;
+        let lua: &'static Lua = unsafe { &*(self.lua.as_ref() as *const Lua) };
+        // `self` is exclusively borrowed for the whole call, so the pointer stays valid
+        unsafe { lua_api::install(lua, engine_ptr)? };
+        sandbox::arm(lua, &ScriptLimits::default())?;
+        self.txn = Some(ScriptTxn::default());
+        let res = chunk.call::<_, Value>(());
+        self.txn = None;
+        if let Some(reason) = sandbox::disarm(lua, res.as_ref().err()) {
+            return Err(Error::ScriptLimit(reason));
+        }
+        let Value::Table(exports) = res.map_err(sandbox::script_error)? else {
+            return Err(Error::Script {
+                message: "a library must return a table of functions".into(),
+                traceback: None,
+            });
+        };
+        let mut functions = Vec::new();
+        for pair in exports.pairs::<Value, Value>() {
+            if let (Value::String(name), Value::Function(_)) = pair? {
+                functions.push(name.to_str()?.to_string());
+            }
+        }
+        functions.sort();
+        Ok(functions)
+    }
+
+    /// Resolves a script name or SHA1 to the SHA1 of a registered script.
+    fn resolve_script(&self, name_or_sha: &str) -> Option<String> {
+        if self.scripts.contains_key(name_or_sha) {
+            Some(name_or_sha.to_string())
+        } else {
+            self.script_names.get(name_or_sha).cloned()
+        }
+    }
+
     /// Executes a script by name or SHA.
     pub fn eval_by_name_or_sha(
         &mut self,
@@ -667,94 +2072,327 @@ impl SlackbaseEngine {
         keys: &[&str],
         args: &[&str]
     ) -> Result<Value> {
-        let sha = if self.scripts.contains_key(name_or_sha) {
-            name_or_sha.to_string()
-        } else if let Some(sha) = self.script_names.get(name_or_sha) {
-            sha.clone()
-        } else {
-            return Err(Error::NotFound);
-        };
+        let sha = self.resolve_script(name_or_sha).ok_or(Error::NotFound)?;
         self.eval_sha(&sha, keys, args)
     }
 
     /// Executes a script by SHA.
     pub fn eval_sha(&mut self, sha: &str, keys: &[&str], args: &[&str]) -> Result<Value> {
-        use mlua::Error as LuaError;
+        self.invoke_script(sha, keys, args, None)
+    }
 
-        let func = match self.scripts.get(sha) {
-            Some(f) => f.clone(),
-            None => {
-                return Err(Error::NotFound);
-            }
+    /// Runs a compiled script, recording its duration and outcome in the
+    /// script's stats and, past the threshold, in the slow log.
+    fn invoke_script(
+        &mut self,
+        sha: &str,
+        keys: &[&str],
+        args: &[&str],
+        trigger: Option<&TriggerCall>
+    ) -> Result<Value<'static>> {
+        if !self.scripts.contains_key(sha) {
+            return Err(Error::NotFound);
+        }
+        let started_at = unix_now()?;
+        let started = std::time::Instant::now();
+        let res = self.run_script(sha, keys, args, trigger);
+        let duration_us = started.elapsed().as_micros() as u64;
+
+        let error = res.as_ref().err().map(error_summary);
+        if self.slow_log.is_slow(duration_us) {
+            self.slow_log.push(SlowEntry {
+                id: 0,
+                started_at,
+                duration_us,
+                script: self.script_meta
+                    .get(sha)
+                    .map_or_else(|| sha.to_string(), |m| m.name.clone()),
+                keys: keys.iter().map(|k| k.to_string()).collect(),
+                args: args.iter().map(|a| a.to_string()).collect(),
+                outcome: error.clone().unwrap_or_else(|| "ok".to_string()),
+            });
+        }
+        self.script_stats.entry(sha.to_string()).or_default().record(duration_us, error);
+        res
+    }
+
+    /// Runs a compiled script. The outermost call installs the API and the
+    /// script's limits; a trigger fired from inside a running script reuses
+    /// them, and its writes join the caller's transaction. Writes are
+    /// committed when the call that opened the transaction succeeds.
+    fn run_script(
+        &mut self,
+        sha: &str,
+        keys: &[&str],
+        args: &[&str],
+        trigger: Option<&TriggerCall>
+    ) -> Result<Value<'static>> {
+        let func = self.scripts.get(sha).cloned().ok_or(Error::NotFound)?;
+
+        let engine_ptr = self as *mut SlackbaseEngine;
+        // The state is boxed and outlives the compiled scripts, which already
+        // borrow it as 'static; doing the same here keeps `self` free for the commit.
+        let lua: &'static Lua = unsafe { &*(self.lua.as_ref() as *const Lua) };
+        let globals = lua.globals();
+        let outermost = !sandbox::armed(lua);
+
+        // The caller's globals, restored once a nested call returns
+        let saved: Option<(Value, Value, Value)> = if outermost {
+            // `self` is exclusively borrowed for the whole call, so the pointer stays valid
+            unsafe { lua_api::install(lua, engine_ptr)? };
+            None
+        } else {
+            Some((globals.get("KEYS")?, globals.get("ARGV")?, globals.get("TRIGGER")?))
         };
 
-        // Build DB snapshot
-        let db_snapshot = {
-            let all_keys: Vec<String> = self.index.keys().cloned().collect();
-            let mut pairs = Vec::new();
-            for key in all_keys {
-                if let Some(val) = self.get(&key) {
-                    pairs.push((key, val));
-                }
-            }
-            pairs
+        globals.set("KEYS", lua.create_sequence_from(keys.iter().copied())?)?;
+        globals.set("ARGV", lua.create_sequence_from(args.iter().copied())?)?;
+        let trigger_table = match trigger {
+            Some(call) => Value::Table(lua_api::trigger_table(lua, call)?),
+            None => Value::Nil,
         };
+        globals.set("TRIGGER", trigger_table)?;
+
+        if let Some((keys, args, trigger)) = saved {
+            let res = func.call::<_, Value>(());
+            globals.set("KEYS", keys)?;
+            globals.set("ARGV", args)?;
+            globals.set("TRIGGER", trigger)?;
+            return res.map_err(sandbox::script_error);
+        }
 
-        let engine_ptr = self as *mut SlackbaseEngine;
-        let globals = self.lua.globals();
+        // Run with the script's limits, buffering its writes until it succeeds
+        let limits = self.script_meta
+            .get(sha)
+            .map(|m| m.limits.clone())
+            .unwrap_or_default();
+        sandbox::arm(lua, &limits)?;
+        let own_txn = self.txn.is_none();
+        if own_txn {
+            self.txn = Some(ScriptTxn::default());
+        }
+        let res = func.call::<_, Value>(());
+        let txn = if own_txn { self.txn.take() } else { None };
+        let limit_hit = sandbox::disarm(lua, res.as_ref().err());
+        if let Some(reason) = limit_hit {
+            return Err(Error::ScriptLimit(reason));
+        }
+        let res = res.map_err(sandbox::script_error)?;
+        if let Some(txn) = txn {
+            self.apply_unit(txn.into_ops())?;
+        }
+        Ok(res)
+    }
 
-        let get_fn = self.lua
-            .create_function_mut(move |_, key: String| {
-                unsafe { Ok((*engine_ptr).get(&key).unwrap_or_default()) }
-            })
-            .map_err(|_| Error::InvalidRecord)?;
-        globals.set("GET", get_fn).map_err(|_| Error::InvalidRecord)?;
-
-        let set_fn = self.lua
-            .create_function_mut(move |_, (key, val): (String, String)| {
-                unsafe {
-                    (*engine_ptr)
-                        .put(&key, &val)
-                        .map_err(|_| LuaError::RuntimeError("Failed SET".into()))?;
-                }
-                Ok(())
-            })
-            .map_err(|_| Error::InvalidRecord)?;
-        globals.set("SET", set_fn).map_err(|_| Error::InvalidRecord)?;
-
-        let del_fn = self.lua
-            .create_function_mut(move |_, key: String| {
-                unsafe {
-                    (*engine_ptr)
-                        .delete(&key)
-                        .map_err(|_| LuaError::RuntimeError("Failed DEL".into()))?;
-                }
-                Ok(())
-            })
-            .map_err(|_| Error::InvalidRecord)?;
-        globals.set("DEL", del_fn).map_err(|_| Error::InvalidRecord)?;
+    /// Registers a trigger. Its script must already be registered.
+    pub fn create_trigger(&mut self, name: &str, def: TriggerDef) -> Result<()> {
+        if self.resolve_script(&def.script).is_none() {
+            return Err(Error::NotFound);
+        }
+        self.triggers.create(name, def)?;
+        self.save_triggers()
+    }
+
+    pub fn drop_trigger(&mut self, name: &str) -> Result<()> {
+        if !self.triggers.drop_trigger(name) {
+            return Err(Error::NotFound);
+        }
+        self.save_triggers()
+    }
+
+    /// Adds a schedule that runs a registered script with the given KEYS/ARGV.
+    pub fn add_schedule(
+        &mut self,
+        name: &str,
+        spec: ScheduleSpec,
+        script: &str,
+        keys: Vec<String>,
+        args: Vec<String>
+    ) -> Result<()> {
+        if self.resolve_script(script).is_none() {
+            return Err(Error::NotFound);
+        }
+        let now = unix_now()?;
+        let next_run = spec.next_after(now);
+        if next_run.is_none() {
+            return Err(Error::InvalidQuery(format!("'{}' never fires", spec)));
+        }
+        let schedule = Schedule {
+            next_run,
+            spec,
+            script: script.to_string(),
+            keys,
+            args,
+            last_run: None,
+        };
+        self.schedules.add(name, schedule)?;
+        self.save_schedules()
+    }
 
-        let lua_keys = self.lua.create_table().map_err(|_| Error::InvalidRecord)?;
-        for (i, &k) in keys.iter().enumerate() {
-            lua_keys.set(i + 1, k).map_err(|_| Error::InvalidRecord)?;
+    pub fn remove_schedule(&mut self, name: &str) -> Result<()> {
+        if !self.schedules.remove(name) {
+            return Err(Error::NotFound);
         }
-        globals.set("KEYS", lua_keys).map_err(|_| Error::InvalidRecord)?;
+        self.save_schedules()
+    }
+
+    pub fn save_schedules(&self) -> Result<()> {
+        self.save_sidecar("schedules", &self.schedules)
+    }
 
-        let lua_args = self.lua.create_table().map_err(|_| Error::InvalidRecord)?;
-        for (i, &a) in args.iter().enumerate() {
-            lua_args.set(i + 1, a).map_err(|_| Error::InvalidRecord)?;
+    /// Runs every schedule that is due, once each (runs missed while the
+    /// database was closed are not repeated), and records how each went.
+    /// Returns the names and records of the schedules that ran.
+    pub fn run_due_schedules(&mut self) -> Result<Vec<(String, RunRecord)>> {
+        let now = unix_now()?;
+        let mut ran = Vec::new();
+        for name in self.schedules.due(now) {
+            let Some(schedule) = self.schedules.get_mut(&name).cloned() else {
+                continue;
+            };
+            let keys: Vec<&str> = schedule.keys.iter().map(String::as_str).collect();
+            let args: Vec<&str> = schedule.args.iter().map(String::as_str).collect();
+            let started = std::time::Instant::now();
+            let res = self.eval_by_name_or_sha(&schedule.script, &keys, &args).map(|_| ());
+            let record = RunRecord {
+                started_at: now,
+                duration_ms: started.elapsed().as_millis() as u64,
+                outcome: match res {
+                    Ok(()) => "ok".to_string(),
+                    Err(Error::NotFound) => format!("script '{}' not found", schedule.script),
+                    Err(e) => error_summary(&e),
+                },
+            };
+            let finished = unix_now()?;
+            if let Some(entry) = self.schedules.get_mut(&name) {
+                entry.next_run = entry.spec.next_after(finished);
+                entry.last_run = Some(record.clone());
+            }
+            ran.push((name, record));
+        }
+        if !ran.is_empty() {
+            self.save_schedules()?;
         }
-        globals.set("ARGV", lua_args).map_err(|_| Error::InvalidRecord)?;
+        Ok(ran)
+    }
 
-        // Now create DB table from the snapshot.
-        let db_table = self.lua.create_table().map_err(|_| Error::InvalidRecord)?;
-        for (key, val) in db_snapshot {
-            db_table.set(key, val).map_err(|_| Error::InvalidRecord)?;
+    /// Sets the serializer and content type of keys under `prefix`. Existing
+    /// values keep the serializer they were written with until rewritten.
+    pub fn set_prefix_rule(
+        &mut self,
+        prefix: &str,
+        serializer: &str,
+        content_type: ContentType
+    ) -> Result<()> {
+        if serialization::from_id(serializer).is_none() {
+            return Err(Error::InvalidQuery(format!("unknown serializer '{}'", serializer)));
         }
-        globals.set("DB", db_table).map_err(|_| Error::InvalidRecord)?;
+        let rule = PrefixRule { serializer: serializer.to_string(), content_type };
+        self.prefixes.set(prefix, rule);
+        self.save_prefixes()
+    }
 
-        let res = func.call(()).map_err(|_| Error::InvalidRecord)?;
-        Ok(res)
+    pub fn remove_prefix_rule(&mut self, prefix: &str) -> Result<()> {
+        if !self.prefixes.remove(prefix) {
+            return Err(Error::NotFound);
+        }
+        self.save_prefixes()
+    }
+
+    /// Sets how new values are compressed (`None` turns compression off) and
+    /// records it in the manifest. Existing records keep their encoding
+    /// until they are rewritten.
+    pub fn set_compression(&mut self, compression: Option<Compression>) -> Result<()> {
+        let mut m = Manifest::load(&self.db_path)?.unwrap_or_else(|| Manifest::new(self.serializer.id(), 0));
+        m.compression = compression;
+        m.save(&self.db_path)?;
+        self.compression = compression;
+        Ok(())
+    }
+
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Sets the codecs (by id, see `codec::register`) values go through after
+    /// compression, in order, and records them in the manifest. Existing
+    /// records keep the chain they were written with.
+    pub fn set_codecs(&mut self, ids: &[&str]) -> Result<()> {
+        let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        let codecs = resolve_codecs(&ids)?;
+        let mut m = Manifest::load(&self.db_path)?.unwrap_or_else(|| Manifest::new(self.serializer.id(), 0));
+        m.codecs = ids;
+        m.save(&self.db_path)?;
+        self.codecs = codecs;
+        Ok(())
+    }
+
+    /// Ids of the codecs values go through after compression.
+    pub fn codecs(&self) -> Vec<String> {
+        self.codecs
+            .iter()
+            .map(|c| c.id().to_string())
+            .collect()
+    }
+
+    pub fn save_prefixes(&self) -> Result<()> {
+        self.save_sidecar("prefixes", &self.prefixes)
+    }
+
+    pub fn save_triggers(&self) -> Result<()> {
+        self.save_sidecar("triggers", &self.triggers)
+    }
+
+    /// Runs the `event` triggers matching `key`, in name order. Each gets
+    /// KEYS = {key}, ARGV = {value or old value} and a TRIGGER table. For
+    /// before_put the value each trigger returns is passed to the next and
+    /// the final one is returned; `false` vetoes the write.
+    fn run_triggers(
+        &mut self,
+        event: TriggerEvent,
+        key: &str,
+        value: Option<&str>,
+        old_value: Option<&str>
+    ) -> Result<Option<String>> {
+        let mut value = value.map(str::to_string);
+        for (name, def) in self.triggers.matching(event, key) {
+            let fail = |message: String| Error::Trigger { name: name.clone(), message };
+            if self.trigger_depth >= MAX_TRIGGER_DEPTH {
+                return Err(fail(format!("triggers nested more than {} deep", MAX_TRIGGER_DEPTH)));
+            }
+            let sha = self
+                .resolve_script(&def.script)
+                .ok_or_else(|| fail(format!("script '{}' not found", def.script)))?;
+            let arg = value.clone().or(old_value.map(str::to_string)).unwrap_or_default();
+            let call = TriggerCall { name: &name, event, key, value: value.as_deref(), old_value };
+
+            self.trigger_depth += 1;
+            let res = self.invoke_script(&sha, &[key], &[&arg], Some(&call));
+            self.trigger_depth -= 1;
+            let returned = res?;
+
+            if event == TriggerEvent::BeforePut {
+                match returned {
+                    Value::Nil | Value::Boolean(true) => {}
+                    Value::Boolean(false) => {
+                        return Err(fail(format!("vetoed write to '{}'", key)));
+                    }
+                    Value::String(s) => {
+                        value = Some(s.to_str()?.to_string());
+                    }
+                    Value::Integer(n) => {
+                        value = Some(n.to_string());
+                    }
+                    Value::Number(n) => {
+                        value = Some(n.to_string());
+                    }
+                    _ => {
+                        return Err(fail("before_put must return nil, a boolean or a string".into()));
+                    }
+                }
+            }
+        }
+        Ok(value)
     }
 
     /// Lists registered script SHAs.
@@ -763,6 +2401,159 @@ impl SlackbaseEngine {
     }
 }
 
+// Marks a key stored from bytes that aren't usable as a text key
+const BINARY_KEY_MARKER: char = '\0';
+
+/// The text form of a byte key: the key itself if it is UTF-8 without tabs
+/// or newlines (which the log format can't hold), otherwise a marker
+/// followed by the key in base64.
+pub fn key_from_bytes(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(text) if !text.contains(['\t', '\n', '\r']) && !text.starts_with(BINARY_KEY_MARKER) => {
+            text.to_string()
+        }
+        _ => format!("{}{}", BINARY_KEY_MARKER, general_purpose::STANDARD.encode(key)),
+    }
+}
+
+/// The bytes of a key, reversing `key_from_bytes`.
+pub fn key_to_bytes(key: &str) -> Vec<u8> {
+    key.strip_prefix(BINARY_KEY_MARKER)
+        .and_then(|b64| general_purpose::STANDARD.decode(b64).ok())
+        .unwrap_or_else(|| key.as_bytes().to_vec())
+}
+
+/// Length of the bytes a (padded) base64 string decodes to.
+fn base64_decoded_len(s: &str) -> usize {
+    let padding = s.bytes().rev().take_while(|&b| b == b'=').count();
+    (s.len() / 4) * 3 - padding.min(2)
+}
+
+/// The engine clock: seconds since the Unix epoch. TTLs, schedules and
+/// Lua's `time.now()` all read it.
+pub fn unix_now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// One-line description of a failed script call, for stats and logs.
+fn error_summary(err: &Error) -> String {
+    match err {
+        Error::Script { message, .. } => message.clone(),
+        Error::ScriptLimit(reason) => reason.clone(),
+        Error::Trigger { name, message } => format!("trigger '{}': {}", name, message),
+        e => format!("{:?}", e),
+    }
+}
+
+// Triggers whose scripts write keys with triggers of their own may nest this deep
+const MAX_TRIGGER_DEPTH: usize = 8;
+
+// Sidecars sealed with the database's key, which a key rotation re-seals
+const SIDECARS: [&str; 9] = [
+    "secindex",
+    "ftsindex",
+    "vecindex",
+    "geoindex",
+    "triggers",
+    "schedules",
+    "prefixes",
+    "scripts",
+    "libraries",
+];
+
+// Suffix of the files a key rotation or format migration writes before they
+// replace the old ones
+const STAGED_SUFFIX: &str = "staged";
+
+/// Hex SHA1 of a script's source, used as its id.
+fn script_sha1(src: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(src.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Loads a JSON-serialized side index (`<db>.<ext>`), or an empty one.
+fn load_sidecar<T: serde::de::DeserializeOwned + Default>(
+    db_path: &str,
+    ext: &str,
+    cipher: Option<&Cipher>
+) -> Result<T> {
+    let data = match fs::read(format!("{}.{}", db_path, ext)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(Error::Io(e)),
+    };
+    // A sidecar that doesn't decrypt is an error rather than an empty one,
+    // which would drop its triggers, schedules or rules on the next save
+    let data = open_sidecar(cipher, data)?;
+    Ok(serde_json::from_slice(&data).unwrap_or_default())
+}
+
+/// Where a key rotation or format migration stages the new version of a file.
+fn staged(path: &str) -> String {
+    format!("{}.{}", path, STAGED_SUFFIX)
+}
+
+/// Finishes a key rotation or format migration interrupted by a crash. Once
+/// the manifest describes the new files (`swap_pending`), the staged files are
+/// moved into place; before that, they are discarded and the database stays
+/// as it was.
+fn finish_swap(db_path: &str) -> Result<()> {
+    let Some(mut m) = Manifest::load(db_path)? else {
+        return Ok(());
+    };
+    let sidecars = SIDECARS.iter().map(|ext| format!("{}.{}", db_path, ext));
+    let files = std::iter::once(db_path.to_string()).chain(sidecars);
+    if !m.swap_pending {
+        for file in files {
+            let _ = fs::remove_file(staged(&file));
+        }
+        return Ok(());
+    }
+    // The hint indexes the old data file; it is rebuilt on open
+    let _ = fs::remove_file(format!("{}.hint", db_path));
+    for file in files {
+        if fs::metadata(staged(&file)).is_ok() {
+            fs::rename(staged(&file), &file)?;
+        }
+    }
+    m.swap_pending = false;
+    m.save(db_path)
+}
+
+/// Looks up codecs by id, failing on one that isn't registered.
+fn resolve_codecs(ids: &[String]) -> Result<Vec<Arc<dyn ValueCodec>>> {
+    ids.iter()
+        .map(|id| codec::from_id(id).ok_or_else(|| Error::UnknownCodec { id: id.clone() }))
+        .collect()
+}
+
+/// Encrypts a sidecar's contents if the database is encrypted.
+fn seal_sidecar(cipher: Option<&Cipher>, data: Vec<u8>) -> Vec<u8> {
+    match cipher {
+        Some(cipher) => cipher.seal(&data),
+        None => data,
+    }
+}
+
+/// Decrypts a sidecar's contents if the database is encrypted.
+fn open_sidecar(cipher: Option<&Cipher>, data: Vec<u8>) -> io::Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) => cipher.open(&data),
+        None => Ok(data),
+    }
+}
+
+/// Checks that `cipher` fits a database (or snapshot) at `path` encrypted as `info` says.
+fn check_key(info: Option<&EncryptionInfo>, cipher: Option<&Cipher>, path: &str) -> Result<()> {
+    match (info, cipher) {
+        (Some(info), Some(cipher)) => crypto::verify(cipher, info),
+        (Some(_), None) => Err(Error::KeyRequired),
+        (None, Some(_)) => Err(Error::InvalidQuery(format!("'{}' isn't encrypted", path))),
+        (None, None) => Ok(()),
+    }
+}
+
 /// fsyncs a file at the given path.
 fn fsync_file(path: &str) -> Result<()> {
     let file = OpenOptions::new().read(true).write(true).open(path)?;
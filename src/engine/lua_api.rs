@@ -0,0 +1,633 @@
+use std::collections::VecDeque;
+use mlua::{ Error as LuaError, FromLua, Function, IntoLua, Lua, Table, Value, Variadic };
+use crate::engine::kv::SlackbaseEngine;
+use crate::engine::agg::AggQuery;
+use crate::engine::geo::{ GeoShape, GeoUnit };
+use crate::engine::trigger::TriggerCall;
+use crate::types::Error;
+
+type LuaResult<T> = mlua::Result<T>;
+
+// Entries fetched per page by `pairs(DB)` and `slackbase.scan`
+const SCAN_PAGE: usize = 256;
+
+// Registry table of the libraries `require`d during the current script call
+const LOADED_LIBRARIES: &str = "slackbase.loaded";
+
+/// Registers the script API for a call against `engine`: the `slackbase.call`
+/// dispatcher, the `slackbase.scan` iterator, the lazy `DB` table, `require`
+/// for libraries and the GET/SET/DEL/AGG/SEARCH/VSEARCH/GEOSEARCH globals.
+///
+/// # Safety
+/// `engine` must stay valid, and not be otherwise borrowed, whenever a script
+/// calls these functions (i.e. for the duration of each script call).
+pub unsafe fn install(lua: &'static Lua, engine: *mut SlackbaseEngine) -> LuaResult<()> {
+    let globals = lua.globals();
+
+    // slackbase.call("hash_set", "user:1", "name", "Ada") -> result; errors are raised
+    let call_fn = lua.create_function(move |lua, (cmd, args): (String, Variadic<Value>)| {
+        let engine = unsafe { &mut *engine };
+        dispatch(lua, engine, &cmd, &args)
+    })?;
+    let module = lua.create_table()?;
+    module.set("call", call_fn)?;
+
+    // for key, value in slackbase.scan("user:") do ... end
+    let scan_fn = lua.create_function(move |lua, (prefix, count): (Option<String>, Option<usize>)| {
+        unsafe { cursor_iter(lua, engine, prefix, count.unwrap_or(SCAN_PAGE)) }
+    })?;
+    module.set("scan", scan_fn)?;
+    globals.set("slackbase", module)?;
+
+    // DB[key] reads through to the engine and pairs(DB) pages through it,
+    // so nothing is loaded up front.
+    let db_meta = lua.create_table()?;
+    let index_fn = lua.create_function(move |_, (_, key): (Table, String)| {
+        unsafe { Ok((*engine).get(&key)) }
+    })?;
+    db_meta.set("__index", index_fn)?;
+    let newindex_fn = lua.create_function(|_, _: Variadic<Value>| -> LuaResult<()> {
+        Err(LuaError::RuntimeError("DB is read-only; use SET/DEL or slackbase.call".into()))
+    })?;
+    db_meta.set("__newindex", newindex_fn)?;
+    let pairs_fn = lua.create_function(move |lua, db: Table| {
+        let iter = unsafe { cursor_iter(lua, engine, None, SCAN_PAGE)? };
+        Ok((iter, db, Value::Nil))
+    })?;
+    db_meta.set("__pairs", pairs_fn)?;
+    let db = lua.create_table()?;
+    db.set_metatable(Some(db_meta));
+    globals.set("DB", db)?;
+
+    // require("name") or require("name@2") -> the table the library returned.
+    // Each library runs at most once per script call.
+    lua.set_named_registry_value(LOADED_LIBRARIES, lua.create_table()?)?;
+    let require_fn = lua.create_function(move |lua, spec: String| {
+        let (name, version) = match spec.split_once('@') {
+            Some((name, v)) => {
+                let v = v.parse().map_err(|_| fail("require", format!("bad version in '{}'", spec)))?;
+                (name, Some(v))
+            }
+            None => (spec.as_str(), None),
+        };
+        let (id, chunk) = unsafe { (*engine).library_chunk(name, version) }.ok_or_else(||
+            fail("require", format!("library '{}' not found", spec))
+        )?;
+        let loaded: Table = lua.named_registry_value(LOADED_LIBRARIES)?;
+        match loaded.raw_get::<_, Value>(id.as_str())? {
+            Value::Nil => {}
+            Value::Boolean(false) => {
+                return Err(fail("require", format!("circular require of '{}'", id)));
+            }
+            exports => {
+                return Ok(exports);
+            }
+        }
+        loaded.raw_set(id.as_str(), false)?;
+        let exports = chunk.call::<_, Value>(());
+        loaded.raw_set(id.as_str(), exports.as_ref().ok())?;
+        exports
+    })?;
+    globals.set("require", require_fn)?;
+
+    // Lua strings are byte strings, so binary values come through intact
+    let get_fn = lua.create_function(move |lua, key: String| {
+        let value = unsafe { (*engine).get_bytes(key.as_bytes()) };
+        lua.create_string(value.unwrap_or_default())
+    })?;
+    globals.set("GET", get_fn)?;
+
+    let set_fn = lua.create_function(move |_, (key, val): (String, String)| {
+        unsafe {
+            (*engine)
+                .put(&key, &val)
+                .map_err(|e| write_error("SET", e))?;
+        }
+        Ok(())
+    })?;
+    globals.set("SET", set_fn)?;
+
+    let del_fn = lua.create_function(move |_, key: String| {
+        unsafe {
+            (*engine)
+                .delete(&key)
+                .map_err(|e| write_error("DEL", e))?;
+        }
+        Ok(())
+    })?;
+    globals.set("DEL", del_fn)?;
+
+    // AGG("sum amount by country") -> { DE = 42, ... }; ungrouped queries return a number
+    let agg_fn = lua.create_function(move |lua, spec: String| {
+        let query = AggQuery::parse(&spec).map_err(|e| fail("AGG", format!("{:?}", e)))?;
+        let rows = unsafe { (*engine).aggregate(&query) }.map_err(|e|
+            fail("AGG", format!("{:?}", e))
+        )?;
+        if query.group_by.is_none() {
+            return rows
+                .first()
+                .and_then(|r| r.value)
+                .into_lua(lua);
+        }
+        let table = lua.create_table()?;
+        for row in rows {
+            if let (Some(group), Some(value)) = (row.group, row.value) {
+                table.set(group, value)?;
+            }
+        }
+        Ok(Value::Table(table))
+    })?;
+    globals.set("AGG", agg_fn)?;
+
+    // SEARCH(index, query [, limit]) -> { {key=..., score=...}, ... }
+    let search_fn = lua.create_function(
+        move |lua, (index, query, limit): (String, String, Option<usize>)| {
+            let engine = unsafe { &mut *engine };
+            search(lua, engine, "SEARCH", &index, &query, limit.unwrap_or(10))
+        }
+    )?;
+    globals.set("SEARCH", search_fn)?;
+
+    // VSEARCH(index, k, {0.1, ...} [, "field=value"]) -> { {key=..., distance=...}, ... }
+    let vsearch_fn = lua.create_function(
+        move |lua, (index, k, vector, filter): (String, usize, Vec<f32>, Option<String>)| {
+            let engine = unsafe { &mut *engine };
+            vsearch(lua, engine, "VSEARCH", &index, k, &vector, filter.as_deref())
+        }
+    )?;
+    globals.set("VSEARCH", vsearch_fn)?;
+
+    // GEOSEARCH(index, lon, lat, radius, unit [, count]) -> { {key=..., distance=...}, ... }
+    let geosearch_fn = lua.create_function(
+        move |
+            lua,
+            (index, lon, lat, radius, unit, count): (String, f64, f64, f64, String, Option<usize>)
+        | {
+            let engine = unsafe { &mut *engine };
+            geosearch(lua, engine, "GEOSEARCH", &index, (lon, lat), radius, &unit, count)
+        }
+    )?;
+    globals.set("GEOSEARCH", geosearch_fn)?;
+
+    Ok(())
+}
+
+/// Runs one `slackbase.call` command. Names follow the engine methods,
+/// with Redis-style aliases for the common ones.
+fn dispatch<'lua>(
+    lua: &'lua Lua,
+    engine: &mut SlackbaseEngine,
+    cmd: &str,
+    values: &[Value<'lua>]
+) -> LuaResult<Value<'lua>> {
+    let cmd = cmd.to_lowercase();
+    let args = Args { cmd: &cmd, values };
+    let done = |res: crate::types::Result<()>| {
+        res.map(|_| Value::Boolean(true)).map_err(|e| write_error(&cmd, e))
+    };
+
+    match cmd.as_str() {
+        // Strings
+        "get" => {
+            args.arity(1, 1)?;
+            engine.get(&args.str(0)?).into_lua(lua)
+        }
+        "exists" => {
+            args.arity(1, 1)?;
+            Ok(Value::Boolean(engine.get(&args.str(0)?).is_some()))
+        }
+        "put" | "set" => {
+            args.arity(2, 2)?;
+            done(engine.put(&args.str(0)?, &args.str(1)?))
+        }
+        "putex" | "setex" => {
+            args.arity(3, 3)?;
+            let ttl = args.int(2)?;
+            if ttl < 0 {
+                return Err(fail(&cmd, "ttl must not be negative"));
+            }
+            done(engine.putex(&args.str(0)?, &args.str(1)?, ttl as u64))
+        }
+        "delete" | "del" => {
+            args.arity(1, 1)?;
+            done(engine.delete(&args.str(0)?))
+        }
+
+        // Key iteration and lookups
+        "scan" => {
+            args.arity(0, 1)?;
+            let prefix = args.opt_str(0)?;
+            rows(lua, engine.scan(prefix.as_deref(), None))
+        }
+        "scan_cursor" => {
+            // -> { cursor = next cursor (nil when done), items = { {key=..., value=...}, ... } }
+            args.arity(0, 3)?;
+            let cursor = args.opt_str(0)?.filter(|c| !c.is_empty());
+            let count = args.opt_int(1)?.unwrap_or(SCAN_PAGE as i64);
+            if count < 1 {
+                return Err(fail(&cmd, "count must be positive"));
+            }
+            let prefix = args.opt_str(2)?;
+            let (items, next) = engine.scan_page(cursor.as_deref(), prefix.as_deref(), count as usize);
+            let page = lua.create_table()?;
+            page.set("cursor", next)?;
+            page.set("items", rows(lua, items.into_iter().map(|(k, v)| (k, Some(v))).collect())?)?;
+            Ok(Value::Table(page))
+        }
+        "scan_range" => {
+            args.arity(2, 2)?;
+            let (start, end) = (args.str(0)?, args.str(1)?);
+            rows(lua, engine.scan(None, Some((&start, &end))))
+        }
+        "find" => {
+            args.arity(2, 2)?;
+            let mut keys = engine.sec_index.find(&args.str(0)?, &args.str(1)?);
+            keys.sort();
+            lua.create_sequence_from(keys).map(Value::Table)
+        }
+
+        // Hashes
+        "hash_set" | "hset" => {
+            args.arity(3, 3)?;
+            done(engine.hash_set(&args.str(0)?, &args.str(1)?, &args.str(2)?))
+        }
+        "hash_get" | "hget" => {
+            args.arity(2, 2)?;
+            decoded(lua, engine.hash_get(&args.str(0)?, &args.str(1)?))
+        }
+        "hash_del" | "hdel" => {
+            args.arity(2, 2)?;
+            done(engine.hash_del(&args.str(0)?, &args.str(1)?))
+        }
+        "hash_getall" | "hgetall" => {
+            args.arity(1, 1)?;
+            match engine.hash_getall(&args.str(0)?) {
+                Some(map) => {
+                    let table = lua.create_table()?;
+                    for (field, raw) in map {
+                        table.set(field, decoded(lua, Some(raw))?)?;
+                    }
+                    Ok(Value::Table(table))
+                }
+                None => Ok(Value::Nil),
+            }
+        }
+
+        // JSON documents
+        "json_set_field" | "json_set" => {
+            args.arity(3, 3)?;
+            done(engine.json_set_field(&args.str(0)?, &args.str(1)?, &args.str(2)?))
+        }
+        "json_get_field" | "json_get" => {
+            args.arity(2, 2)?;
+            decoded(lua, engine.json_get_field(&args.str(0)?, &args.str(1)?))
+        }
+
+        // Lists
+        "list_push" => {
+            args.arity(2, 2)?;
+            done(engine.list_push(&args.str(0)?, &args.str(1)?))
+        }
+        "list_lpush" | "lpush" => {
+            args.arity(2, 2)?;
+            done(engine.list_lpush(&args.str(0)?, &args.str(1)?))
+        }
+        "list_rpush" | "rpush" => {
+            args.arity(2, 2)?;
+            done(engine.list_rpush(&args.str(0)?, &args.str(1)?))
+        }
+        "list_lpop" | "lpop" => {
+            args.arity(1, 1)?;
+            decoded(lua, engine.list_lpop(&args.str(0)?))
+        }
+        "list_rpop" | "rpop" => {
+            args.arity(1, 1)?;
+            decoded(lua, engine.list_rpop(&args.str(0)?))
+        }
+        "list_range" | "lrange" => {
+            args.arity(3, 3)?;
+            let (start, end) = (args.int(1)? as isize, args.int(2)? as isize);
+            match engine.list_range(&args.str(0)?, start, end) {
+                Some(items) => {
+                    let table = lua.create_table()?;
+                    for (i, raw) in items.into_iter().enumerate() {
+                        table.set(i + 1, decoded(lua, Some(raw))?)?;
+                    }
+                    Ok(Value::Table(table))
+                }
+                None => Ok(Value::Nil),
+            }
+        }
+        "list_len" | "llen" => {
+            args.arity(1, 1)?;
+            Ok(Value::Integer(engine.list_len(&args.str(0)?) as i64))
+        }
+
+        // Sets
+        "set_add" | "sadd" => {
+            args.arity(2, 2)?;
+            done(engine.set_add(&args.str(0)?, &args.str(1)?))
+        }
+
+        // Queries
+        "aggregate" | "agg" => {
+            args.arity(1, 1)?;
+            let query = AggQuery::parse(&args.str(0)?).map_err(|e| fail(&cmd, format!("{:?}", e)))?;
+            let result = engine.aggregate(&query).map_err(|e| fail(&cmd, format!("{:?}", e)))?;
+            let table = lua.create_table()?;
+            for (i, row) in result.into_iter().enumerate() {
+                let entry = lua.create_table()?;
+                entry.set("group", row.group)?;
+                entry.set("value", row.value)?;
+                entry.set("count", row.count)?;
+                table.set(i + 1, entry)?;
+            }
+            Ok(Value::Table(table))
+        }
+        "search" => {
+            args.arity(2, 3)?;
+            let limit = args.opt_int(2)?.unwrap_or(10).max(0) as usize;
+            search(lua, engine, &cmd, &args.str(0)?, &args.str(1)?, limit).map(Value::Table)
+        }
+        "vsearch" => {
+            args.arity(3, 4)?;
+            let k = args.int(1)?.max(0) as usize;
+            let vector = Vec::<f32>::from_lua(values[2].clone(), lua).map_err(|_|
+                fail(&cmd, "argument #3 must be a table of numbers")
+            )?;
+            let filter = args.opt_str(3)?;
+            vsearch(lua, engine, &cmd, &args.str(0)?, k, &vector, filter.as_deref()).map(
+                Value::Table
+            )
+        }
+
+        // Geo
+        "geoadd" => {
+            args.arity(4, 4)?;
+            let (lon, lat) = (args.num(2)?, args.num(3)?);
+            done(engine.geoadd(&args.str(0)?, &args.str(1)?, lon, lat))
+        }
+        "geopos" => {
+            args.arity(2, 2)?;
+            let pos = engine.geopos(&args.str(0)?, &args.str(1)?);
+            match pos.map_err(|e| fail(&cmd, format!("{:?}", e)))? {
+                Some((lon, lat)) => lua.create_sequence_from([lon, lat]).map(Value::Table),
+                None => Ok(Value::Nil),
+            }
+        }
+        "geodist" => {
+            args.arity(3, 4)?;
+            let unit = parse_unit(&cmd, &args.opt_str(3)?.unwrap_or_else(|| "m".into()))?;
+            engine
+                .geodist(&args.str(0)?, &args.str(1)?, &args.str(2)?, unit)
+                .map_err(|e| fail(&cmd, format!("{:?}", e)))?
+                .into_lua(lua)
+        }
+        "geohash" => {
+            args.arity(2, 2)?;
+            engine
+                .geohash(&args.str(0)?, &args.str(1)?)
+                .map_err(|e| fail(&cmd, format!("{:?}", e)))?
+                .into_lua(lua)
+        }
+        "geosearch" => {
+            args.arity(5, 6)?;
+            let center = (args.num(1)?, args.num(2)?);
+            let count = args.opt_int(5)?.map(|n| n.max(0) as usize);
+            let (index, radius, unit) = (args.str(0)?, args.num(3)?, args.str(4)?);
+            geosearch(lua, engine, &cmd, &index, center, radius, &unit, count).map(Value::Table)
+        }
+
+        _ => Err(LuaError::RuntimeError(format!("unknown command '{}'", cmd))),
+    }
+}
+
+/// `{ name=..., event=..., key=..., value=..., old=... }` for a trigger's script.
+pub fn trigger_table<'lua>(lua: &'lua Lua, call: &TriggerCall) -> LuaResult<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("name", call.name)?;
+    table.set("event", call.event.as_str())?;
+    table.set("key", call.key)?;
+    table.set("value", call.value)?;
+    table.set("old", call.old_value)?;
+    Ok(table)
+}
+
+/// A generic-for iterator over the live entries under `prefix`, fetching
+/// `page` entries at a time from the engine.
+///
+/// # Safety
+/// Same contract as `install`.
+unsafe fn cursor_iter(
+    lua: &Lua,
+    engine: *mut SlackbaseEngine,
+    prefix: Option<String>,
+    page: usize
+) -> LuaResult<Function<'_>> {
+    let mut buffered = VecDeque::new();
+    let mut cursor: Option<String> = None;
+    let mut exhausted = false;
+    lua.create_function_mut(move |_, _: Variadic<Value>| {
+        if buffered.is_empty() && !exhausted {
+            let engine = unsafe { &mut *engine };
+            let (items, next) = engine.scan_page(cursor.as_deref(), prefix.as_deref(), page);
+            buffered.extend(items);
+            exhausted = next.is_none();
+            cursor = next;
+        }
+        // A nil key ends the loop
+        Ok(buffered.pop_front().unzip())
+    })
+}
+
+/// Positional arguments of a `slackbase.call` command.
+struct Args<'a, 'lua> {
+    cmd: &'a str,
+    values: &'a [Value<'lua>],
+}
+
+impl Args<'_, '_> {
+    fn arity(&self, min: usize, max: usize) -> LuaResult<()> {
+        if self.values.len() < min || self.values.len() > max {
+            return Err(fail(self.cmd, "wrong number of arguments"));
+        }
+        Ok(())
+    }
+
+    fn str(&self, i: usize) -> LuaResult<String> {
+        match self.values.get(i) {
+            Some(Value::String(s)) => Ok(s.to_str()?.to_string()),
+            Some(Value::Integer(n)) => Ok(n.to_string()),
+            Some(Value::Number(n)) => Ok(n.to_string()),
+            _ => Err(fail(self.cmd, format!("argument #{} must be a string", i + 1))),
+        }
+    }
+
+    fn opt_str(&self, i: usize) -> LuaResult<Option<String>> {
+        match self.values.get(i) {
+            None | Some(Value::Nil) => Ok(None),
+            _ => self.str(i).map(Some),
+        }
+    }
+
+    fn num(&self, i: usize) -> LuaResult<f64> {
+        match self.values.get(i) {
+            Some(Value::Integer(n)) => Ok(*n as f64),
+            Some(Value::Number(n)) => Ok(*n),
+            Some(Value::String(s)) if s.to_str().is_ok_and(|s| s.parse::<f64>().is_ok()) => {
+                Ok(s.to_str()?.parse().unwrap_or_default())
+            }
+            _ => Err(fail(self.cmd, format!("argument #{} must be a number", i + 1))),
+        }
+    }
+
+    fn int(&self, i: usize) -> LuaResult<i64> {
+        let n = self.num(i)?;
+        if n.fract() != 0.0 {
+            return Err(fail(self.cmd, format!("argument #{} must be an integer", i + 1)));
+        }
+        Ok(n as i64)
+    }
+
+    fn opt_int(&self, i: usize) -> LuaResult<Option<i64>> {
+        match self.values.get(i) {
+            None | Some(Value::Nil) => Ok(None),
+            _ => self.int(i).map(Some),
+        }
+    }
+}
+
+fn fail(cmd: &str, msg: impl std::fmt::Display) -> LuaError {
+    LuaError::RuntimeError(format!("{}: {}", cmd, msg))
+}
+
+/// A failed write. Errors from triggers the write fired keep their own message,
+/// so a chain of nested triggers doesn't wrap it once per level.
+fn write_error(cmd: &str, err: Error) -> LuaError {
+    match err {
+        Error::Script { message, .. } => LuaError::RuntimeError(message),
+        Error::Trigger { name, message } => fail(cmd, format!("trigger '{}': {}", name, message)),
+        other => fail(cmd, format!("{:?}", other)),
+    }
+}
+
+fn parse_unit(cmd: &str, unit: &str) -> LuaResult<GeoUnit> {
+    GeoUnit::parse(unit).ok_or_else(|| fail(cmd, format!("unknown unit '{}'", unit)))
+}
+
+/// Converts a stored JSON fragment (as returned by hash_get, list_range, ...) to a Lua value.
+fn decoded(lua: &Lua, raw: Option<String>) -> LuaResult<Value<'_>> {
+    match raw {
+        Some(raw) =>
+            match serde_json::from_str::<serde_json::Value>(&raw) {
+                Ok(json) => json_to_lua(lua, &json),
+                Err(_) => raw.into_lua(lua),
+            }
+        None => Ok(Value::Nil),
+    }
+}
+
+/// Converts a JSON value to Lua (objects and arrays become tables, null becomes nil).
+pub fn json_to_lua<'lua>(lua: &'lua Lua, json: &serde_json::Value) -> LuaResult<Value<'lua>> {
+    Ok(match json {
+        serde_json::Value::Null => Value::Nil,
+        serde_json::Value::Bool(b) => Value::Boolean(*b),
+        serde_json::Value::Number(n) =>
+            match n.as_i64() {
+                Some(i) => Value::Integer(i),
+                None => Value::Number(n.as_f64().unwrap_or_default()),
+            }
+        serde_json::Value::String(s) => s.as_str().into_lua(lua)?,
+        serde_json::Value::Array(items) => {
+            let table = lua.create_table()?;
+            for (i, item) in items.iter().enumerate() {
+                table.set(i + 1, json_to_lua(lua, item)?)?;
+            }
+            Value::Table(table)
+        }
+        serde_json::Value::Object(map) => {
+            let table = lua.create_table()?;
+            for (k, v) in map {
+                table.set(k.as_str(), json_to_lua(lua, v)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+/// `{ {key=..., value=...}, ... }` in key order.
+fn rows(lua: &Lua, pairs: Vec<(String, Option<String>)>) -> LuaResult<Value<'_>> {
+    let table = lua.create_table()?;
+    for (i, (key, value)) in pairs.into_iter().enumerate() {
+        let row = lua.create_table()?;
+        row.set("key", key)?;
+        row.set("value", value)?;
+        table.set(i + 1, row)?;
+    }
+    Ok(Value::Table(table))
+}
+
+fn search<'lua>(
+    lua: &'lua Lua,
+    engine: &SlackbaseEngine,
+    cmd: &str,
+    index: &str,
+    query: &str,
+    limit: usize
+) -> LuaResult<Table<'lua>> {
+    let hits = engine.search(index, query, limit).map_err(|e| fail(cmd, format!("{:?}", e)))?;
+    let table = lua.create_table()?;
+    for (i, hit) in hits.into_iter().enumerate() {
+        let row = lua.create_table()?;
+        row.set("key", hit.key)?;
+        row.set("score", hit.score)?;
+        table.set(i + 1, row)?;
+    }
+    Ok(table)
+}
+
+fn vsearch<'lua>(
+    lua: &'lua Lua,
+    engine: &SlackbaseEngine,
+    cmd: &str,
+    index: &str,
+    k: usize,
+    vector: &[f32],
+    filter: Option<&str>
+) -> LuaResult<Table<'lua>> {
+    let filter = filter.and_then(|f| f.split_once('='));
+    let hits = engine.vsearch(index, k, vector, filter).map_err(|e| fail(cmd, format!("{:?}", e)))?;
+    let table = lua.create_table()?;
+    for (i, hit) in hits.into_iter().enumerate() {
+        let row = lua.create_table()?;
+        row.set("key", hit.key)?;
+        row.set("distance", hit.distance)?;
+        table.set(i + 1, row)?;
+    }
+    Ok(table)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn geosearch<'lua>(
+    lua: &'lua Lua,
+    engine: &SlackbaseEngine,
+    cmd: &str,
+    index: &str,
+    center: (f64, f64),
+    radius: f64,
+    unit: &str,
+    count: Option<usize>
+) -> LuaResult<Table<'lua>> {
+    let unit = parse_unit(cmd, unit)?;
+    let hits = engine
+        .geosearch(index, center, GeoShape::Radius(radius), unit, count, false)
+        .map_err(|e| fail(cmd, format!("{:?}", e)))?;
+    let table = lua.create_table()?;
+    for (i, hit) in hits.into_iter().enumerate() {
+        let row = lua.create_table()?;
+        row.set("key", hit.key)?;
+        row.set("distance", hit.distance)?;
+        table.set(i + 1, row)?;
+    }
+    Ok(table)
+}
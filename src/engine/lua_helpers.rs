@@ -0,0 +1,79 @@
+use base64::{ engine::general_purpose, Engine };
+use mlua::{ Error as LuaError, Lua, Value };
+use sha1::{ Digest, Sha1 };
+use sha2::Sha256;
+use crate::engine::kv::unix_now;
+use crate::engine::lua_api::json_to_lua;
+use crate::logging::try_lua_value_to_json;
+
+type LuaResult<T> = mlua::Result<T>;
+
+/// Registers the engine-independent helper globals scripts get in the
+/// sandbox: `json`, `base64`, `sha1`, `sha256` and `time`.
+pub fn install(lua: &Lua) -> LuaResult<()> {
+    let globals = lua.globals();
+
+    // json.encode({a = 1}) -> '{"a":1}'; json.decode('[1,2]') -> {1, 2} (null becomes nil)
+    let json = lua.create_table()?;
+    json.set(
+        "encode",
+        lua.create_function(|_, value: Value| {
+            let json = try_lua_value_to_json(&value)
+                .map_err(|e| helper_error("json.encode", format!("can't encode: {}", e)))?
+                .ok_or_else(|| helper_error("json.encode", format!("can't encode a {}", value.type_name())))?;
+            serde_json::to_string(&json).map_err(|e| helper_error("json.encode", e))
+        })?
+    )?;
+    json.set(
+        "decode",
+        lua.create_function(|lua, text: mlua::String| {
+            let json: serde_json::Value = serde_json
+                ::from_slice(text.as_bytes())
+                .map_err(|e| helper_error("json.decode", e))?;
+            json_to_lua(lua, &json)
+        })?
+    )?;
+    globals.set("json", json)?;
+
+    let base64 = lua.create_table()?;
+    base64.set(
+        "encode",
+        lua.create_function(|_, data: mlua::String| {
+            Ok(general_purpose::STANDARD.encode(data.as_bytes()))
+        })?
+    )?;
+    base64.set(
+        "decode",
+        lua.create_function(|lua, text: mlua::String| {
+            let bytes = general_purpose::STANDARD
+                .decode(text.as_bytes())
+                .map_err(|e| helper_error("base64.decode", e))?;
+            lua.create_string(&bytes)
+        })?
+    )?;
+    globals.set("base64", base64)?;
+
+    // Hex digests
+    globals.set(
+        "sha1",
+        lua.create_function(|_, data: mlua::String| Ok(hex::encode(Sha1::digest(data.as_bytes()))))?
+    )?;
+    globals.set(
+        "sha256",
+        lua.create_function(|_, data: mlua::String| Ok(hex::encode(Sha256::digest(data.as_bytes()))))?
+    )?;
+
+    // time.now() -> seconds since the Unix epoch, from the clock TTLs use
+    let time = lua.create_table()?;
+    time.set(
+        "now",
+        lua.create_function(|_, ()| unix_now().map_err(|e| helper_error("time.now", format!("{:?}", e))))?
+    )?;
+    globals.set("time", time)?;
+
+    Ok(())
+}
+
+fn helper_error(func: &str, msg: impl std::fmt::Display) -> LuaError {
+    LuaError::RuntimeError(format!("{}: {}", func, msg))
+}
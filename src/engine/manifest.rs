@@ -0,0 +1,88 @@
+use serde::{ Deserialize, Serialize };
+use std::fs;
+use crate::types::{ Error, Result };
+use crate::engine::compression::Compression;
+use crate::storage::crypto::EncryptionInfo;
+
+// Bumped when the data file layout changes
+pub const FORMAT_VERSION: u32 = 2;
+// Encryption seals every line of the data file, WAL and hint, so encrypted
+// databases are marked with a version older builds refuse to open. Plaintext
+// ones keep the version those builds read.
+const PLAINTEXT_FORMAT_VERSION: u32 = 1;
+
+/// Describes how a database's values are encoded, persisted to
+/// `<db>.manifest` when the database is created.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// `Serializer::id` of the serializer every value was written with.
+    pub serializer: String,
+    /// Unix seconds.
+    pub created_at: u64,
+    /// How new values are compressed; `None` stores them as they are.
+    #[serde(default)]
+    pub compression: Option<Compression>,
+    /// Ids of the codecs values go through after compression (see `ValueCodec`).
+    #[serde(default)]
+    pub codecs: Vec<String>,
+    /// How the files are encrypted; `None` for a plaintext database.
+    #[serde(default)]
+    pub encryption: Option<EncryptionInfo>,
+    /// Set while a key rotation or format migration moves its rewritten
+    /// files into place; the rest of the manifest already describes them.
+    #[serde(default)]
+    pub swap_pending: bool,
+}
+
+impl Manifest {
+    pub fn new(serializer: &str, created_at: u64) -> Self {
+        Self {
+            format_version: PLAINTEXT_FORMAT_VERSION,
+            serializer: serializer.to_string(),
+            created_at,
+            compression: None,
+            codecs: Vec::new(),
+            encryption: None,
+            swap_pending: false,
+        }
+    }
+
+    /// The database's manifest, or `None` if it has none yet (a new
+    /// database, or one created before manifests were written).
+    pub fn load(db_path: &str) -> Result<Option<Self>> {
+        let data = match fs::read(path(db_path)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let manifest: Manifest = serde_json::from_slice(&data)?;
+        if manifest.format_version > FORMAT_VERSION {
+            return Err(
+                Error::InvalidQuery(
+                    format!(
+                        "database format version {} is newer than this build supports ({})",
+                        manifest.format_version,
+                        FORMAT_VERSION
+                    )
+                )
+            );
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Writes the manifest, replacing the old one atomically. The format
+    /// version is set from whether the database is encrypted.
+    pub fn save(&self, db_path: &str) -> Result<()> {
+        let mut manifest = self.clone();
+        manifest.format_version = if self.encryption.is_some() { FORMAT_VERSION } else { PLAINTEXT_FORMAT_VERSION };
+        let tmp = format!("{}.tmp", path(db_path));
+        fs::write(&tmp, serde_json::to_vec(&manifest)?)?;
+        fs::rename(tmp, path(db_path))?;
+        Ok(())
+    }
+}
+
+pub fn path(db_path: &str) -> String {
+    format!("{}.manifest", db_path)
+}
@@ -1,4 +1,18 @@
 pub mod kv;
 pub mod wal;
 pub mod batch;
-pub mod index;
\ No newline at end of file
+pub mod index;
+pub mod agg;
+pub mod fts;
+pub mod vector;
+pub mod geo;
+pub mod txn;
+pub mod sandbox;
+pub mod lua_api;
+pub mod lua_helpers;
+pub mod trigger;
+pub mod schedule;
+pub mod script_stats;
+pub mod manifest;
+pub mod content;
+pub mod compression;
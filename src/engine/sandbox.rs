@@ -0,0 +1,157 @@
+use std::time::{ Duration, Instant };
+use mlua::{ Error as LuaError, HookTriggers, Lua, LuaOptions, MultiValue, StdLib, Value };
+use crate::engine::lua_helpers;
+use crate::types::{ Error, Result, ScriptLimits };
+
+// Instructions between limit checks
+const HOOK_INTERVAL: u32 = 1000;
+
+// Base-library functions that can load code from strings or files
+const REMOVED_GLOBALS: &[&str] = &["load", "loadfile", "dofile"];
+
+/// Limit state for the script call in progress, kept in the Lua app data.
+struct Budget {
+    limits: ScriptLimits,
+    executed: u64,
+    deadline: Option<Instant>,
+    tripped: Option<String>,
+}
+
+/// Creates the Lua state scripts run in: only the table, string, math, utf8
+/// and coroutine libraries (no os/io/debug/package), without `load`,
+/// `loadfile` or `dofile`, with error-catching functions that can't
+/// swallow a limit error, plus the json/base64/hash/time helpers.
+pub fn new_lua() -> Result<Lua> {
+    let libs = StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8 | StdLib::COROUTINE;
+    let lua = Lua::new_with(libs, LuaOptions::default())?;
+    let globals = lua.globals();
+    for name in REMOVED_GLOBALS {
+        globals.set(*name, mlua::Nil)?;
+    }
+    wrap_protected_call(&lua, &globals, "pcall")?;
+    wrap_protected_call(&lua, &globals, "xpcall")?;
+    let coroutine: mlua::Table = globals.get("coroutine")?;
+    wrap_protected_call(&lua, &coroutine, "resume")?;
+    drop(coroutine);
+    drop(globals);
+    lua_helpers::install(&lua)?;
+    Ok(lua)
+}
+
+/// Wraps `table[name]` so that once a limit has tripped, the error is raised
+/// again after the call instead of being returned to the script. Errors raised
+/// by the engine API are handed to the script as their message string.
+fn wrap_protected_call(lua: &Lua, table: &mlua::Table, name: &str) -> Result<()> {
+    let raw: mlua::Function = table.get(name)?;
+    let key = lua.create_registry_value(raw)?;
+    let wrapped = lua.create_function(move |lua, args: MultiValue| {
+        let raw: mlua::Function = lua.registry_value(&key)?;
+        let res: MultiValue = raw.call(args)?;
+        if let Some(reason) = tripped(lua) {
+            return Err(LuaError::RuntimeError(reason));
+        }
+        let values = res
+            .into_iter()
+            .map(|v| match v {
+                Value::Error(e) => lua.create_string(error_message(&e)).map(Value::String),
+                v => Ok(v),
+            })
+            .collect::<mlua::Result<Vec<_>>>()?;
+        Ok(MultiValue::from_vec(values))
+    })?;
+    table.set(name, wrapped)?;
+    Ok(())
+}
+
+fn tripped(lua: &Lua) -> Option<String> {
+    lua.app_data_ref::<Budget>().and_then(|b| b.tripped.clone())
+}
+
+/// Installs `limits` for the next script call.
+pub fn arm(lua: &Lua, limits: &ScriptLimits) -> Result<()> {
+    let deadline = limits.timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
+    lua.set_app_data(Budget { limits: limits.clone(), executed: 0, deadline, tripped: None });
+
+    if limits.max_instructions.is_some() || deadline.is_some() {
+        lua.set_hook(HookTriggers::new().every_nth_instruction(HOOK_INTERVAL), |lua, _| {
+            let Some(mut budget) = lua.app_data_mut::<Budget>() else {
+                return Ok(());
+            };
+            if budget.tripped.is_none() {
+                budget.executed += HOOK_INTERVAL as u64;
+                if let Some(max) = budget.limits.max_instructions && budget.executed > max {
+                    budget.tripped = Some(format!("instruction limit of {} exceeded", max));
+                } else if budget.deadline.is_some_and(|d| Instant::now() >= d) {
+                    let ms = budget.limits.timeout_ms.unwrap_or_default();
+                    budget.tripped = Some(format!("timeout of {} ms exceeded", ms));
+                }
+            }
+            match &budget.tripped {
+                Some(reason) => Err(LuaError::RuntimeError(reason.clone())),
+                None => Ok(()),
+            }
+        });
+    }
+    if let Some(max) = limits.max_memory {
+        lua.set_memory_limit(lua.used_memory() + max)?;
+    }
+    Ok(())
+}
+
+/// Whether a script call is in progress (its limits are installed).
+pub fn armed(lua: &Lua) -> bool {
+    lua.app_data_ref::<Budget>().is_some()
+}
+
+/// Removes the limits installed by `arm`. Returns why the call was stopped,
+/// if it hit a limit (`err` is the error the call returned, if any).
+pub fn disarm(lua: &Lua, err: Option<&LuaError>) -> Option<String> {
+    lua.remove_hook();
+    lua.set_memory_limit(0).ok();
+    let budget = lua.remove_app_data::<Budget>()?;
+    if budget.tripped.is_some() {
+        return budget.tripped;
+    }
+    match (err, budget.limits.max_memory) {
+        (Some(e), Some(max)) if is_memory_error(e) => {
+            Some(format!("memory limit of {} bytes exceeded", max))
+        }
+        _ => None,
+    }
+}
+
+/// The message of the innermost error, without mlua's wrapping and traceback.
+pub fn error_message(err: &LuaError) -> String {
+    match err {
+        LuaError::CallbackError { cause, .. } => error_message(cause),
+        LuaError::RuntimeError(msg) => msg.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Converts a failed compile or call into `Error::Script`, separating the
+/// message from the Lua stack traceback.
+pub fn script_error(err: LuaError) -> Error {
+    let (message, traceback) = match &err {
+        LuaError::CallbackError { traceback, cause } => {
+            let trace = traceback.strip_prefix("stack traceback:\n").unwrap_or(traceback);
+            (error_message(cause), Some(trace.to_string()))
+        }
+        LuaError::RuntimeError(msg) =>
+            match msg.split_once("\nstack traceback:\n") {
+                Some((message, trace)) => (message.to_string(), Some(trace.to_string())),
+                None => (msg.clone(), None),
+            }
+        LuaError::SyntaxError { message, .. } => (message.clone(), None),
+        other => (other.to_string(), None),
+    };
+    Error::Script { message, traceback }
+}
+
+fn is_memory_error(err: &LuaError) -> bool {
+    match err {
+        LuaError::MemoryError(_) => true,
+        LuaError::CallbackError { cause, .. } => is_memory_error(cause),
+        _ => false,
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use serde::{ Deserialize, Serialize };
+use crate::types::{ Error, Result };
+
+// How far ahead `Cron::next_after` looks before giving up on an expression
+// that can never match (e.g. February 30th).
+const CRON_SEARCH_DAYS: u64 = 366 * 5;
+
+/// When a schedule fires.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleSpec {
+    /// Every `n` seconds, counted from the previous run (or from when the schedule was added).
+    Every(u64),
+    /// A five-field cron expression (minute hour day-of-month month day-of-week), in UTC.
+    Cron(String),
+}
+
+impl ScheduleSpec {
+    /// Parses an interval such as `30s`, `15m`, `1h` or `7d` (bare numbers are seconds).
+    pub fn parse_interval(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidQuery(format!("invalid interval '{}'", s));
+        let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+            Some(i) => s.split_at(i),
+            None => (s, "s"),
+        };
+        let n: u64 = num.parse().map_err(|_| invalid())?;
+        let secs = match unit {
+            "s" => n,
+            "m" => n.checked_mul(60).ok_or_else(invalid)?,
+            "h" => n.checked_mul(3600).ok_or_else(invalid)?,
+            "d" => n.checked_mul(86400).ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        };
+        if secs == 0 {
+            return Err(invalid());
+        }
+        Ok(ScheduleSpec::Every(secs))
+    }
+
+    /// Validates a cron expression.
+    pub fn parse_cron(expr: &str) -> Result<Self> {
+        Cron::parse(expr)?;
+        Ok(ScheduleSpec::Cron(expr.to_string()))
+    }
+
+    /// The first time after `now` (unix seconds) the schedule fires.
+    pub fn next_after(&self, now: u64) -> Option<u64> {
+        match self {
+            ScheduleSpec::Every(secs) => now.checked_add(*secs),
+            ScheduleSpec::Cron(expr) => Cron::parse(expr).ok()?.next_after(now),
+        }
+    }
+}
+
+impl std::fmt::Display for ScheduleSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleSpec::Every(secs) => write!(f, "every {}s", secs),
+            ScheduleSpec::Cron(expr) => write!(f, "cron {}", expr),
+        }
+    }
+}
+
+/// Outcome of a schedule's most recent run.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunRecord {
+    /// Unix seconds.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    /// `ok`, or the error the script failed with.
+    pub outcome: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Schedule {
+    pub spec: ScheduleSpec,
+    /// Name or SHA1 of the script, resolved on each run.
+    pub script: String,
+    pub keys: Vec<String>,
+    pub args: Vec<String>,
+    /// Unix seconds of the next run.
+    pub next_run: Option<u64>,
+    pub last_run: Option<RunRecord>,
+}
+
+/// Named schedules, persisted to `<db>.schedules`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Schedules {
+    schedules: BTreeMap<String, Schedule>,
+}
+
+impl Schedules {
+    pub fn add(&mut self, name: &str, schedule: Schedule) -> Result<()> {
+        if self.schedules.contains_key(name) {
+            return Err(Error::InvalidQuery(format!("schedule '{}' already exists", name)));
+        }
+        self.schedules.insert(name.to_string(), schedule);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.schedules.remove(name).is_some()
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Schedule> {
+        self.schedules.get_mut(name)
+    }
+
+    pub fn definitions(&self) -> Vec<(String, Schedule)> {
+        self.schedules
+            .iter()
+            .map(|(name, s)| (name.clone(), s.clone()))
+            .collect()
+    }
+
+    /// Names of the schedules due at `now`, by name.
+    pub fn due(&self, now: u64) -> Vec<String> {
+        self.schedules
+            .iter()
+            .filter(|(_, s)| s.next_run.is_some_and(|t| t <= now))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
+
+/// A parsed cron expression: the allowed values of each field.
+struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    // Standard cron: if both day fields are restricted, either may match
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    fn parse(expr: &str) -> Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(Error::InvalidQuery(format!("cron expression needs 5 fields: '{}'", expr)));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        // Both 0 and 7 mean Sunday
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        Ok(Cron {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            days_restricted: !day.starts_with('*'),
+            weekdays_restricted: !weekday.starts_with('*'),
+        })
+    }
+
+    fn day_matches(&self, day: u32, weekday: u32) -> bool {
+        let by_day = self.days[day as usize];
+        let by_weekday = self.weekdays[weekday as usize];
+        if self.days_restricted && self.weekdays_restricted {
+            by_day || by_weekday
+        } else {
+            by_day && by_weekday
+        }
+    }
+
+    /// The first matching minute strictly after `now`.
+    fn next_after(&self, now: u64) -> Option<u64> {
+        let mut t = (now / 60 + 1) * 60;
+        let limit = now + CRON_SEARCH_DAYS * 86400;
+        while t <= limit {
+            let days = t / 86400;
+            let (_, month, day) = civil_from_days(days as i64);
+            // 1970-01-01 was a Thursday
+            let weekday = ((days + 4) % 7) as u32;
+            if !self.months[month as usize] || !self.day_matches(day, weekday) {
+                t = (days + 1) * 86400;
+                continue;
+            }
+            let hour = ((t % 86400) / 3600) as usize;
+            if !self.hours[hour] {
+                t = (t / 3600 + 1) * 3600;
+                continue;
+            }
+            let minute = ((t % 3600) / 60) as usize;
+            if self.minutes[minute] {
+                return Some(t);
+            }
+            t += 60;
+        }
+        None
+    }
+}
+
+/// Parses one cron field (`*`, `5`, `1-5`, `*/15`, `0-30/10`, or a comma list
+/// of these) into a table indexed by value.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>> {
+    let invalid = || Error::InvalidQuery(format!("invalid cron field '{}'", field));
+    let mut allowed = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ =>
+                match range.split_once('-') {
+                    Some((a, b)) => (
+                        a.parse().map_err(|_| invalid())?,
+                        b.parse().map_err(|_| invalid())?,
+                    ),
+                    None => {
+                        let v = range.parse().map_err(|_| invalid())?;
+                        // `5/10` means from 5 to the end in steps of 10
+                        (v, if part.contains('/') { max } else { v })
+                    }
+                }
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for v in (start..=end).step_by(step as usize) {
+            allowed[v as usize] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+/// (year, month, day) of a count of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Formats unix seconds as `YYYY-MM-DD HH:MM:SS` (UTC).
+pub fn format_timestamp(ts: u64) -> String {
+    let (year, month, day) = civil_from_days((ts / 86400) as i64);
+    let secs = ts % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+
+// Durations kept per script for the p99
+const DURATION_WINDOW: usize = 1000;
+// Entries kept in the slow log
+const SLOW_LOG_LEN: usize = 128;
+// Default slow log threshold, in milliseconds
+const DEFAULT_SLOW_MS: u64 = 100;
+
+/// Execution counters for one script (kept in memory, reset on restart).
+#[derive(Debug, Default, Clone)]
+pub struct ScriptStats {
+    pub calls: u64,
+    pub errors: u64,
+    pub total_us: u64,
+    // Most recent durations, oldest first
+    recent_us: VecDeque<u64>,
+    pub last_error: Option<String>,
+}
+
+impl ScriptStats {
+    pub fn record(&mut self, duration_us: u64, error: Option<String>) {
+        self.calls += 1;
+        self.total_us += duration_us;
+        if self.recent_us.len() == DURATION_WINDOW {
+            self.recent_us.pop_front();
+        }
+        self.recent_us.push_back(duration_us);
+        if error.is_some() {
+            self.errors += 1;
+            self.last_error = error;
+        }
+    }
+
+    pub fn avg_us(&self) -> u64 {
+        self.total_us.checked_div(self.calls).unwrap_or(0)
+    }
+
+    /// 99th percentile over the last `DURATION_WINDOW` calls.
+    pub fn p99_us(&self) -> u64 {
+        if self.recent_us.is_empty() {
+            return 0;
+        }
+        let mut sorted: Vec<u64> = self.recent_us.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (sorted.len() * 99).div_ceil(100);
+        sorted[rank.saturating_sub(1)]
+    }
+}
+
+/// A script call that took longer than the slow log threshold.
+#[derive(Debug, Clone)]
+pub struct SlowEntry {
+    pub id: u64,
+    /// Unix seconds.
+    pub started_at: u64,
+    pub duration_us: u64,
+    pub script: String,
+    pub keys: Vec<String>,
+    pub args: Vec<String>,
+    /// `ok`, or the error the call failed with.
+    pub outcome: String,
+}
+
+/// The most recent slow script calls, newest first.
+#[derive(Debug)]
+pub struct SlowLog {
+    pub threshold_ms: u64,
+    entries: VecDeque<SlowEntry>,
+    next_id: u64,
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        Self { threshold_ms: DEFAULT_SLOW_MS, entries: VecDeque::new(), next_id: 0 }
+    }
+}
+
+impl SlowLog {
+    pub fn is_slow(&self, duration_us: u64) -> bool {
+        duration_us >= self.threshold_ms.saturating_mul(1000)
+    }
+
+    /// Adds an entry (its `id` is assigned here), dropping the oldest if full.
+    pub fn push(&mut self, mut entry: SlowEntry) {
+        entry.id = self.next_id;
+        self.next_id += 1;
+        if self.entries.len() == SLOW_LOG_LEN {
+            self.entries.pop_back();
+        }
+        self.entries.push_front(entry);
+    }
+
+    /// Up to `count` entries, newest first.
+    pub fn entries(&self, count: usize) -> Vec<SlowEntry> {
+        self.entries.iter().take(count).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
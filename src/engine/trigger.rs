@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use serde::{ Deserialize, Serialize };
+use crate::types::{ Error, Result };
+
+/// When a trigger runs relative to a key write.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerEvent {
+    /// Before a put; the script can veto the write or replace the value.
+    BeforePut,
+    AfterPut,
+    AfterDelete,
+    /// When a key is found to have outlived its TTL.
+    OnExpire,
+}
+
+impl TriggerEvent {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "before_put" => Some(TriggerEvent::BeforePut),
+            "after_put" => Some(TriggerEvent::AfterPut),
+            "after_delete" => Some(TriggerEvent::AfterDelete),
+            "on_expire" => Some(TriggerEvent::OnExpire),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TriggerEvent::BeforePut => "before_put",
+            TriggerEvent::AfterPut => "after_put",
+            TriggerEvent::AfterDelete => "after_delete",
+            TriggerEvent::OnExpire => "on_expire",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TriggerDef {
+    pub event: TriggerEvent,
+    /// Key pattern; `*` matches any run of characters and `?` a single one.
+    pub pattern: String,
+    /// Name or SHA1 of the script to run, resolved when the trigger fires.
+    pub script: String,
+}
+
+/// The trigger being run, exposed to its script as the `TRIGGER` table.
+pub struct TriggerCall<'a> {
+    pub name: &'a str,
+    pub event: TriggerEvent,
+    pub key: &'a str,
+    /// The value being written (before_put, after_put).
+    pub value: Option<&'a str>,
+    /// The value the key held before (after_put, after_delete, on_expire).
+    pub old_value: Option<&'a str>,
+}
+
+/// Named triggers, persisted to `<db>.triggers`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Triggers {
+    triggers: BTreeMap<String, TriggerDef>,
+}
+
+impl Triggers {
+    pub fn create(&mut self, name: &str, def: TriggerDef) -> Result<()> {
+        if self.triggers.contains_key(name) {
+            return Err(Error::InvalidQuery(format!("trigger '{}' already exists", name)));
+        }
+        self.triggers.insert(name.to_string(), def);
+        Ok(())
+    }
+
+    pub fn drop_trigger(&mut self, name: &str) -> bool {
+        self.triggers.remove(name).is_some()
+    }
+
+    /// All triggers, by name.
+    pub fn definitions(&self) -> Vec<(String, TriggerDef)> {
+        self.triggers
+            .iter()
+            .map(|(name, def)| (name.clone(), def.clone()))
+            .collect()
+    }
+
+    /// Triggers for `event` whose pattern matches `key`, by name.
+    pub fn matching(&self, event: TriggerEvent, key: &str) -> Vec<(String, TriggerDef)> {
+        self.triggers
+            .iter()
+            .filter(|(_, def)| def.event == event && glob_match(&def.pattern, key))
+            .map(|(name, def)| (name.clone(), def.clone()))
+            .collect()
+    }
+
+    pub fn any(&self, event: TriggerEvent, key: &str) -> bool {
+        self.triggers.values().any(|def| def.event == event && glob_match(&def.pattern, key))
+    }
+}
+
+/// Matches `text` against a pattern where `*` is any run of characters
+/// (including none) and `?` is exactly one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // Position of the last `*` and the text position it is currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            // Let the last `*` swallow one more character
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+/// A buffered write: a value with optional expiry, or `None` for a delete.
+pub type TxnWrite = Option<(String, Option<u64>)>;
+
+/// Writes made by a running script. They are only applied to the log
+/// once the script returns successfully; on error they are discarded.
+#[derive(Debug, Default)]
+pub struct ScriptTxn {
+    // In order of execution
+    ops: Vec<(String, TxnWrite)>,
+    // Latest pending value per key, for reads inside the script
+    latest: HashMap<String, Option<String>>,
+}
+
+impl ScriptTxn {
+    pub fn put(&mut self, key: &str, value: &str, expires_at: Option<u64>) {
+        self.latest.insert(key.to_string(), Some(value.to_string()));
+        self.ops.push((key.to_string(), Some((value.to_string(), expires_at))));
+    }
+
+    pub fn delete(&mut self, key: &str) {
+        self.latest.insert(key.to_string(), None);
+        self.ops.push((key.to_string(), None));
+    }
+
+    /// `Some(value)` if the transaction wrote `key` (`Some(None)` if it deleted it).
+    pub fn get(&self, key: &str) -> Option<Option<&str>> {
+        self.latest.get(key).map(|v| v.as_deref())
+    }
+
+    /// Keys written (or deleted) by the transaction.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.latest.keys()
+    }
+
+    /// Latest pending value per key (None for a delete), for constraint checks.
+    pub fn pending(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.latest.iter().map(|(k, v)| (k.as_str(), v.as_deref()))
+    }
+
+    pub fn into_ops(self) -> Vec<(String, TxnWrite)> {
+        self.ops
+    }
+}
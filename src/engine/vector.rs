@@ -0,0 +1,441 @@
+use std::cmp::{ Ordering, Reverse };
+use std::collections::{ BinaryHeap, HashMap, HashSet };
+use serde::{ Serialize, Deserialize };
+use crate::types::{ Error, Result };
+
+// HNSW tuning
+const M: usize = 16;
+const M0: usize = 2 * M;
+const EF_CONSTRUCTION: usize = 100;
+const EF_SEARCH: usize = 64;
+
+/// Distance metric. Lower distances are better for all of them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// 1 - cosine similarity
+    Cosine,
+    /// Negated dot product
+    Dot,
+    /// Euclidean distance
+    L2,
+}
+
+impl Metric {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "cosine" => Some(Metric::Cosine),
+            "dot" => Some(Metric::Dot),
+            "l2" => Some(Metric::L2),
+            _ => None,
+        }
+    }
+
+    fn distance(self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Cosine => {
+                let (mut dot, mut na, mut nb) = (0.0f32, 0.0f32, 0.0f32);
+                for (x, y) in a.iter().zip(b) {
+                    dot += x * y;
+                    na += x * x;
+                    nb += y * y;
+                }
+                if na == 0.0 || nb == 0.0 {
+                    return 1.0;
+                }
+                1.0 - dot / (na.sqrt() * nb.sqrt())
+            }
+            Metric::Dot =>
+                -a
+                    .iter()
+                    .zip(b)
+                    .map(|(x, y)| x * y)
+                    .sum::<f32>(),
+            Metric::L2 =>
+                a
+                    .iter()
+                    .zip(b)
+                    .map(|(x, y)| (x - y) * (x - y))
+                    .sum::<f32>()
+                    .sqrt(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorIndexKind {
+    /// Exact brute-force scan.
+    Flat,
+    /// Approximate nearest neighbours over a navigable small-world graph.
+    Hnsw,
+}
+
+/// Declaration of a vector index over a JSON array field of keys with `prefix`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorIndexDef {
+    pub field: String,
+    pub prefix: Option<String>,
+    pub dims: usize,
+    pub metric: Metric,
+    pub kind: VectorIndexKind,
+}
+
+/// A nearest-neighbour result.
+#[derive(Debug, Clone)]
+pub struct VectorHit {
+    pub key: String,
+    pub distance: f32,
+}
+
+/// Parses `0.1,0.2,0.3` or `[0.1, 0.2, 0.3]`.
+pub fn parse_vector(src: &str) -> Result<Vec<f32>> {
+    src.trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|x| {
+            x.trim()
+                .parse::<f32>()
+                .map_err(|_| Error::InvalidQuery(format!("bad vector component '{}'", x)))
+        })
+        .collect()
+}
+
+// Heap entry ordered by distance
+#[derive(Clone, Copy, PartialEq)]
+struct Cand(f32, u32);
+
+impl Eq for Cand {}
+
+impl PartialOrd for Cand {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Cand {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Hnsw {
+    // node id => level => neighbour ids
+    links: Vec<Vec<Vec<u32>>>,
+    entry: Option<u32>,
+    max_level: usize,
+    rng: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct VecIndex {
+    def: VectorIndexDef,
+    // node id => vector / owning key (None once the key is removed)
+    points: Vec<Vec<f32>>,
+    keys: Vec<Option<String>>,
+    ids: HashMap<String, u32>,
+    hnsw: Option<Hnsw>,
+}
+
+impl Hnsw {
+    fn random_level(&mut self) -> usize {
+        // xorshift64
+        let mut x = self.rng.max(1);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng = x;
+        let u = ((x >> 11) as f64) / ((1u64 << 53) as f64);
+        let ml = 1.0 / (M as f64).ln();
+        (-u.max(f64::MIN_POSITIVE).ln() * ml) as usize
+    }
+}
+
+impl VecIndex {
+    fn new(def: VectorIndexDef) -> Self {
+        let hnsw = match def.kind {
+            VectorIndexKind::Flat => None,
+            VectorIndexKind::Hnsw => Some(Hnsw { rng: 0x9e3779b97f4a7c15, ..Default::default() }),
+        };
+        Self { def, points: Vec::new(), keys: Vec::new(), ids: HashMap::new(), hnsw }
+    }
+
+    fn applies_to(&self, key: &str) -> bool {
+        self.def.prefix.as_deref().is_none_or(|p| key.starts_with(p))
+    }
+
+    fn dist(&self, q: &[f32], id: u32) -> f32 {
+        self.def.metric.distance(q, &self.points[id as usize])
+    }
+
+    fn live_count(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(id) = self.ids.remove(key) {
+            // Graph nodes stay as waypoints until the index is vacuumed
+            self.keys[id as usize] = None;
+        }
+    }
+
+    fn insert(&mut self, key: &str, vector: Vec<f32>) {
+        self.remove(key);
+        let id = self.points.len() as u32;
+        self.points.push(vector);
+        self.keys.push(Some(key.to_string()));
+        self.ids.insert(key.to_string(), id);
+        if self.hnsw.is_some() {
+            self.hnsw_insert(id);
+        }
+    }
+
+    /// Best-first search of one graph layer, returning up to `ef` closest nodes.
+    fn search_layer(&self, q: &[f32], entry: &[u32], ef: usize, level: usize) -> Vec<Cand> {
+        let hnsw = self.hnsw.as_ref().unwrap();
+        let mut visited: HashSet<u32> = entry.iter().copied().collect();
+        let mut candidates: BinaryHeap<Reverse<Cand>> = BinaryHeap::new();
+        let mut results: BinaryHeap<Cand> = BinaryHeap::new();
+        for &e in entry {
+            let c = Cand(self.dist(q, e), e);
+            candidates.push(Reverse(c));
+            results.push(c);
+        }
+        while let Some(Reverse(c)) = candidates.pop() {
+            if results.len() >= ef && c.0 > results.peek().unwrap().0 {
+                break;
+            }
+            let Some(neighbours) = hnsw.links[c.1 as usize].get(level) else {
+                continue;
+            };
+            for &n in neighbours {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let d = self.dist(q, n);
+                if results.len() < ef || d < results.peek().unwrap().0 {
+                    candidates.push(Reverse(Cand(d, n)));
+                    results.push(Cand(d, n));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        results.into_sorted_vec()
+    }
+
+    fn hnsw_insert(&mut self, id: u32) {
+        let q = self.points[id as usize].clone();
+        let hnsw = self.hnsw.as_mut().unwrap();
+        let level = hnsw.random_level();
+        hnsw.links.push(vec![Vec::new(); level + 1]);
+        let Some(mut ep) = hnsw.entry else {
+            hnsw.entry = Some(id);
+            hnsw.max_level = level;
+            return;
+        };
+        let max_level = hnsw.max_level;
+
+        // Greedy descent through the layers above the new node
+        for l in (level + 1..=max_level).rev() {
+            ep = self.search_layer(&q, &[ep], 1, l)[0].1;
+        }
+        let mut entry = vec![ep];
+        for l in (0..=level.min(max_level)).rev() {
+            let found = self.search_layer(&q, &entry, EF_CONSTRUCTION, l);
+            let cap = if l == 0 { M0 } else { M };
+            let neighbours: Vec<u32> = found
+                .iter()
+                .filter(|c| c.1 != id)
+                .take(M)
+                .map(|c| c.1)
+                .collect();
+            for &n in &neighbours {
+                let links = &self.hnsw.as_ref().unwrap().links[n as usize][l];
+                let mut updated: Vec<u32> = links.clone();
+                updated.push(id);
+                if updated.len() > cap {
+                    let base = self.points[n as usize].clone();
+                    updated.sort_by(|a, b| self.dist(&base, *a).total_cmp(&self.dist(&base, *b)));
+                    updated.truncate(cap);
+                }
+                self.hnsw.as_mut().unwrap().links[n as usize][l] = updated;
+            }
+            self.hnsw.as_mut().unwrap().links[id as usize][l] = neighbours;
+            entry = found
+                .iter()
+                .map(|c| c.1)
+                .collect();
+        }
+        let hnsw = self.hnsw.as_mut().unwrap();
+        if level > hnsw.max_level {
+            hnsw.max_level = level;
+            hnsw.entry = Some(id);
+        }
+    }
+
+    /// Nearest points, closest first (at least `k` when available). `allowed` restricts
+    /// the search to those keys (exact scan), which is how index filters are applied.
+    fn search(&self, q: &[f32], k: usize, allowed: Option<&HashSet<String>>) -> Vec<VectorHit> {
+        let mut found: Vec<Cand> = match (&self.hnsw, allowed) {
+            (Some(hnsw), None) => {
+                let Some(mut ep) = hnsw.entry else {
+                    return Vec::new();
+                };
+                for l in (1..=hnsw.max_level).rev() {
+                    ep = self.search_layer(q, &[ep], 1, l)[0].1;
+                }
+                // Removed keys stay in the graph until a vacuum and are dropped
+                // below, so the beam is widened to still yield `k` live hits
+                let tombstones = self.points.len() - self.live_count();
+                self.search_layer(q, &[ep], EF_SEARCH.max(k) + tombstones, 0)
+            }
+            (_, Some(allowed)) =>
+                allowed
+                    .iter()
+                    .filter_map(|key| self.ids.get(key))
+                    .map(|&id| Cand(self.dist(q, id), id))
+                    .collect(),
+            (None, None) =>
+                self.ids
+                    .values()
+                    .map(|&id| Cand(self.dist(q, id), id))
+                    .collect(),
+        };
+        found.sort();
+        found
+            .into_iter()
+            .filter_map(|c| {
+                self.keys[c.1 as usize]
+                    .as_ref()
+                    .map(|key| VectorHit { key: key.clone(), distance: c.0 })
+            })
+            .collect()
+    }
+
+    /// Rebuilds storage and graph from live points only.
+    fn vacuum(&mut self) {
+        let live: Vec<(String, Vec<f32>)> = self.ids
+            .iter()
+            .map(|(key, &id)| (key.clone(), self.points[id as usize].clone()))
+            .collect();
+        let mut fresh = VecIndex::new(self.def.clone());
+        for (key, vector) in live {
+            fresh.insert(&key, vector);
+        }
+        *self = fresh;
+    }
+}
+
+/// Named vector indexes over JSON array fields holding embeddings.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct VectorIndexes {
+    indexes: HashMap<String, VecIndex>,
+}
+
+/// Extracts a `dims`-long number array from `field` of a JSON object.
+fn extract_vector(json: &str, field: &str, dims: usize) -> Option<Vec<f32>> {
+    let value: serde_json::Value = serde_json::from_str(json).ok()?;
+    let arr = value.get(field)?.as_array()?;
+    if arr.len() != dims {
+        return None;
+    }
+    arr.iter()
+        .map(|v| v.as_f64().map(|x| x as f32))
+        .collect()
+}
+
+impl VectorIndexes {
+    pub fn new() -> Self {
+        Self { indexes: HashMap::new() }
+    }
+
+    pub fn create(&mut self, name: &str, def: VectorIndexDef) -> Result<()> {
+        if self.indexes.contains_key(name) {
+            return Err(Error::InvalidQuery(format!("vector index '{}' already exists", name)));
+        }
+        if def.dims == 0 {
+            return Err(Error::InvalidQuery("vector dimensions must be > 0".into()));
+        }
+        self.indexes.insert(name.to_string(), VecIndex::new(def));
+        Ok(())
+    }
+
+    pub fn drop_index(&mut self, name: &str) -> bool {
+        self.indexes.remove(name).is_some()
+    }
+
+    pub fn definitions(&self) -> Vec<(String, VectorIndexDef, usize)> {
+        let mut out: Vec<_> = self.indexes
+            .iter()
+            .map(|(name, idx)| (name.clone(), idx.def.clone(), idx.live_count()))
+            .collect();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
+    pub fn covers(&self, key: &str) -> bool {
+        self.indexes.values().any(|idx| idx.applies_to(key))
+    }
+
+    /// Re-indexes `key` in every applicable index (None removes it).
+    pub fn update(&mut self, key: &str, new_json: Option<&str>) {
+        for idx in self.indexes.values_mut() {
+            if !idx.applies_to(key) {
+                continue;
+            }
+            match new_json.and_then(|j| extract_vector(j, &idx.def.field, idx.def.dims)) {
+                Some(vector) => idx.insert(key, vector),
+                None => idx.remove(key),
+            }
+        }
+    }
+
+    /// Indexes a document into one index only (used when building a new index).
+    pub fn add_to(&mut self, name: &str, key: &str, json: &str) {
+        if let Some(idx) = self.indexes.get_mut(name)
+            && idx.applies_to(key)
+            && let Some(vector) = extract_vector(json, &idx.def.field, idx.def.dims)
+        {
+            idx.insert(key, vector);
+        }
+    }
+
+    /// Drops keys that no longer exist and rebuilds indexes left mostly tombstones.
+    pub fn retain<F: Fn(&str) -> bool>(&mut self, keep: F) {
+        for idx in self.indexes.values_mut() {
+            let stale: Vec<String> = idx.ids
+                .keys()
+                .filter(|k| !keep(k))
+                .cloned()
+                .collect();
+            for key in stale {
+                idx.remove(&key);
+            }
+            if idx.points.len() > 2 * idx.live_count() {
+                idx.vacuum();
+            }
+        }
+    }
+
+    /// Nearest neighbours of `query` in index `name`, closest first. Returns at
+    /// least `k` hits when available; the caller trims after its own filtering.
+    pub fn search(
+        &self,
+        name: &str,
+        query: &[f32],
+        k: usize,
+        allowed: Option<&HashSet<String>>
+    ) -> Result<Vec<VectorHit>> {
+        let idx = self.indexes.get(name).ok_or(Error::NotFound)?;
+        if query.len() != idx.def.dims {
+            return Err(
+                Error::InvalidQuery(
+                    format!("expected {} dimensions, got {}", idx.def.dims, query.len())
+                )
+            );
+        }
+        Ok(idx.search(query, k, allowed))
+    }
+}
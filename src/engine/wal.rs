@@ -1,13 +1,18 @@
 use std::fs::{OpenOptions, File};
 use std::io::{Write, BufWriter, BufRead, BufReader};
+use crate::storage::crypto::{self, Cipher};
+use crate::storage::file;
 
 pub struct WAL {
     writer: BufWriter<File>,
     path: String,
+    // Seals each record, for an encrypted database
+    cipher: Option<Cipher>,
 }
 
 impl WAL {
-    pub fn open(path: &str) -> std::io::Result<Self> {
+    pub fn open(path: &str, cipher: Option<Cipher>) -> std::io::Result<Self> {
+        file::truncate_torn_tail(path, cipher.as_ref())?;
         let file = OpenOptions::new()
             .create(true)
             .append(true)
@@ -15,12 +20,13 @@ impl WAL {
         Ok(Self {
             writer: BufWriter::new(file),
             path: path.to_string(),
+            cipher,
         })
     }
 
     /// Append a record (command) to WAL.
     pub fn append(&mut self, record: &str) -> std::io::Result<()> {
-        writeln!(self.writer, "{}", record)
+        writeln!(self.writer, "{}", crypto::seal_line(self.cipher.as_ref(), record))
     }
 
     /// Explicitly flush WAL to disk (call this after a batch, or after END).
@@ -28,11 +34,24 @@ impl WAL {
         self.writer.flush()
     }
    
+    /// Flush and fsync, so everything appended so far survives a crash.
+    pub fn sync(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()
+    }
+
     /// Re-read all records for recovery.
     pub fn iter(&self) -> std::io::Result<Vec<String>> {
         let file = File::open(&self.path)?;
         let reader = BufReader::new(file);
-        Ok(reader.lines().filter_map(Result::ok).collect())
+        let lines: Vec<String> = reader.lines().filter_map(Result::ok).collect();
+        let mut records = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            if let Some(record) = file::open_record_line(self.cipher.as_ref(), line, i + 1 == lines.len())? {
+                records.push(record);
+            }
+        }
+        Ok(records)
     }
 
     /// Truncate WAL (after compaction/checkpoint).
@@ -5,3 +5,6 @@ pub mod types;
 pub mod serialization;
 pub mod script;
 pub mod logging;
+
+#[cfg(test)]
+mod tests;
@@ -1,5 +1,18 @@
 use mlua::{Value as LuaValue, Table};
 use serde_json::{json, Value as JsonValue};
+use std::ffi::c_void;
+use crate::types::Error;
+
+/// Print a script failure to stderr; Lua errors show their message and stack traceback.
+pub fn print_script_error(prefix: &str, err: &Error) {
+    match err {
+        Error::Script { message, traceback: Some(traceback) } => {
+            eprintln!("{}: {}\nstack traceback:\n{}", prefix, message, traceback)
+        }
+        Error::Script { message, traceback: None } => eprintln!("{}: {}", prefix, message),
+        other => eprintln!("{}: {:?}", prefix, other),
+    }
+}
 
 /// Pretty-print any Lua value as human-friendly JSON or fallback string.
 pub fn print_lua_value(val: &LuaValue) {
@@ -18,23 +31,49 @@ pub fn print_lua_value(val: &LuaValue) {
     }
 }
 
+// Tables nested deeper than this aren't converted to JSON
+const MAX_JSON_DEPTH: usize = 128;
+
 /// Convert LuaValue (recursively) into serde_json::Value if possible.
 /// Supports tables (both array-like and map-like), strings, numbers, bool, nil.
 /// Returns None if value is not representable as JSON.
 pub fn lua_value_to_json(val: &LuaValue) -> Option<JsonValue> {
-    match val {
+    try_lua_value_to_json(val).ok().flatten()
+}
+
+/// Like `lua_value_to_json`, but a table that contains itself or nests
+/// deeper than `MAX_JSON_DEPTH` is an error rather than converted without end.
+pub fn try_lua_value_to_json(val: &LuaValue) -> Result<Option<JsonValue>, String> {
+    value_to_json(val, &mut Vec::new())
+}
+
+// `parents` holds the tables being converted around `val`, to catch cycles
+fn value_to_json(val: &LuaValue, parents: &mut Vec<*const c_void>) -> Result<Option<JsonValue>, String> {
+    Ok(match val {
         LuaValue::Nil => Some(JsonValue::Null),
         LuaValue::Boolean(b) => Some(JsonValue::Bool(*b)),
+        LuaValue::Integer(i) => Some(json!(i)),
         LuaValue::Number(n) => Some(json!(n)),
         LuaValue::String(s) => Some(JsonValue::String(s.to_str().unwrap_or("").to_string())),
-        LuaValue::Table(t) => table_to_json(t),
+        LuaValue::Table(t) => {
+            if parents.contains(&t.to_pointer()) {
+                return Err("table contains itself".to_string());
+            }
+            if parents.len() >= MAX_JSON_DEPTH {
+                return Err(format!("tables nested deeper than {}", MAX_JSON_DEPTH));
+            }
+            parents.push(t.to_pointer());
+            let json = table_to_json(t, parents);
+            parents.pop();
+            Some(json?)
+        }
         // Ignore other types (functions, userdata, thread, lightuserdata)
         _ => None,
-    }
+    })
 }
 
 /// Converts a Lua Table into serde_json::Value (Object or Array)
-fn table_to_json(table: &Table) -> Option<JsonValue> {
+fn table_to_json(table: &Table, parents: &mut Vec<*const c_void>) -> Result<JsonValue, String> {
     // Try as array: check if all keys are integer indices from 1..N with no gaps
     let mut max_idx = 0;
     let mut min_idx = usize::MAX;
@@ -63,11 +102,11 @@ fn table_to_json(table: &Table) -> Option<JsonValue> {
     if is_array && count > 0 && min_idx == 1 && max_idx == count && array_elems.len() == count {
         // Sorted array values
         array_elems.sort_by_key(|(idx, _)| *idx);
-        let arr = array_elems
-            .into_iter()
-            .map(|(_, v)| lua_value_to_json(&v).unwrap_or(JsonValue::Null))
-            .collect::<Vec<_>>();
-        return Some(JsonValue::Array(arr));
+        let mut arr = Vec::with_capacity(array_elems.len());
+        for (_, v) in array_elems {
+            arr.push(value_to_json(&v, parents)?.unwrap_or(JsonValue::Null));
+        }
+        return Ok(JsonValue::Array(arr));
     }
 
     // Otherwise, treat as map/object
@@ -80,9 +119,9 @@ fn table_to_json(table: &Table) -> Option<JsonValue> {
                 LuaValue::Integer(i) => i.to_string(),
                 _ => continue, // skip keys that can't be stringified
             };
-            let vjson = lua_value_to_json(&value).unwrap_or(JsonValue::Null);
+            let vjson = value_to_json(&value, parents)?.unwrap_or(JsonValue::Null);
             map.insert(kstr, vjson);
         }
     }
-    Some(JsonValue::Object(map))
+    Ok(JsonValue::Object(map))
 }
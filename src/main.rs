@@ -6,6 +6,8 @@ mod serialization;
 mod script;
 mod logging;
 
-fn main() {
-    cli::run();
-}
\ No newline at end of file
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    cli::run()
+}
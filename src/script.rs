@@ -1,11 +1,16 @@
 // src/script.rs
 
 use crate::engine::kv::SlackbaseEngine;
-use crate::types::{ Error, ScriptMeta };
+use crate::engine::script_stats::ScriptStats;
+use crate::types::{ Error, LibraryMeta, ScriptMeta };
 use mlua;
 use std::fs::File;
 use std::io::{ self, Read };
 
+/// Where `script begin` reads the lines of a script from: the next line, or
+/// `None` at the end of the input.
+pub type NextLine<'a> = dyn FnMut() -> io::Result<Option<String>> + 'a;
+
 pub struct ScriptManager<'a> {
     engine: &'a mut SlackbaseEngine,
 }
@@ -29,20 +34,40 @@ impl<'a> ScriptManager<'a> {
         self.engine.eval_register(&src, Some(name), desc)
     }
 
-    pub fn begin_script_interactive(
+    /// Registers `lua_scripts/<filename>` as a new version of library `name`.
+    pub fn load_library_from_file(
         &mut self,
+        filename: &str,
         name: &str,
         desc: Option<&str>
+    ) -> Result<u32, Error> {
+        let mut path = std::path::PathBuf::from("lua_scripts");
+        path.push(filename);
+        let mut file = File::open(&path).map_err(|_| Error::NotFound)?;
+        let mut src = String::new();
+        file.read_to_string(&mut src)?;
+        self.engine.library_register(name, &src, desc)
+    }
+
+    /// Registers a script read from `next_line` up to a line containing only
+    /// `END`. Input that ends before it is an error.
+    pub fn begin_script_interactive(
+        &mut self,
+        name: &str,
+        desc: Option<&str>,
+        next_line: &mut NextLine
     ) -> Result<String, Error> {
         println!("Enter Lua script. End with a line containing only END:");
         let mut src = String::new();
         loop {
-            let mut line = String::new();
-            io::stdin().read_line(&mut line)?;
+            let Some(line) = next_line()? else {
+                return Err(Error::InvalidQuery("input ended before END".to_string()));
+            };
             if line.trim() == "END" {
                 break;
             }
-            src.push_str(&line);
+            src.push_str(line.trim_end_matches(['\r', '\n']));
+            src.push('\n');
         }
         self.engine.eval_register(&src, Some(name), desc)
     }
@@ -51,6 +76,22 @@ impl<'a> ScriptManager<'a> {
         self.engine.script_meta.values().cloned().collect()
     }
 
+    /// Registered libraries, by name.
+    pub fn list_libraries(&self) -> Vec<LibraryMeta> {
+        let mut libs: Vec<LibraryMeta> = self.engine.libraries
+            .values()
+            .filter(|lib| !lib.versions.is_empty())
+            .cloned()
+            .collect();
+        libs.sort_by(|a, b| a.name.cmp(&b.name));
+        libs
+    }
+
+    /// Execution stats of a script, if it has run since the database was opened.
+    pub fn script_stats(&self, sha: &str) -> Option<ScriptStats> {
+        self.engine.script_stats.get(sha).cloned()
+    }
+
     pub fn run_script(
         &mut self,
         sha_or_name: &str,
@@ -74,7 +115,7 @@ impl<'a> ScriptManager<'a> {
             if let Some(meta) = self.engine.script_meta.get_mut(&sha) {
                 meta.name = new_name.to_string();
             }
-            Ok(())
+            self.engine.save_scripts_to_disk()
         } else {
             Err(Error::NotFound)
         }
@@ -91,7 +132,8 @@ impl<'a> ScriptManager<'a> {
         };
         self.engine.scripts.remove(&sha);
         self.engine.script_meta.remove(&sha);
-        // Optionally: remove script file, update disk, etc.
-        Ok(())
+        self.engine.script_stats.remove(&sha);
+        self.engine.script_names.retain(|_, s| *s != sha);
+        self.engine.save_scripts_to_disk()
     }
 }
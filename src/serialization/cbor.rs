@@ -0,0 +1,146 @@
+use crate::types::Error;
+use crate::types::Result;
+use base64::{ engine::general_purpose, Engine };
+use ciborium::value::{ Integer, Value as Cbor };
+use serde_json::{ Map, Number, Value, from_slice, to_vec };
+
+/// Stores values as CBOR. Values go in and come out as JSON text; CBOR types
+/// JSON lacks are written as single-purpose objects, so they round-trip:
+///
+/// * `{"$bytes": "<base64>"}` is a byte string.
+/// * `{"$tag": 1, "$value": 1700000000}` is a tagged value (here an epoch
+///   datetime; tag 0 is an RFC 3339 string, tags 2 and 3 bignums over `$bytes`).
+/// * `{"$map": [[1, "a"], [2, "b"]]}` is a map whose keys aren't all strings.
+pub struct CborSerializer;
+
+impl crate::serialization::Serializer for CborSerializer {
+    fn serialize(&self, value: &[u8]) -> Result<Vec<u8>> {
+        let v: Value = from_slice(value).map_err(Error::Serde)?;
+        let mut out = Vec::new();
+        ciborium::into_writer(&json_to_cbor(&v)?, &mut out).map_err(|_| Error::InvalidRecord)?;
+        Ok(out)
+    }
+    fn deserialize(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let v: Cbor = ciborium::from_reader(data).map_err(|_| Error::InvalidRecord)?;
+        to_vec(&cbor_to_json(v)?).map_err(Error::Serde)
+    }
+    fn id(&self) -> &'static str {
+        "cbor"
+    }
+    fn box_clone(&self) -> Box<dyn crate::serialization::Serializer> {
+        Box::new(CborSerializer)
+    }
+}
+
+fn json_to_cbor(v: &Value) -> Result<Cbor> {
+    let invalid = |msg: &str| Error::InvalidQuery(format!("invalid CBOR value: {}", msg));
+    Ok(match v {
+        Value::Null => Cbor::Null,
+        Value::Bool(b) => Cbor::Bool(*b),
+        Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                Cbor::Integer(u.into())
+            } else if let Some(i) = n.as_i64() {
+                Cbor::Integer(i.into())
+            } else {
+                Cbor::Float(n.as_f64().unwrap_or_default())
+            }
+        }
+        Value::String(s) => Cbor::Text(s.clone()),
+        Value::Array(items) => Cbor::Array(items.iter().map(json_to_cbor).collect::<Result<_>>()?),
+        Value::Object(obj) => {
+            if let [("$bytes", Value::String(b64))] = fields(obj)[..] {
+                let bytes = general_purpose::STANDARD
+                    .decode(b64)
+                    .map_err(|_| invalid("$bytes is not base64"))?;
+                return Ok(Cbor::Bytes(bytes));
+            }
+            if let [("$tag", tag), ("$value", inner)] = fields(obj)[..] {
+                let tag = tag.as_u64().ok_or_else(|| invalid("$tag must be an unsigned integer"))?;
+                return Ok(Cbor::Tag(tag, Box::new(json_to_cbor(inner)?)));
+            }
+            if let [("$map", Value::Array(pairs))] = fields(obj)[..] {
+                let mut entries = Vec::with_capacity(pairs.len());
+                for pair in pairs {
+                    let Some([k, v]) = pair.as_array().map(Vec::as_slice) else {
+                        return Err(invalid("$map entries must be [key, value] pairs"));
+                    };
+                    entries.push((json_to_cbor(k)?, json_to_cbor(v)?));
+                }
+                return Ok(Cbor::Map(entries));
+            }
+            let mut entries = Vec::with_capacity(obj.len());
+            for (k, v) in obj {
+                entries.push((Cbor::Text(k.clone()), json_to_cbor(v)?));
+            }
+            Cbor::Map(entries)
+        }
+    })
+}
+
+fn cbor_to_json(v: Cbor) -> Result<Value> {
+    Ok(match v {
+        Cbor::Null => Value::Null,
+        Cbor::Bool(b) => Value::Bool(b),
+        Cbor::Integer(i) => integer_to_json(i)?,
+        Cbor::Float(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+        Cbor::Text(s) => Value::String(s),
+        Cbor::Bytes(bytes) => {
+            let mut obj = Map::new();
+            obj.insert("$bytes".to_string(), Value::String(general_purpose::STANDARD.encode(bytes)));
+            Value::Object(obj)
+        }
+        Cbor::Array(items) => Value::Array(items.into_iter().map(cbor_to_json).collect::<Result<_>>()?),
+        Cbor::Tag(tag, inner) => {
+            let mut obj = Map::new();
+            obj.insert("$tag".to_string(), Value::from(tag));
+            obj.insert("$value".to_string(), cbor_to_json(*inner)?);
+            Value::Object(obj)
+        }
+        Cbor::Map(entries) => {
+            if entries.iter().all(|(k, _)| k.is_text()) {
+                let mut obj = Map::new();
+                for (k, v) in entries {
+                    if let Cbor::Text(k) = k {
+                        obj.insert(k, cbor_to_json(v)?);
+                    }
+                }
+                Value::Object(obj)
+            } else {
+                let pairs = entries
+                    .into_iter()
+                    .map(|(k, v)| Ok(Value::Array(vec![cbor_to_json(k)?, cbor_to_json(v)?])))
+                    .collect::<Result<_>>()?;
+                let mut obj = Map::new();
+                obj.insert("$map".to_string(), Value::Array(pairs));
+                Value::Object(obj)
+            }
+        }
+        _ => {
+            return Err(Error::InvalidRecord);
+        }
+    })
+}
+
+/// CBOR integers reach 64 bits of magnitude either way; JSON numbers here
+/// stop at `i64::MIN`, so the rest of the negative range is rejected.
+fn integer_to_json(i: Integer) -> Result<Value> {
+    let i = i128::from(i);
+    if let Ok(u) = u64::try_from(i) {
+        Ok(Value::from(u))
+    } else if let Ok(n) = i64::try_from(i) {
+        Ok(Value::from(n))
+    } else {
+        Err(Error::InvalidRecord)
+    }
+}
+
+/// An object's fields in key order (`$tag` sorts before `$value`).
+fn fields(obj: &Map<String, Value>) -> Vec<(&str, &Value)> {
+    let mut fields: Vec<(&str, &Value)> = obj
+        .iter()
+        .map(|(k, v)| (k.as_str(), v))
+        .collect();
+    fields.sort_by(|a, b| a.0.cmp(b.0));
+    fields
+}
@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+use std::sync::{ Arc, RwLock };
+use crate::engine::compression::{ Algorithm, Compression };
+use crate::types::{ Error, Result };
+
+/// Separates the codec ids in a record's chain, e.g. `msgpack+zstd`.
+pub const CHAIN_SEPARATOR: char = '+';
+
+/// A byte-to-byte stage applied to a value after its serializer, such as
+/// compression or encryption. A value is written through the serializer and
+/// then each codec in turn; the ids of the stages it went through are stored
+/// in its record, so it is read back through the same ones in reverse, even
+/// after the database's pipeline has changed.
+pub trait ValueCodec: Send + Sync {
+    /// Stored in each record. Must be stable, and can't contain `+`, tabs or newlines.
+    fn id(&self) -> &str;
+    /// Encodes a value, or returns `None` to leave this one as it is (e.g.
+    /// too small to compress). The codec is then left out of its record.
+    fn encode(&self, data: &[u8]) -> Result<Option<Vec<u8>>>;
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+// Codecs registered by library users, by id
+static REGISTRY: RwLock<BTreeMap<String, Arc<dyn ValueCodec>>> = RwLock::new(BTreeMap::new());
+
+/// Makes a codec available to every database in the process, for pipelines
+/// and for reading the records written with it. Register codecs before
+/// opening a database that uses them. Built-in ids (serializers, `lz4`,
+/// `zstd`) can't be replaced.
+pub fn register(codec: Arc<dyn ValueCodec>) -> Result<()> {
+    let id = codec.id().to_string();
+    if id.is_empty() || id.contains([CHAIN_SEPARATOR, '\t', '\n']) {
+        return Err(Error::InvalidQuery(format!("invalid codec id '{}'", id)));
+    }
+    if builtin(&id).is_some() || super::from_id(&id).is_some() {
+        return Err(Error::InvalidQuery(format!("'{}' is a built-in codec", id)));
+    }
+    REGISTRY.write().unwrap().insert(id, codec);
+    Ok(())
+}
+
+/// The codec with the given id: a built-in one or a registered one.
+pub fn from_id(id: &str) -> Option<Arc<dyn ValueCodec>> {
+    builtin(id).or_else(|| REGISTRY.read().unwrap().get(id).cloned())
+}
+
+/// Ids of every codec available, built-in ones first.
+pub fn ids() -> Vec<String> {
+    let mut ids = vec![Algorithm::Lz4.as_str().to_string(), Algorithm::Zstd.as_str().to_string()];
+    ids.extend(REGISTRY.read().unwrap().keys().cloned());
+    ids
+}
+
+/// Built-in compression codecs compress whatever shrinks; the engine's
+/// thresholded compression stage uses the same ids.
+fn builtin(id: &str) -> Option<Arc<dyn ValueCodec>> {
+    Algorithm::parse(id).map(|algorithm| Arc::new(Compression { algorithm, threshold: 0 }) as Arc<dyn ValueCodec>)
+}
@@ -1,17 +1,21 @@
 use crate::types::Error;
 use crate::types::Result;
-use serde_json::{ Value, from_str, to_string };
+use serde_json::{ Value, from_slice, to_vec };
 
 pub struct JsonSerializer;
 
 impl crate::serialization::Serializer for JsonSerializer {
-    fn serialize(&self, value: &str) -> Result<Vec<u8>> {
+    fn serialize(&self, value: &[u8]) -> Result<Vec<u8>> {
         // Validate JSON
-        let v: Value = from_str(value).map_err(Error::Serde)?;
-        Ok(to_string(&v).map_err(Error::Serde)?.into_bytes())
+        let v: Value = from_slice(value).map_err(Error::Serde)?;
+        to_vec(&v).map_err(Error::Serde)
     }
-    fn deserialize(&self, data: &[u8]) -> Result<String> {
-        String::from_utf8(data.to_vec()).map_err(|_| Error::InvalidRecord)
+    fn deserialize(&self, data: &[u8]) -> Result<Vec<u8>> {
+        std::str::from_utf8(data).map_err(|_| Error::InvalidRecord)?;
+        Ok(data.to_vec())
+    }
+    fn id(&self) -> &'static str {
+        "json"
     }
     fn box_clone(&self) -> Box<dyn crate::serialization::Serializer> {
         Box::new(JsonSerializer)
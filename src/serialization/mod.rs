@@ -1,11 +1,28 @@
 pub mod plain;
 pub mod json;
+pub mod msgpack;
+pub mod cbor;
+pub mod codec;
 
 use crate::types::Result;
 
+/// Converts values to and from their stored form. Values are bytes; the
+/// JSON-based serializers require them to be UTF-8 JSON text.
 pub trait Serializer: Send + Sync {
-    fn serialize(&self, value: &str) -> Result<Vec<u8>>;
-    fn deserialize(&self, data: &[u8]) -> Result<String>;
+    fn serialize(&self, value: &[u8]) -> Result<Vec<u8>>;
+    fn deserialize(&self, data: &[u8]) -> Result<Vec<u8>>;
+    /// Name stored in the database manifest, e.g. `json`.
+    fn id(&self) -> &'static str;
     fn box_clone(&self) -> Box<dyn Serializer>;
 }
 
+/// The built-in serializer with the given id.
+pub fn from_id(id: &str) -> Option<Box<dyn Serializer>> {
+    match id {
+        "plain" => Some(Box::new(plain::PlainSerializer)),
+        "json" => Some(Box::new(json::JsonSerializer)),
+        "msgpack" => Some(Box::new(msgpack::MsgPackSerializer)),
+        "cbor" => Some(Box::new(cbor::CborSerializer)),
+        _ => None,
+    }
+}
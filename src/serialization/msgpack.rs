@@ -0,0 +1,24 @@
+use crate::types::Error;
+use crate::types::Result;
+use serde_json::{ Value, from_slice, to_vec };
+
+/// Stores JSON values as MessagePack. Values go in and come out as JSON
+/// text, so hashes, lists and sets work unchanged.
+pub struct MsgPackSerializer;
+
+impl crate::serialization::Serializer for MsgPackSerializer {
+    fn serialize(&self, value: &[u8]) -> Result<Vec<u8>> {
+        let v: Value = from_slice(value).map_err(Error::Serde)?;
+        rmp_serde::to_vec(&v).map_err(|_| Error::InvalidRecord)
+    }
+    fn deserialize(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let v: Value = rmp_serde::from_slice(data).map_err(|_| Error::InvalidRecord)?;
+        to_vec(&v).map_err(Error::Serde)
+    }
+    fn id(&self) -> &'static str {
+        "msgpack"
+    }
+    fn box_clone(&self) -> Box<dyn crate::serialization::Serializer> {
+        Box::new(MsgPackSerializer)
+    }
+}
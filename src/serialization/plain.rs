@@ -4,11 +4,14 @@ use crate::types::Result;
 pub struct PlainSerializer;
 
 impl Serializer for PlainSerializer {
-    fn serialize(&self, value: &str) -> Result<Vec<u8>> {
-        Ok(value.as_bytes().to_vec())
+    fn serialize(&self, value: &[u8]) -> Result<Vec<u8>> {
+        Ok(value.to_vec())
     }
-    fn deserialize(&self, data: &[u8]) -> Result<String> {
-        String::from_utf8(data.to_vec()).map_err(|_| crate::types::Error::InvalidRecord)
+    fn deserialize(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+    fn id(&self) -> &'static str {
+        "plain"
     }
     fn box_clone(&self) -> Box<dyn Serializer> {
         Box::new(PlainSerializer)
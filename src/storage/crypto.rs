@@ -0,0 +1,226 @@
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{ Aead, KeyInit, OsRng, rand_core::RngCore };
+use argon2::Argon2;
+use base64::{ engine::general_purpose, Engine };
+use chacha20poly1305::ChaCha20Poly1305;
+use serde::{ Deserialize, Serialize };
+use std::fs;
+use std::io;
+use crate::types::{ Error, Result };
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+// Sealed into the manifest, so a wrong key is told apart from a corrupt record
+const KEY_CHECK: &[u8] = b"slackbase key check";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherKind {
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+}
+
+impl CipherKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "aes-256-gcm" | "aes" => Some(CipherKind::Aes256Gcm),
+            "chacha20-poly1305" | "chacha20" => Some(CipherKind::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CipherKind::Aes256Gcm => "aes-256-gcm",
+            CipherKind::ChaCha20Poly1305 => "chacha20-poly1305",
+        }
+    }
+}
+
+/// Where a database's key comes from.
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    /// A file holding the 32-byte key, raw or as 64 hex digits.
+    KeyFile(String),
+    /// A passphrase the key is derived from with Argon2id.
+    Passphrase(String),
+}
+
+/// The key and cipher to open (or create, or re-key) a database with.
+/// `cipher` is only used when the key is set up; an encrypted database
+/// keeps the cipher recorded in its manifest.
+#[derive(Debug, Clone)]
+pub struct EncryptionKey {
+    pub source: KeySource,
+    pub cipher: CipherKind,
+}
+
+/// How the key was obtained, as recorded in the manifest.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum KeyDerivation {
+    KeyFile,
+    Argon2 {
+        /// Base64.
+        salt: String,
+    },
+}
+
+/// A database's encryption settings, persisted in the manifest. Holds no secrets.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptionInfo {
+    pub cipher: CipherKind,
+    pub key: KeyDerivation,
+    /// `KEY_CHECK` sealed with the key (base64).
+    pub check: String,
+}
+
+/// An AEAD cipher with its key. Every record is sealed on its own under a
+/// random nonce, stored in front of the ciphertext.
+#[derive(Clone)]
+pub struct Cipher {
+    kind: CipherKind,
+    key: [u8; KEY_LEN],
+}
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cipher({})", self.kind.as_str())
+    }
+}
+
+impl Cipher {
+    pub fn kind(&self) -> CipherKind {
+        self.kind
+    }
+
+    /// Encrypts `plaintext` into `nonce || ciphertext`.
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let sealed = match self.kind {
+            CipherKind::Aes256Gcm =>
+                Aes256Gcm::new(&self.key.into()).encrypt(&nonce.into(), plaintext),
+            CipherKind::ChaCha20Poly1305 =>
+                ChaCha20Poly1305::new(&self.key.into()).encrypt(&nonce.into(), plaintext),
+        };
+        // Encrypting into a Vec only fails on allocation
+        let mut out = nonce.to_vec();
+        out.extend(sealed.expect("AEAD encryption failed"));
+        out
+    }
+
+    /// Decrypts the output of `seal`. Fails if the data was altered or sealed with another key.
+    pub fn open(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let failed = || io::Error::new(io::ErrorKind::InvalidData, "record failed to decrypt");
+        if data.len() < NONCE_LEN {
+            return Err(failed());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().map_err(|_| failed())?;
+        let opened = match self.kind {
+            CipherKind::Aes256Gcm =>
+                Aes256Gcm::new(&self.key.into()).decrypt(&nonce.into(), ciphertext),
+            CipherKind::ChaCha20Poly1305 =>
+                ChaCha20Poly1305::new(&self.key.into()).decrypt(&nonce.into(), ciphertext),
+        };
+        opened.map_err(|_| failed())
+    }
+
+    /// Seals a line of text into a base64 line (no tabs or newlines).
+    pub fn seal_line(&self, line: &str) -> String {
+        general_purpose::STANDARD.encode(self.seal(line.as_bytes()))
+    }
+
+    pub fn open_line(&self, line: &str) -> io::Result<String> {
+        let data = general_purpose::STANDARD
+            .decode(line.trim_end())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        String::from_utf8(self.open(&data)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Seals `line` if there is a cipher.
+pub fn seal_line(cipher: Option<&Cipher>, line: &str) -> String {
+    match cipher {
+        Some(cipher) => cipher.seal_line(line),
+        None => line.to_string(),
+    }
+}
+
+/// Opens `line` if there is a cipher.
+pub fn open_line(cipher: Option<&Cipher>, line: &str) -> io::Result<String> {
+    match cipher {
+        Some(cipher) => cipher.open_line(line),
+        None => Ok(line.to_string()),
+    }
+}
+
+/// Sets up a new key: derives it (with a fresh salt for a passphrase) and
+/// returns the cipher with the settings to record in the manifest.
+pub fn setup(key: &EncryptionKey) -> Result<(Cipher, EncryptionInfo)> {
+    let derivation = match key.source {
+        KeySource::KeyFile(_) => KeyDerivation::KeyFile,
+        KeySource::Passphrase(_) => {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            KeyDerivation::Argon2 { salt: general_purpose::STANDARD.encode(salt) }
+        }
+    };
+    let cipher = Cipher { kind: key.cipher, key: derive(&key.source, &derivation)? };
+    let check = general_purpose::STANDARD.encode(cipher.seal(KEY_CHECK));
+    Ok((cipher, EncryptionInfo { cipher: key.cipher, key: derivation, check }))
+}
+
+/// The cipher for a database encrypted as `info` describes, or
+/// `Error::WrongKey` if `source` isn't the database's key.
+pub fn unlock(source: &KeySource, info: &EncryptionInfo) -> Result<Cipher> {
+    let cipher = Cipher { kind: info.cipher, key: derive(source, &info.key)? };
+    verify(&cipher, info)?;
+    Ok(cipher)
+}
+
+/// Checks that `cipher` is the key `info` was set up with.
+pub fn verify(cipher: &Cipher, info: &EncryptionInfo) -> Result<()> {
+    let check = general_purpose::STANDARD.decode(&info.check).map_err(|_| Error::InvalidRecord)?;
+    match cipher.open(&check) {
+        Ok(plain) if cipher.kind == info.cipher && plain == KEY_CHECK => Ok(()),
+        _ => Err(Error::WrongKey),
+    }
+}
+
+fn derive(source: &KeySource, derivation: &KeyDerivation) -> Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    match (source, derivation) {
+        (KeySource::KeyFile(path), KeyDerivation::KeyFile) => {
+            let data = fs::read(path)?;
+            let text = std::str::from_utf8(&data).map(str::trim).unwrap_or("");
+            if data.len() == KEY_LEN {
+                key.copy_from_slice(&data);
+            } else if text.len() == KEY_LEN * 2 && let Ok(bytes) = hex::decode(text) {
+                key.copy_from_slice(&bytes);
+            } else {
+                return Err(
+                    Error::InvalidQuery(
+                        format!("key file '{}' must hold {} bytes, raw or as {} hex digits", path, KEY_LEN, KEY_LEN * 2)
+                    )
+                );
+            }
+        }
+        (KeySource::Passphrase(passphrase), KeyDerivation::Argon2 { salt }) => {
+            let salt = general_purpose::STANDARD.decode(salt).map_err(|_| Error::InvalidRecord)?;
+            Argon2::default()
+                .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+                .map_err(|e| Error::InvalidQuery(format!("can't derive key: {}", e)))?;
+        }
+        (KeySource::KeyFile(_), _) => {
+            return Err(Error::InvalidQuery("the database is encrypted with a passphrase, not a key file".to_string()));
+        }
+        (KeySource::Passphrase(_), _) => {
+            return Err(Error::InvalidQuery("the database is encrypted with a key file, not a passphrase".to_string()));
+        }
+    }
+    Ok(key)
+}
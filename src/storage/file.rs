@@ -1,41 +1,91 @@
 use std::fs::{File, OpenOptions};
 use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use memmap2::Mmap;
 use std::time::{SystemTime, UNIX_EPOCH};
+use crate::storage::crypto::{self, Cipher};
 
-pub fn append_record(path: &str, record: &str) -> io::Result<(u64, usize)> {
+// Every function that reads or writes record lines takes the database's
+// cipher; with one, each line on disk is the record sealed on its own.
+
+pub fn append_record(path: &str, record: &str, cipher: Option<&Cipher>) -> io::Result<(u64, usize)> {
     let mut file = OpenOptions::new().append(true).create(true).open(path)?;
     let offset = file.seek(SeekFrom::End(0))?;
-    let line = format!("{}\n", record);
+    let line = format!("{}\n", crypto::seal_line(cipher, record));
     file.write_all(line.as_bytes())?;
     Ok((offset, line.len()))
 }
 
+/// Opens a record line. The last line of a file may be torn by a crash during
+/// an append; if it doesn't open it is dropped (`None`), as that write never
+/// completed. Any other line that doesn't open is an error.
+pub fn open_record_line(cipher: Option<&Cipher>, line: &str, last: bool) -> io::Result<Option<String>> {
+    match crypto::open_line(cipher, line) {
+        Ok(line) => Ok(Some(line)),
+        Err(_) if last => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Cuts a torn last line off the end of the file, so the next append starts a
+/// line of its own: one without its newline, left by a crash during an
+/// append, or, with a cipher, one that doesn't open.
+pub fn truncate_torn_tail(path: &str, cipher: Option<&Cipher>) -> io::Result<()> {
+    let file = match OpenOptions::new().write(true).read(true).open(path) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if file.metadata()?.len() == 0 {
+        return Ok(());
+    }
+    let mmap = unsafe { Mmap::map(&file)? };
+    let complete = mmap.last() == Some(&b'\n');
+    let body = if complete { &mmap[..mmap.len() - 1] } else { &mmap[..] };
+    let start = body.iter().rposition(|b| *b == b'\n').map_or(0, |pos| pos + 1);
+    let torn = !complete || crypto::open_line(cipher, &String::from_utf8_lossy(&body[start..])).is_err();
+    drop(mmap);
+    if torn {
+        file.set_len(start as u64)?;
+    }
+    Ok(())
+}
+
+/// Split a record line (`put\tkey\tbase64_value\texpiry?\tserializer_id?\tcompression?` or `del\tkey`)
+/// into (op, key, rest after the key).
+pub fn parse_record(line: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = line.splitn(3, '\t');
+    let op = parts.next()?;
+    let key = parts.next()?;
+    Some((op, key, parts.next().unwrap_or("")))
+}
+
 /// Read all records from file as Vec<(key, full_value_str)>
-/// `full_value_str` is the rest after the first tab, e.g. `put\t...` or `del\t...`
-pub fn read_records(path: &str) -> io::Result<Vec<(String, String)>> {
+/// `full_value_str` is the op followed by the fields after the key, e.g. `put\t...` or `del`
+pub fn read_records(path: &str, cipher: Option<&Cipher>) -> io::Result<Vec<(String, String)>> {
     let file = match File::open(path) {
         Ok(f) => f,
         Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
         Err(e) => return Err(e),
     };
     let reader = BufReader::new(file);
+    let lines: Vec<String> = reader.lines().map_while(Result::ok).collect();
     let mut records = Vec::new();
 
-    for line in reader.lines() {
-        if let Ok(l) = line {
-            let parts: Vec<_> = l.splitn(2, '\t').collect();
-            if parts.len() == 2 {
-                records.push((parts[0].to_string(), parts[1].to_string()));
-            }
+    for (i, l) in lines.iter().enumerate() {
+        let Some(l) = open_record_line(cipher, l, i + 1 == lines.len())? else {
+            continue;
+        };
+        if let Some((op, key, rest)) = parse_record(&l) {
+            let value = if rest.is_empty() { op.to_string() } else { format!("{}\t{}", op, rest) };
+            records.push((key.to_string(), value));
         }
     }
     Ok(records)
 }
 
 /// Read a slice from file at offset and length, return as String
-pub fn read_record_slice(path: &str, offset: u64, len: usize) -> io::Result<Option<String>> {
+pub fn read_record_slice(path: &str, offset: u64, len: usize, cipher: Option<&Cipher>) -> io::Result<Option<String>> {
     let file = File::open(path)?;
     let mmap = unsafe { Mmap::map(&file)? };
     let end = (offset as usize).saturating_add(len);
@@ -46,13 +96,41 @@ pub fn read_record_slice(path: &str, offset: u64, len: usize) -> io::Result<Opti
     let line = std::str::from_utf8(slice)
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
         .trim_end();
-    Ok(Some(line.to_string()))
+    Ok(Some(crypto::open_line(cipher, line)?))
 }
 
-/// Compact the log file by keeping only the latest valid (not deleted, not expired) record per key
-pub fn compact_log(path: &str) -> io::Result<()> {
-    let records = read_records(path)?;
-    let mut latest: HashMap<String, (String, Option<u64>)> = HashMap::new(); // key -> (base64_value, expiry)
+/// Read several slices of the file, mapping it once. Slices past the end are skipped.
+pub fn read_record_slices(path: &str, spans: &[(u64, usize)], cipher: Option<&Cipher>) -> io::Result<Vec<String>> {
+    let file = File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let mut lines = Vec::with_capacity(spans.len());
+    for &(offset, len) in spans {
+        let end = (offset as usize).saturating_add(len);
+        if end > mmap.len() {
+            continue;
+        }
+        let line = std::str::from_utf8(&mmap[offset as usize..end])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .trim_end();
+        lines.push(crypto::open_line(cipher, line)?);
+    }
+    Ok(lines)
+}
+
+/// Compact the log file by keeping only the latest valid (not deleted, not expired) record per key.
+/// Records are read with cipher `from` and written with `to`, so compaction can also re-key the file.
+pub fn compact_log(path: &str, from: Option<&Cipher>, to: Option<&Cipher>) -> io::Result<()> {
+    // Rewrite file atomically
+    let tmp_path = format!("{}.compact", path);
+    compact_log_to(path, &tmp_path, from, to)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Writes the compacted log to `dest`, leaving the log itself as it is.
+pub fn compact_log_to(path: &str, dest: &str, from: Option<&Cipher>, to: Option<&Cipher>) -> io::Result<()> {
+    let records = read_records(path, from)?;
+    let mut latest: HashMap<String, String> = HashMap::new(); // key -> fields after the key
 
     let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
 
@@ -63,11 +141,10 @@ pub fn compact_log(path: &str) -> io::Result<()> {
             continue;
         }
 
-        // Value format expected: "put\tbase64_value\texpiry?"
+        // Value format expected: "put\tbase64_value\texpiry?\tserializer_id?"
         let parts: Vec<&str> = value.split('\t').collect();
 
         if parts[0] == "put" {
-            let base64_val = parts.get(1).unwrap_or(&"").to_string();
             let expiry = parts.get(2).and_then(|s| s.parse::<u64>().ok());
 
             // If expired, remove key if exists
@@ -78,70 +155,67 @@ pub fn compact_log(path: &str) -> io::Result<()> {
                 }
             }
 
-            latest.insert(key, (base64_val, expiry));
+            // Kept verbatim, so the serializer id survives
+            latest.insert(key, parts[1..].join("\t"));
         } else if parts[0] == "del" {
             latest.remove(&key);
         }
     }
 
-    // Rewrite file atomically
-    let tmp_path = format!("{}.compact", path);
-    let mut file = File::create(&tmp_path)?;
+    let mut file = File::create(dest)?;
 
-    for (key, (base64_val, expiry)) in latest {
-        if let Some(exp) = expiry {
-            writeln!(file, "{}\tput\t{}\t{}", key, base64_val, exp)?;
-        } else {
-            writeln!(file, "{}\tput\t{}\t", key, base64_val)?;
-        }
+    for (key, fields) in latest {
+        writeln!(file, "{}", crypto::seal_line(to, &format!("put\t{}\t{}", key, fields)))?;
     }
-
-    std::fs::rename(tmp_path, path)?;
     Ok(())
 }
 
 /// Build an offset index for the latest valid records only
-/// Returns map: key -> (offset, length)
-pub fn build_offset_index(path: &str) -> io::Result<HashMap<String, (u64, usize)>> {
+/// Returns map: key -> (offset, length), in key order
+/// Where a key's latest record is: (offset, length, expiry).
+pub type IndexEntry = (u64, usize, Option<u64>);
+
+pub fn build_offset_index(path: &str, cipher: Option<&Cipher>) -> io::Result<BTreeMap<String, IndexEntry>> {
     let file = match File::open(path) {
         Ok(f) => f,
-        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
         Err(e) => return Err(e),
     };
 
     let mmap = unsafe { Mmap::map(&file)? };
-    let mut idx = HashMap::new();
+    let mut idx = BTreeMap::new();
     let mut offset = 0u64;
 
     let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
 
-    for line in mmap.split(|b| *b == b'\n') {
+    let lines: Vec<&[u8]> = mmap.split(|b| *b == b'\n').collect();
+    let last = lines.iter().rposition(|line| !line.is_empty());
+
+    for (i, &line) in lines.iter().enumerate() {
         if line.is_empty() {
             continue;
         }
-        if let Some(tab) = line.iter().position(|b| *b == b'\t') {
-            let key = String::from_utf8_lossy(&line[..tab]).to_string();
-            let rest = &line[tab + 1..];
-            let rest_str = String::from_utf8_lossy(rest).to_string();
-
-            if rest_str.is_empty() {
-                idx.remove(&key);
-            } else {
-                // rest_str like: put\tbase64_value\texpiry?
-                let parts: Vec<&str> = rest_str.split('\t').collect();
-                if parts[0] == "put" {
-                    let expiry = parts.get(2).and_then(|s| s.parse::<u64>().ok());
-                    if let Some(exp) = expiry {
-                        if now > exp {
-                            idx.remove(&key);
-                            offset += (line.len() as u64) + 1;
-                            continue;
-                        }
-                    }
-                    idx.insert(key, (offset, line.len()));
-                } else if parts[0] == "del" {
-                    idx.remove(&key);
+        let line_str = match cipher {
+            Some(_) => {
+                let opened = open_record_line(cipher, &String::from_utf8_lossy(line), Some(i) == last)?;
+                let Some(line_str) = opened else {
+                    break;
+                };
+                line_str
+            }
+            None => String::from_utf8_lossy(line).into_owned(),
+        };
+        if let Some((op, key, rest)) = parse_record(&line_str) {
+            if op == "put" {
+                // rest like: base64_value\texpiry?
+                let expiry = rest.split('\t').nth(1).and_then(|s| s.parse::<u64>().ok());
+                if expiry.is_some_and(|exp| now > exp) {
+                    idx.remove(key);
+                } else {
+                    idx.insert(key.to_string(), (offset, line.len() + 1, expiry));
                 }
+            } else if op == "del" {
+                idx.remove(key);
             }
         }
         offset += (line.len() as u64) + 1;
@@ -150,33 +224,43 @@ pub fn build_offset_index(path: &str) -> io::Result<HashMap<String, (u64, usize)
     Ok(idx)
 }
 
-/// Save index hint file in CSV format: key,offset,length
-pub fn save_hint(path: &str, index: &HashMap<String, (u64, usize)>) -> io::Result<()> {
+/// Save index hint file in CSV format: key,offset,length,expiry (empty if none)
+pub fn save_hint(path: &str, index: &BTreeMap<String, IndexEntry>, cipher: Option<&Cipher>) -> io::Result<()> {
     let hint_path = format!("{}.hint", path);
     let mut file = File::create(&hint_path)?;
 
-    for (k, (off, len)) in index {
-        writeln!(file, "{},{},{}", k, off, len)?;
+    for (k, (off, len, expiry)) in index {
+        let expiry = expiry.map(|ts| ts.to_string()).unwrap_or_default();
+        writeln!(file, "{}", crypto::seal_line(cipher, &format!("{},{},{},{}", k, off, len, expiry)))?;
     }
     Ok(())
 }
 
-/// Load index hint file from CSV
-pub fn load_hint(path: &str) -> io::Result<HashMap<String, (u64, usize)>> {
+/// Load index hint file from CSV. A line that doesn't parse, including one
+/// from a hint written before expiries were recorded, fails the whole load
+/// so the caller rebuilds the index from the log.
+pub fn load_hint(path: &str, cipher: Option<&Cipher>) -> io::Result<BTreeMap<String, IndexEntry>> {
     let hint_path = format!("{}.hint", path);
     let file = File::open(&hint_path)?;
     let reader = BufReader::new(file);
-    let mut map = HashMap::new();
+    let mut map = BTreeMap::new();
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed hint line");
 
     for line in reader.lines() {
-        let l = line?;
-        let parts: Vec<_> = l.split(',').collect();
-        if parts.len() == 3 {
-            let key = parts[0].to_string();
-            if let (Ok(offset), Ok(len)) = (parts[1].parse(), parts[2].parse()) {
-                map.insert(key, (offset, len));
-            }
-        }
+        let l = crypto::open_line(cipher, &line?)?;
+        // Split from the right, so keys may contain commas
+        let parts: Vec<_> = l.rsplitn(4, ',').collect();
+        let [expiry, len, offset, key] = parts[..] else {
+            return Err(invalid());
+        };
+        let expiry = match expiry {
+            "" => None,
+            ts => Some(ts.parse().map_err(|_| invalid())?),
+        };
+        let (Ok(offset), Ok(len)) = (offset.parse(), len.parse()) else {
+            return Err(invalid());
+        };
+        map.insert(key.to_string(), (offset, len, expiry));
     }
     Ok(map)
 }
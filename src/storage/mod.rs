@@ -1 +1,2 @@
-pub mod file;
\ No newline at end of file
+pub mod file;
+pub mod crypto;
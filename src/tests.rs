@@ -0,0 +1,224 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use crate::engine::compression::{ Algorithm, Compression };
+use crate::engine::kv::SlackbaseEngine;
+use crate::engine::manifest::Manifest;
+use crate::serialization::{ self, codec::{ self, ValueCodec } };
+use crate::storage::crypto::{ CipherKind, EncryptionKey, KeySource };
+use crate::types::{ Error, Result };
+
+/// A fresh database path under the system temp dir, with any files left by
+/// an earlier run removed.
+fn temp_db(name: &str) -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(
+        format!("slackbase-test-{}-{}-{}", std::process::id(), name, NEXT.fetch_add(1, Ordering::Relaxed))
+    );
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("test.db").to_string_lossy().into_owned()
+}
+
+fn open(path: &str, serializer: &str) -> SlackbaseEngine {
+    SlackbaseEngine::open(path, serialization::from_id(serializer).unwrap()).unwrap()
+}
+
+/// The codec chain of the last record written for `key`.
+fn chain_of(path: &str, key: &str) -> String {
+    let data = fs::read_to_string(path).unwrap();
+    let line = data
+        .lines()
+        .rfind(|line| line.split('\t').nth(1) == Some(key))
+        .unwrap();
+    line.rsplit('\t').next().unwrap().to_string()
+}
+
+/// XORs every byte, so encoded values differ from their input.
+struct Xor;
+
+impl ValueCodec for Xor {
+    fn id(&self) -> &str {
+        "xor"
+    }
+
+    fn encode(&self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(Some(data.iter().map(|b| b ^ 0x5a).collect()))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.iter().map(|b| b ^ 0x5a).collect())
+    }
+}
+
+#[test]
+fn registered_codec_reads_back_after_the_pipeline_changes() {
+    codec::register(Arc::new(Xor)).unwrap();
+    let path = temp_db("codec");
+    {
+        let mut db = open(&path, "msgpack");
+        db.set_codecs(&["xor"]).unwrap();
+        db.put("a", r#"{"n":1}"#).unwrap();
+        db.set_codecs(&[]).unwrap();
+        db.put("b", r#"{"n":2}"#).unwrap();
+    }
+    assert_eq!(chain_of(&path, "a"), "msgpack+xor");
+    assert_eq!(chain_of(&path, "b"), "msgpack");
+
+    let mut db = open(&path, "msgpack");
+    assert_eq!(db.get("a").as_deref(), Some(r#"{"n":1}"#));
+    assert_eq!(db.get("b").as_deref(), Some(r#"{"n":2}"#));
+}
+
+#[test]
+fn builtin_codec_ids_cannot_be_registered() {
+    struct Fake(&'static str);
+    impl ValueCodec for Fake {
+        fn id(&self) -> &str {
+            self.0
+        }
+        fn encode(&self, _: &[u8]) -> Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+        fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.to_vec())
+        }
+    }
+    for id in ["zstd", "msgpack", "a+b", ""] {
+        assert!(matches!(codec::register(Arc::new(Fake(id))), Err(Error::InvalidQuery(_))));
+    }
+}
+
+#[test]
+fn compressed_and_plain_values_read_back_across_compression_changes() {
+    let path = temp_db("compression");
+    let big = format!(r#"{{"text":"{}"}}"#, "abc".repeat(200));
+    {
+        let mut db = open(&path, "json");
+        db.set_compression(Some(Compression { algorithm: Algorithm::Lz4, threshold: 64 })).unwrap();
+        db.put("lz4", &big).unwrap();
+        db.put("small", r#"{"n":1}"#).unwrap();
+        db.set_compression(Some(Compression { algorithm: Algorithm::Zstd, threshold: 64 })).unwrap();
+        db.put("zstd", &big).unwrap();
+        db.set_compression(None).unwrap();
+        db.put("off", &big).unwrap();
+    }
+    assert_eq!(chain_of(&path, "lz4"), "json+lz4");
+    assert_eq!(chain_of(&path, "small"), "json");
+    assert_eq!(chain_of(&path, "zstd"), "json+zstd");
+    assert_eq!(chain_of(&path, "off"), "json");
+
+    let mut db = open(&path, "json");
+    for key in ["lz4", "zstd", "off"] {
+        assert_eq!(db.get(key).as_deref(), Some(big.as_str()), "{}", key);
+    }
+    assert_eq!(db.get("small").as_deref(), Some(r#"{"n":1}"#));
+}
+
+/// A key file next to the database at `path`, holding `byte` repeated.
+fn key_file(path: &str, byte: u8) -> EncryptionKey {
+    let key_path = format!("{}.{:02x}.key", path, byte);
+    fs::write(&key_path, hex::encode([byte; 32])).unwrap();
+    EncryptionKey { source: KeySource::KeyFile(key_path), cipher: CipherKind::Aes256Gcm }
+}
+
+fn open_encrypted(path: &str, key: &EncryptionKey) -> Result<SlackbaseEngine> {
+    SlackbaseEngine::open_encrypted(path, serialization::from_id("json").unwrap(), key)
+}
+
+/// Copies every file of the database at `from` into the directory of `to`.
+fn copy_db(from: &str, to: &str) {
+    let to_dir = Path::new(to).parent().unwrap();
+    for entry in fs::read_dir(Path::new(from).parent().unwrap()).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), to_dir.join(entry.file_name())).unwrap();
+    }
+}
+
+#[test]
+fn encrypted_database_refuses_the_wrong_key() {
+    let path = temp_db("wrong-key");
+    let key = key_file(&path, 1);
+    open_encrypted(&path, &key).unwrap().put("a", r#"{"n":1}"#).unwrap();
+
+    assert!(matches!(open_encrypted(&path, &key_file(&path, 2)), Err(Error::WrongKey)));
+    assert!(matches!(SlackbaseEngine::open(&path, serialization::from_id("json").unwrap()), Err(Error::KeyRequired)));
+    assert_eq!(open_encrypted(&path, &key).unwrap().get("a").as_deref(), Some(r#"{"n":1}"#));
+}
+
+#[test]
+fn torn_last_encrypted_line_is_dropped_on_open() {
+    let path = temp_db("torn");
+    let key = key_file(&path, 1);
+    {
+        let mut db = open_encrypted(&path, &key).unwrap();
+        db.put("a", r#"{"n":1}"#).unwrap();
+        db.put("b", r#"{"n":2}"#).unwrap();
+    }
+    // A crash in the middle of appending `b`
+    let data = fs::read(&path).unwrap();
+    fs::write(&path, &data[..data.len() - 10]).unwrap();
+
+    {
+        let mut db = open_encrypted(&path, &key).unwrap();
+        assert_eq!(db.get("a").as_deref(), Some(r#"{"n":1}"#));
+        assert_eq!(db.get("b"), None);
+        db.put("c", r#"{"n":3}"#).unwrap();
+    }
+    let mut db = open_encrypted(&path, &key).unwrap();
+    assert_eq!(db.get("a").as_deref(), Some(r#"{"n":1}"#));
+    assert_eq!(db.get("c").as_deref(), Some(r#"{"n":3}"#));
+}
+
+/// Leaves the database at `path` as a key rotation to `new_key` would if it
+/// crashed after staging its files: the staged data file and sidecars sit next
+/// to the old ones, the WAL is cleared, and the manifest is left unchanged.
+/// Returns the manifest the rotation would commit.
+fn stage_rotation(path: &str, old_key: &EncryptionKey, new_key: &EncryptionKey) -> Manifest {
+    {
+        let mut db = open_encrypted(path, old_key).unwrap();
+        db.put("user:1", r#"{"email":"a@x"}"#).unwrap();
+        db.create_unique_index("email", Some("user:")).unwrap();
+    }
+    let rotated = temp_db("rotated");
+    copy_db(path, &rotated);
+    open_encrypted(&rotated, old_key).unwrap().rotate_key(Some(new_key)).unwrap();
+
+    for ext in ["", ".secindex"] {
+        fs::copy(format!("{}{}", rotated, ext), format!("{}{}.staged", path, ext)).unwrap();
+    }
+    fs::write(format!("{}.wal", path), "").unwrap();
+    Manifest::load(&rotated).unwrap().unwrap()
+}
+
+#[test]
+fn rotation_interrupted_after_its_commit_point_finishes_on_open() {
+    let path = temp_db("rotate-committed");
+    let (old_key, new_key) = (key_file(&path, 1), key_file(&path, 2));
+    let mut committed = stage_rotation(&path, &old_key, &new_key);
+    committed.swap_pending = true;
+    committed.save(&path).unwrap();
+
+    let mut db = open_encrypted(&path, &new_key).unwrap();
+    assert_eq!(db.get("user:1").as_deref(), Some(r#"{"email":"a@x"}"#));
+    assert!(matches!(db.put("user:2", r#"{"email":"a@x"}"#), Err(Error::UniqueViolation { .. })));
+    drop(db);
+    assert!(!Path::new(&format!("{}.staged", path)).exists());
+    assert!(!Manifest::load(&path).unwrap().unwrap().swap_pending);
+    assert!(matches!(open_encrypted(&path, &old_key), Err(Error::WrongKey)));
+}
+
+#[test]
+fn rotation_interrupted_before_its_commit_point_is_discarded() {
+    let path = temp_db("rotate-staged");
+    let (old_key, new_key) = (key_file(&path, 1), key_file(&path, 2));
+    stage_rotation(&path, &old_key, &new_key);
+
+    let mut db = open_encrypted(&path, &old_key).unwrap();
+    assert_eq!(db.get("user:1").as_deref(), Some(r#"{"email":"a@x"}"#));
+    assert!(matches!(db.put("user:2", r#"{"email":"a@x"}"#), Err(Error::UniqueViolation { .. })));
+    drop(db);
+    assert!(!Path::new(&format!("{}.staged", path)).exists());
+    assert!(!Path::new(&format!("{}.secindex.staged", path)).exists());
+}
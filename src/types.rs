@@ -7,6 +7,55 @@ pub struct ScriptMeta {
     pub name: String,
     pub sha1: String,
     pub desc: Option<String>,
+    /// Lua source, recompiled when the database is reopened.
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub limits: ScriptLimits,
+}
+
+/// A named script library; each registration with new source adds a version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryMeta {
+    pub name: String,
+    /// Oldest first; the last one is what `require(name)` loads.
+    pub versions: Vec<LibraryVersion>,
+    /// Highest version ever registered. Kept after the versions are removed
+    /// so a number is never reused for different code.
+    #[serde(default)]
+    pub last_version: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryVersion {
+    pub version: u32,
+    pub sha1: String,
+    pub desc: Option<String>,
+    pub source: String,
+    /// Names of the functions in the table the library returns.
+    pub functions: Vec<String>,
+}
+
+/// Per-call resource limits for a script. `None` disables a limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScriptLimits {
+    /// Lua VM instructions per call.
+    pub max_instructions: Option<u64>,
+    /// Bytes the Lua heap may grow by during a call.
+    pub max_memory: Option<usize>,
+    /// Wall-clock time per call, in milliseconds.
+    pub timeout_ms: Option<u64>,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_instructions: Some(100_000_000),
+            max_memory: Some(64 * 1024 * 1024),
+            timeout_ms: Some(5_000),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -15,7 +64,27 @@ pub enum Error {
     Serde(serde_json::Error),
     NotFound,
     InvalidRecord,
+    InvalidQuery(String),
+    /// A unique field value is already held by another key.
+    /// `conflicts` lists each offending value with the keys holding it.
+    UniqueViolation { field: String, conflicts: Vec<(String, Vec<String>)> },
     Lua(mlua::Error),
+    /// A script exceeded one of its `ScriptLimits`; its writes were discarded.
+    ScriptLimit(String),
+    /// A script failed to compile or raised an error; its writes were discarded.
+    Script { message: String, traceback: Option<String> },
+    /// A trigger vetoed a write or could not run; the write was not applied.
+    Trigger { name: String, message: String },
+    /// A value isn't of the content type registered for its key's prefix.
+    InvalidContent { key: String, expected: String },
+    /// The database was created with a different serializer than the one it was opened with.
+    SerializerMismatch { stored: String, requested: String },
+    /// The database is encrypted and was opened without a key.
+    KeyRequired,
+    /// The key given isn't the one the database (or snapshot) was encrypted with.
+    WrongKey,
+    /// A record or the manifest names a codec that isn't registered.
+    UnknownCodec { id: String },
     SystemTime(std::time::SystemTimeError),
 }
 